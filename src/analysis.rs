@@ -0,0 +1,436 @@
+//! Checks the clauses of a function for clauses that can never run
+//! and for arguments that no clause accepts
+//!
+//! Both checks ask whether a pattern matches any value that earlier
+//! patterns don't, by splitting values into the same cases that the
+//! decision tree switches on. Only enums, structs, tuples and bools are
+//! checked for missing cases, since numbers can't be covered without a
+//! wildcard.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::decision::Case;
+use crate::value::{Pattern, Template, Value};
+
+/// The problems found in the clauses of a function
+#[derive(Debug, Default, PartialEq)]
+pub struct Analysis {
+    /// The clauses that only match arguments that earlier clauses match
+    pub unreachable: Vec<usize>,
+    /// Examples of arguments that no clause matches
+    pub missing: Vec<Vec<String>>,
+}
+
+/// The variants of every enum, by the name of the enum
+pub type Enums = HashMap<String, Vec<Rc<Template>>>;
+
+/// The largest number of missing arguments that are reported
+const MAX_MISSING: usize = 3;
+
+/// Analyzes clauses with the given patterns, where the clauses in
+/// `guarded` have guards and `enums` holds the variants of every enum
+///
+/// A clause with a guard can still be unreachable, but it never
+/// stops later clauses from running, since its guard can be false.
+///
+/// # Examples
+/// ```
+/// use std::collections::HashMap;
+/// use std::rc::Rc;
+///
+/// use lucent_lang::analysis;
+/// use lucent_lang::value::{Pattern, Template};
+///
+/// let some = Rc::new(Template::variant("Option", "Some", vec!["value".to_string()], vec![]));
+/// let none = Rc::new(Template::variant("Option", "None", vec![], vec![]));
+/// let mut enums = HashMap::new();
+/// enums.insert("Option".to_string(), vec![Rc::clone(&some), none]);
+///
+/// let analysis = analysis::analyze(
+///     &[
+///         vec![Pattern::Template(Rc::clone(&some), None)],
+///         vec![Pattern::Template(some, Some(vec![Pattern::Int]))],
+///     ],
+///     &[],
+///     &enums,
+/// );
+/// assert_eq!(vec![1], analysis.unreachable);
+/// assert_eq!(vec![vec!["Option::None".to_string()]], analysis.missing);
+/// ```
+pub fn analyze(clauses: &[Vec<Pattern>], guarded: &[usize], enums: &Enums) -> Analysis {
+    let checker = Checker { enums };
+    let covering: Vec<Vec<Pattern>> = clauses
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !guarded.contains(index))
+        .map(|(_, clause)| clause.clone())
+        .collect();
+
+    let unreachable = clauses
+        .iter()
+        .enumerate()
+        .filter(|(index, clause)| {
+            let earlier = (0..*index).filter(|other| !guarded.contains(other)).count();
+            !checker.useful(&covering[..earlier], clause)
+        })
+        .map(|(index, _)| index)
+        .collect();
+
+    let width = clauses.first().map_or(0, Vec::len);
+    let mut missing = checker.missing(&covering, width);
+    missing.truncate(MAX_MISSING);
+
+    Analysis {
+        unreachable,
+        missing,
+    }
+}
+
+struct Checker<'a> {
+    enums: &'a Enums,
+}
+
+impl<'a> Checker<'a> {
+    /// Whether `row` matches any values that none of `rows` match
+    fn useful(&self, rows: &[Vec<Pattern>], row: &[Pattern]) -> bool {
+        let head = match row.first() {
+            Some(head) => strip(head),
+            None => return rows.is_empty(),
+        };
+
+        match Case::of(head) {
+            // Every variant has to be covered for a whole enum to be covered
+            Some(Case::Enum(name)) => match self.enums.get(&name) {
+                Some(variants) => variants.iter().any(|variant| {
+                    let case = Case::Template(Rc::clone(variant));
+                    self.useful(
+                        &specialize_all(rows, &case),
+                        &specialize(row, &case).unwrap_or_default(),
+                    )
+                }),
+                None => false,
+            },
+            // As can both `true` and `false` for the whole Bool type
+            Some(Case::Bool) => [true, false].iter().any(|value| {
+                let case = Case::Literal(Value::bool(*value));
+                self.useful(
+                    &specialize_all(rows, &case),
+                    &specialize(row, &case).unwrap_or_default(),
+                )
+            }),
+            Some(case) => {
+                let row = specialize(row, &case).unwrap_or_default();
+                self.useful(&specialize_all(rows, &case), &row)
+            }
+            None => match self.domain(rows) {
+                Some(cases) => cases.iter().any(|case| {
+                    let row = specialize(row, case).unwrap_or_default();
+                    self.useful(&specialize_all(rows, case), &row)
+                }),
+                None => self.useful(&default(rows), &row[1..]),
+            },
+        }
+    }
+
+    /// Examples of `width` arguments that none of `rows` match
+    fn missing(&self, rows: &[Vec<Pattern>], width: usize) -> Vec<Vec<String>> {
+        if width == 0 {
+            return if rows.is_empty() {
+                vec![vec![]]
+            } else {
+                vec![]
+            };
+        }
+
+        // Values that aren't enums or structs are assumed to be
+        // one of the cases that the patterns test for
+        let cases = match self.domain(rows) {
+            Some(cases) => cases,
+            None => column_cases(rows),
+        };
+        if cases.is_empty() {
+            return self
+                .missing(&default(rows), width - 1)
+                .into_iter()
+                .map(|rest| prepend("_".to_string(), rest))
+                .collect();
+        }
+
+        let mut missing = vec![];
+        for case in cases.iter() {
+            let arity = arity(case);
+            for mut example in self.missing(&specialize_all(rows, case), arity + width - 1) {
+                let rest = example.split_off(arity);
+                missing.push(prepend(describe(case, &example), rest));
+                if missing.len() >= MAX_MISSING {
+                    return missing;
+                }
+            }
+        }
+        missing
+    }
+
+    /// Every case of the type that the first column of `rows`
+    /// tests for, if it is an enum, a struct, a tuple or a bool
+    fn domain(&self, rows: &[Vec<Pattern>]) -> Option<Vec<Case>> {
+        let cases = column_cases(rows);
+
+        let enum_name = |case: &Case| match case {
+            Case::Template(template) => template.enum_name().map(str::to_string),
+            Case::Enum(name) => Some(name.clone()),
+            _ => None,
+        };
+        let first = cases.first()?;
+        if let Some(name) = enum_name(first) {
+            if cases
+                .iter()
+                .all(|case| enum_name(case).as_ref() == Some(&name))
+            {
+                let variants = self.enums.get(&name)?;
+                return Some(
+                    variants
+                        .iter()
+                        .map(|variant| Case::Template(Rc::clone(variant)))
+                        .collect(),
+                );
+            }
+        }
+        let is_bool = |case: &Case| match case {
+            Case::Literal(value) => value.is_bool(),
+            case => *case == Case::Bool,
+        };
+        if cases.iter().all(is_bool) {
+            return Some(vec![
+                Case::Literal(Value::bool(true)),
+                Case::Literal(Value::bool(false)),
+            ]);
+        }
+        match first {
+            Case::Template(template) if cases.len() == 1 && template.enum_name().is_none() => {
+                Some(cases)
+            }
+            Case::Tuple(_) if cases.len() == 1 => Some(cases),
+            _ => None,
+        }
+    }
+}
+
+/// The cases that the first column of `rows` tests for, in order
+fn column_cases(rows: &[Vec<Pattern>]) -> Vec<Case> {
+    let mut cases: Vec<Case> = vec![];
+    for row in rows.iter() {
+        if let Some(case) = row.first().map(strip).and_then(Case::of) {
+            if !cases.contains(&case) {
+                cases.push(case);
+            }
+        }
+    }
+    cases
+}
+
+/// Ignores any bindings around a pattern
+fn strip(pattern: &Pattern) -> &Pattern {
+    match pattern {
+        Pattern::Bind(pattern) => strip(pattern),
+        _ => pattern,
+    }
+}
+
+fn arity(case: &Case) -> usize {
+    match case {
+        Case::Template(template) => template.fields().len(),
+        Case::Tuple(length) => *length,
+        Case::Record { fields, .. } => fields.len(),
+        _ => 0,
+    }
+}
+
+/// The rest of `row` once its first value is known to be in `case`,
+/// with the fields of the value in place of the first pattern
+fn specialize(row: &[Pattern], case: &Case) -> Option<Vec<Pattern>> {
+    let head = strip(row.first()?);
+    let fields = match head {
+        Pattern::Wildcard => vec![Pattern::Wildcard; arity(case)],
+        _ if !case.implies(head) => return None,
+        Pattern::Template(_, Some(fields)) | Pattern::Tuple(fields) => fields.clone(),
+        // The fields of a record are in the order of the case's fields
+        Pattern::Record { fields, .. } => match case {
+            Case::Record { fields: names, .. } => names
+                .iter()
+                .map(|name| {
+                    fields
+                        .iter()
+                        .find(|(field, _)| field == name)
+                        .map_or(Pattern::Wildcard, |(_, pattern)| pattern.clone())
+                })
+                .collect(),
+            _ => vec![],
+        },
+        _ => vec![Pattern::Wildcard; arity(case)],
+    };
+    Some(fields.into_iter().chain(row[1..].iter().cloned()).collect())
+}
+
+fn specialize_all(rows: &[Vec<Pattern>], case: &Case) -> Vec<Vec<Pattern>> {
+    rows.iter()
+        .filter_map(|row| specialize(row, case))
+        .collect()
+}
+
+/// The rows that match any first value, without their first pattern
+fn default(rows: &[Vec<Pattern>]) -> Vec<Vec<Pattern>> {
+    rows.iter()
+        .filter(|row| matches!(row.first().map(strip), Some(Pattern::Wildcard)))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+/// Writes a value in `case` with the given fields, such as `Shape::Circle(_)`
+fn describe(case: &Case, fields: &[String]) -> String {
+    match case {
+        Case::Template(template) if !fields.is_empty() => {
+            format!("{}({})", template.qualified_name(), fields.join(", "))
+        }
+        Case::Tuple(_) => format!("({})", fields.join(", ")),
+        Case::Record {
+            fields: names,
+            open,
+        } => {
+            let mut fields: Vec<String> = names
+                .iter()
+                .zip(fields.iter())
+                .map(|(name, field)| format!("{}: {}", name, field))
+                .collect();
+            if *open {
+                fields.push("...".to_string());
+            }
+            format!("{{{}}}", fields.join(", "))
+        }
+        _ => case.to_string(),
+    }
+}
+
+fn prepend(first: String, mut rest: Vec<String>) -> Vec<String> {
+    rest.insert(0, first);
+    rest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shape() -> (Vec<Rc<Template>>, Enums) {
+        let variants: Vec<Rc<Template>> = vec![("Circle", 1), ("Rectangle", 2), ("Empty", 0)]
+            .into_iter()
+            .map(|(name, fields)| {
+                let fields = (0..fields).map(|index| index.to_string()).collect();
+                Rc::new(Template::variant("Shape", name, fields, vec![]))
+            })
+            .collect();
+        let mut enums = HashMap::new();
+        enums.insert("Shape".to_string(), variants.clone());
+        (variants, enums)
+    }
+
+    fn variant(template: &Rc<Template>, fields: Option<Vec<Pattern>>) -> Pattern {
+        Pattern::Template(Rc::clone(template), fields)
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let (_, enums) = shape();
+        let clauses = [
+            vec![Pattern::Float],
+            vec![Pattern::Int],
+            vec![Pattern::Literal(Value::int(0))],
+            vec![Pattern::Enum("Shape".to_string())],
+            vec![Pattern::Wildcard],
+            vec![Pattern::Unit],
+        ];
+
+        // Ints aren't Floats, but 0 is an Int
+        assert_eq!(vec![2, 5], analyze(&clauses, &[], &enums).unreachable);
+    }
+
+    #[test]
+    fn test_covered_enum() {
+        let (shapes, enums) = shape();
+        let clauses = [
+            vec![variant(&shapes[0], None)],
+            vec![variant(&shapes[1], None)],
+            vec![variant(&shapes[2], None)],
+            vec![Pattern::Enum("Shape".to_string())],
+        ];
+
+        let analysis = analyze(&clauses, &[], &enums);
+        assert_eq!(vec![3], analysis.unreachable);
+        assert!(analysis.missing.is_empty());
+    }
+
+    #[test]
+    fn test_guarded() {
+        let (shapes, enums) = shape();
+        let clauses = [
+            vec![variant(&shapes[0], None)],
+            vec![Pattern::Enum("Shape".to_string())],
+            vec![variant(&shapes[2], None)],
+        ];
+
+        // A guarded clause can be unreachable itself,
+        // but it doesn't cover the clauses after it
+        let analysis = analyze(&clauses, &[1, 2], &enums);
+        assert!(analysis.unreachable.is_empty());
+        assert_eq!(
+            vec![
+                vec!["Shape::Rectangle(_, _)".to_string()],
+                vec!["Shape::Empty".to_string()],
+            ],
+            analysis.missing
+        );
+
+        let analysis = analyze(&clauses, &[0], &enums);
+        assert_eq!(vec![2], analysis.unreachable);
+    }
+
+    #[test]
+    fn test_missing() {
+        let (shapes, enums) = shape();
+        let clauses = [
+            vec![
+                variant(&shapes[0], Some(vec![Pattern::Wildcard])),
+                Pattern::Literal(Value::int(0)),
+            ],
+            vec![
+                variant(&shapes[1], Some(vec![Pattern::Int, Pattern::Wildcard])),
+                Pattern::Wildcard,
+            ],
+        ];
+
+        let missing: Vec<String> = analyze(&clauses, &[], &enums)
+            .missing
+            .iter()
+            .map(|example| example.join(", "))
+            .collect();
+        assert_eq!(vec!["Shape::Empty, _"], missing);
+    }
+
+    #[test]
+    fn test_nested_missing() {
+        let (shapes, enums) = shape();
+        let clauses = [
+            vec![variant(&shapes[0], Some(vec![variant(&shapes[2], None)]))],
+            vec![variant(&shapes[1], None)],
+            vec![variant(&shapes[2], None)],
+        ];
+
+        let analysis = analyze(&clauses, &[], &enums);
+        assert_eq!(
+            vec![
+                vec!["Shape::Circle(Shape::Circle(_))".to_string()],
+                vec!["Shape::Circle(Shape::Rectangle(_, _))".to_string()],
+            ],
+            analysis.missing
+        );
+    }
+}
@@ -0,0 +1,382 @@
+//! The abstract syntax tree for the productions in `lucent.lang`
+//!
+//! Nodes keep the tokens they were parsed from so that later
+//! passes can report errors at the right place in the source.
+
+mod parser;
+
+pub use parser::{parse, parse_expression};
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::scanner::{Span, Token};
+
+/// program → (structDecl structDef? | enumDecl enumDef? | traitDecl | functionDef | constDef)* EOF
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub items: Vec<Item>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Struct(StructDecl),
+    Enum(EnumDecl),
+    Trait(TraitDecl),
+    Function(FunctionDef),
+    Const(ConstDef),
+}
+
+/// structDecl → "struct" identifier "{" (matchExpr ",")* "}"
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDecl {
+    pub name: Token,
+    pub fields: Vec<Pattern>,
+    pub definition: Option<StructDef>,
+}
+
+/// structDef → "define" identifier "{" (constDef | constructor | functionDef)* "}"
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructDef {
+    pub name: Token,
+    pub members: Vec<StructMember>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StructMember {
+    Const(ConstDef),
+    Constructor(Constructor),
+    Function(FunctionDef),
+}
+
+/// constructor → identifier "(" arguments ")" (";" | "{" expression+ "}")
+///
+/// A constructor without a body builds the struct
+/// directly from its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constructor {
+    pub name: Token,
+    pub params: Vec<Pattern>,
+    pub body: Option<Block>,
+}
+
+/// enumDecl → "enum" identifier "{" (enum ",")* "}"
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumDecl {
+    pub name: Token,
+    pub variants: Vec<Variant>,
+    pub definition: Option<EnumDef>,
+}
+
+/// enum → identifier ("(" (matchExpr ",")* matchExpr ")")
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: Token,
+    pub payload: Vec<Pattern>,
+}
+
+/// enumDef → "define" identifier "{" (constDef | functionDef)* "}"
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumDef {
+    pub name: Token,
+    pub members: Vec<EnumMember>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnumMember {
+    Const(ConstDef),
+    Function(FunctionDef),
+}
+
+/// traitDecl → "trait" identifier "{" "}"
+///
+/// A trait doesn't have any members yet, so it only declares its name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraitDecl {
+    pub name: Token,
+}
+
+/// functionDef → "impure"? "fn" identifier "(" parameters? ")" ("where" expression)? block
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDef {
+    pub impure: bool,
+    pub name: Token,
+    pub params: Vec<Pattern>,
+    pub guard: Option<Expr>,
+    pub body: Block,
+}
+
+/// constDef → "let" matchExpr "=" expression ";"
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConstDef {
+    pub keyword: Token,
+    pub pattern: Pattern,
+    pub value: Expr,
+}
+
+/// block → "{" (statement ";")* expression? "}"
+///
+/// The value of a block is its trailing expression,
+/// or unit if it ends with a semicolon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub brace: Token,
+    pub statements: Vec<Stmt>,
+    pub value: Option<Box<Expr>>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Let(ConstDef),
+    Assert { keyword: Token, condition: Expr },
+    Expression(Expr),
+}
+
+impl ConstDef {
+    pub fn span(&self) -> Span {
+        self.keyword.span().to(self.value.span())
+    }
+}
+
+impl Stmt {
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Let(definition) => definition.span(),
+            Stmt::Assert { keyword, condition } => keyword.span().to(condition.span()),
+            Stmt::Expression(expression) => expression.span(),
+        }
+    }
+}
+
+/// matchExpr, used for parameters, fields and `let` bindings
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`
+    Wildcard(Token),
+    /// `0`, `"text"`, `true`
+    Literal { token: Token, value: Literal },
+    /// `a` or `a is Int`
+    Binding {
+        name: Token,
+        pattern: Option<Box<Pattern>>,
+    },
+    /// `Int`, `Point`, `Some(x)` or `Shape::Circle(r)`
+    Named {
+        path: Vec<Token>,
+        arguments: Option<Vec<Pattern>>,
+        span: Span,
+    },
+    /// `(x, y)` or `(Int, Int)`
+    Tuple {
+        paren: Token,
+        elements: Vec<Pattern>,
+        span: Span,
+    },
+    /// `{x: x2, y}` or `{x, y, ...}`
+    Record {
+        brace: Token,
+        fields: Vec<FieldPattern>,
+        open: bool,
+        span: Span,
+    },
+}
+
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Wildcard(token) | Pattern::Literal { token, .. } => token.span(),
+            Pattern::Binding {
+                name,
+                pattern: Some(pattern),
+            } => name.span().to(pattern.span()),
+            Pattern::Binding {
+                name,
+                pattern: None,
+            } => name.span(),
+            Pattern::Named { span, .. }
+            | Pattern::Tuple { span, .. }
+            | Pattern::Record { span, .. } => *span,
+        }
+    }
+}
+
+/// Writes the pattern as it would be written in the source
+impl Display for Pattern {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Pattern::Wildcard(_) => write!(f, "_"),
+            Pattern::Literal { token, value } => match value {
+                Literal::Int(value) if *value < 0 => write!(f, "-{}", token.lexeme()),
+                Literal::Float(value) if *value < 0.0 => write!(f, "-{}", token.lexeme()),
+                _ => write!(f, "{}", token.lexeme()),
+            },
+            Pattern::Binding {
+                name,
+                pattern: None,
+            } => write!(f, "{}", name.lexeme()),
+            Pattern::Binding {
+                name,
+                pattern: Some(pattern),
+            } => write!(f, "{} is {}", name.lexeme(), pattern),
+            Pattern::Named {
+                path, arguments, ..
+            } => {
+                let path: Vec<String> = path.iter().map(Token::lexeme).collect();
+                write!(f, "{}", path.join("::"))?;
+                match arguments {
+                    Some(arguments) => write!(f, "({})", join(arguments)),
+                    None => Ok(()),
+                }
+            }
+            Pattern::Tuple { elements, .. } => write!(f, "({})", join(elements)),
+            Pattern::Record { fields, open, .. } => {
+                let mut fields: Vec<String> = fields
+                    .iter()
+                    .map(|field| match &field.pattern {
+                        Some(pattern) => format!("{}: {}", field.name.lexeme(), pattern),
+                        None => field.name.lexeme(),
+                    })
+                    .collect();
+                if *open {
+                    fields.push("...".to_string());
+                }
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+        }
+    }
+}
+
+fn join(patterns: &[Pattern]) -> String {
+    patterns
+        .iter()
+        .map(Pattern::to_string)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldPattern {
+    pub name: Token,
+    pub pattern: Option<Pattern>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Unit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal {
+        token: Token,
+        value: Literal,
+    },
+    /// `fib` or `IO::println`
+    Variable {
+        path: Vec<Token>,
+    },
+    /// `$println`, which names a builtin even if something else has its name
+    Builtin {
+        dollar: Token,
+        name: Token,
+    },
+    SelfValue(Token),
+    Grouping {
+        expression: Box<Expr>,
+        span: Span,
+    },
+    Tuple {
+        paren: Token,
+        elements: Vec<Expr>,
+        span: Span,
+    },
+    Record {
+        brace: Token,
+        fields: Vec<FieldInit>,
+        span: Span,
+    },
+    Struct {
+        name: Token,
+        fields: Vec<FieldInit>,
+        span: Span,
+    },
+    Unary {
+        operator: Token,
+        operand: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    Is {
+        value: Box<Expr>,
+        keyword: Token,
+        pattern: Pattern,
+    },
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+        span: Span,
+    },
+    Field {
+        object: Box<Expr>,
+        name: Token,
+    },
+    /// `"x: ${ point.x }"`
+    Interpolation {
+        token: Token,
+        parts: Vec<StringPart>,
+        span: Span,
+    },
+    If {
+        keyword: Token,
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    Block(Block),
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal { token, .. } | Expr::SelfValue(token) => token.span(),
+            Expr::Variable { path } => path[0].span().to(path[path.len() - 1].span()),
+            Expr::Builtin { dollar, name } => dollar.span().to(name.span()),
+            Expr::Grouping { span, .. }
+            | Expr::Tuple { span, .. }
+            | Expr::Record { span, .. }
+            | Expr::Struct { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Interpolation { span, .. } => *span,
+            Expr::Unary { operator, operand } => operator.span().to(operand.span()),
+            Expr::Binary { left, right, .. } => left.span().to(right.span()),
+            Expr::Is { value, pattern, .. } => value.span().to(pattern.span()),
+            Expr::Field { object, name } => object.span().to(name.span()),
+            Expr::If {
+                keyword,
+                else_branch,
+                ..
+            } => keyword.span().to(else_branch.span()),
+            Expr::Block(block) => block.span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Literal(String),
+    Expression(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldInit {
+    pub name: Token,
+    pub value: Expr,
+}
@@ -0,0 +1,1103 @@
+use crate::ast::*;
+use crate::compiler::CompileError;
+use crate::scanner::{Scanner, Span, Token, TokenType};
+
+type ParseResult<T> = Result<T, CompileError>;
+
+/// Parses the tokens produced by `scanner` into a `Program`,
+/// collecting every error instead of stopping at the first one
+///
+/// # Examples
+/// ```
+/// use lucent_lang::ast::{self, Item};
+/// use lucent_lang::scanner;
+///
+/// let program = ast::parse(scanner::build_scanner("fn one() { 1 }")).unwrap();
+///
+/// match &program.items[0] {
+///     Item::Function(function) => assert_eq!("one", function.name.lexeme()),
+///     _ => panic!("expected a function"),
+/// }
+/// ```
+pub fn parse(scanner: Scanner) -> Result<Program, Vec<CompileError>> {
+    Parser::new(scanner).program()
+}
+
+/// Parses the tokens produced by `scanner` as a single expression
+pub fn parse_expression(scanner: Scanner) -> Result<Expr, Vec<CompileError>> {
+    Parser::new(scanner).single_expression()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+    errors: Vec<CompileError>,
+    // Struct literals are not allowed directly in a `where` guard,
+    // since the `{` that follows the guard starts the function body
+    allow_struct: bool,
+}
+
+impl Parser {
+    fn new(mut scanner: Scanner) -> Self {
+        // The scanner keeps track of any error tokens
+        let tokens = scanner
+            .by_ref()
+            .filter(|token| !matches!(token.token_type(), TokenType::Error(_)))
+            .collect();
+
+        Parser {
+            tokens,
+            current: 0,
+            errors: scanner.errors().to_vec(),
+            allow_struct: true,
+        }
+    }
+
+    fn program(mut self) -> Result<Program, Vec<CompileError>> {
+        let mut items = vec![];
+
+        while !self.is_at_end() {
+            let start = self.current;
+            match self.item() {
+                Ok(item) => items.push(item),
+                Err(error) => {
+                    self.errors.push(error);
+                    if self.current == start {
+                        self.advance();
+                    }
+                    self.synchronize();
+                }
+            }
+        }
+
+        if self.errors.is_empty() {
+            Ok(Program { items })
+        } else {
+            self.errors
+                .sort_by_key(|error| error.span().start().offset());
+            Err(self.errors)
+        }
+    }
+
+    fn single_expression(mut self) -> Result<Expr, Vec<CompileError>> {
+        let expression = self.expression().and_then(|expression| {
+            self.consume(&TokenType::EOF, "Expect end of expression.")?;
+            Ok(expression)
+        });
+
+        match expression {
+            Ok(expression) if self.errors.is_empty() => Ok(expression),
+            Ok(_) => Err(self.errors),
+            Err(error) => {
+                self.errors.push(error);
+                self.errors
+                    .sort_by_key(|error| error.span().start().offset());
+                Err(self.errors)
+            }
+        }
+    }
+
+    // Item parsing functions
+    fn item(&mut self) -> ParseResult<Item> {
+        if self.check(&TokenType::Struct) {
+            self.struct_decl().map(Item::Struct)
+        } else if self.check(&TokenType::Enum) {
+            self.enum_decl().map(Item::Enum)
+        } else if self.check(&TokenType::Trait) {
+            self.trait_decl().map(Item::Trait)
+        } else if self.check(&TokenType::Impure) || self.check(&TokenType::Function) {
+            self.function_def().map(Item::Function)
+        } else if self.check(&TokenType::Let) {
+            self.const_def().map(Item::Const)
+        } else if self.check(&TokenType::Define) {
+            Err(self.error_at_current(
+                "A define block must directly follow the struct or enum it defines.",
+            ))
+        } else {
+            Err(self.error_at_current("Expect a struct, enum, trait, function or constant."))
+        }
+    }
+
+    fn struct_decl(&mut self) -> ParseResult<StructDecl> {
+        self.advance(); // "struct"
+        let name = self.consume(&TokenType::Identifier, "Expect struct name.")?;
+        self.consume(&TokenType::LeftBrace, "Expect '{' after struct name.")?;
+
+        let mut fields = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            fields.push(self.pattern()?);
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after struct fields.")?;
+
+        let definition = if self.check(&TokenType::Define) {
+            let (name, members) = self.definition(&name, Parser::struct_member)?;
+            Some(StructDef { name, members })
+        } else {
+            None
+        };
+
+        Ok(StructDecl {
+            name,
+            fields,
+            definition,
+        })
+    }
+
+    fn struct_member(&mut self, type_name: &Token) -> ParseResult<StructMember> {
+        if self.check(&TokenType::Let) {
+            self.const_def().map(StructMember::Const)
+        } else if self.check(&TokenType::Impure) || self.check(&TokenType::Function) {
+            self.function_def().map(StructMember::Function)
+        } else if self.check_lexeme(&type_name.lexeme()) {
+            self.constructor().map(StructMember::Constructor)
+        } else {
+            Err(self.error_at_current("Expect a constant, constructor or function."))
+        }
+    }
+
+    fn constructor(&mut self) -> ParseResult<Constructor> {
+        let name = self.advance();
+        self.consume(&TokenType::LeftParen, "Expect '(' after constructor name.")?;
+        let params = self.parameters()?;
+
+        let body = if self.match_token(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.block()?)
+        };
+
+        Ok(Constructor { name, params, body })
+    }
+
+    fn trait_decl(&mut self) -> ParseResult<TraitDecl> {
+        self.advance(); // "trait"
+        let name = self.consume(&TokenType::Identifier, "Expect trait name.")?;
+        self.consume(&TokenType::LeftBrace, "Expect '{' after trait name.")?;
+        self.consume(&TokenType::RightBrace, "Expect '}', since a trait can't have members.")?;
+
+        Ok(TraitDecl { name })
+    }
+
+    fn enum_decl(&mut self) -> ParseResult<EnumDecl> {
+        self.advance(); // "enum"
+        let name = self.consume(&TokenType::Identifier, "Expect enum name.")?;
+        self.consume(&TokenType::LeftBrace, "Expect '{' after enum name.")?;
+
+        let mut variants = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let variant = self.consume(&TokenType::Identifier, "Expect variant name.")?;
+            let payload = if self.match_token(&TokenType::LeftParen) {
+                self.parameters()?
+            } else {
+                vec![]
+            };
+            variants.push(Variant {
+                name: variant,
+                payload,
+            });
+
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after enum variants.")?;
+
+        let definition = if self.check(&TokenType::Define) {
+            let (name, members) = self.definition(&name, Parser::enum_member)?;
+            Some(EnumDef { name, members })
+        } else {
+            None
+        };
+
+        Ok(EnumDecl {
+            name,
+            variants,
+            definition,
+        })
+    }
+
+    fn enum_member(&mut self, _type_name: &Token) -> ParseResult<EnumMember> {
+        if self.check(&TokenType::Let) {
+            self.const_def().map(EnumMember::Const)
+        } else if self.check(&TokenType::Impure) || self.check(&TokenType::Function) {
+            self.function_def().map(EnumMember::Function)
+        } else {
+            Err(self.error_at_current("Expect a constant or function."))
+        }
+    }
+
+    /// Parses a `define` block for the type named `type_name`,
+    /// using `member` to parse each of its members
+    fn definition<T, F>(&mut self, type_name: &Token, member: F) -> ParseResult<(Token, Vec<T>)>
+    where
+        F: Fn(&mut Parser, &Token) -> ParseResult<T>,
+    {
+        self.advance(); // "define"
+        let name = self.consume(&TokenType::Identifier, "Expect type name after 'define'.")?;
+        if name.lexeme() != type_name.lexeme() {
+            return Err(CompileError::at(
+                &name,
+                &format!("Expect define block for '{}'.", type_name.lexeme()),
+            ));
+        }
+        self.consume(&TokenType::LeftBrace, "Expect '{' after type name.")?;
+
+        let mut members = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            members.push(member(self, type_name)?);
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after define block.")?;
+
+        Ok((name, members))
+    }
+
+    fn function_def(&mut self) -> ParseResult<FunctionDef> {
+        let impure = self.match_token(&TokenType::Impure);
+        self.consume(&TokenType::Function, "Expect 'fn'.")?;
+        let name = self.consume(&TokenType::Identifier, "Expect function name.")?;
+        self.consume(&TokenType::LeftParen, "Expect '(' after function name.")?;
+        let params = self.parameters()?;
+
+        let guard = if self.match_token(&TokenType::Where) {
+            self.allow_struct = false;
+            let guard = self.expression();
+            self.allow_struct = true;
+            Some(guard?)
+        } else {
+            None
+        };
+
+        let body = self.block()?;
+
+        Ok(FunctionDef {
+            impure,
+            name,
+            params,
+            guard,
+            body,
+        })
+    }
+
+    /// Parses a comma-separated list of patterns,
+    /// including the closing parenthesis
+    fn parameters(&mut self) -> ParseResult<Vec<Pattern>> {
+        let mut params = vec![];
+        while !self.check(&TokenType::RightParen) && !self.is_at_end() {
+            params.push(self.pattern()?);
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightParen, "Expect ')' after parameters.")?;
+
+        Ok(params)
+    }
+
+    fn const_def(&mut self) -> ParseResult<ConstDef> {
+        let keyword = self.advance(); // "let"
+        let pattern = self.pattern()?;
+        self.consume(&TokenType::Equal, "Expect '=' after pattern.")?;
+        let value = self.expression()?;
+        self.consume(&TokenType::Semicolon, "Expect ';' after value.")?;
+
+        Ok(ConstDef {
+            keyword,
+            pattern,
+            value,
+        })
+    }
+
+    fn block(&mut self) -> ParseResult<Block> {
+        let brace = self.consume(&TokenType::LeftBrace, "Expect '{' before block.")?;
+        let allow_struct = self.allow_struct;
+        self.allow_struct = true;
+
+        let mut statements = vec![];
+        let mut value = None;
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if self.check(&TokenType::Let) {
+                statements.push(Stmt::Let(self.const_def()?));
+                continue;
+            }
+
+            let statement = if self.check(&TokenType::Assert) {
+                let keyword = self.advance();
+                let condition = self.expression()?;
+                Stmt::Assert { keyword, condition }
+            } else {
+                let expression = self.expression()?;
+                if self.check(&TokenType::RightBrace) {
+                    value = Some(Box::new(expression));
+                    break;
+                }
+                Stmt::Expression(expression)
+            };
+            statements.push(statement);
+
+            if !self.check(&TokenType::RightBrace) {
+                self.consume(&TokenType::Semicolon, "Expect ';' after expression.")?;
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after block.")?;
+        self.allow_struct = allow_struct;
+
+        Ok(Block {
+            span: self.span_from(&brace),
+            brace,
+            statements,
+            value,
+        })
+    }
+
+    // Pattern parsing functions
+    fn pattern(&mut self) -> ParseResult<Pattern> {
+        let token = self.advance();
+        match token.token_type() {
+            TokenType::Underscore => Ok(Pattern::Wildcard(token)),
+            TokenType::Int(value) => Ok(Pattern::Literal {
+                token,
+                value: Literal::Int(value),
+            }),
+            TokenType::Float(value) => Ok(Pattern::Literal {
+                token,
+                value: Literal::Float(value),
+            }),
+            TokenType::Minus => {
+                let number = self.advance();
+                match number.token_type() {
+                    TokenType::Int(value) => Ok(Pattern::Literal {
+                        token: number,
+                        value: Literal::Int(-value),
+                    }),
+                    TokenType::Float(value) => Ok(Pattern::Literal {
+                        token: number,
+                        value: Literal::Float(-value),
+                    }),
+                    _ => Err(CompileError::at(&number, "Expect number after '-'.")),
+                }
+            }
+            TokenType::String(value) => Ok(Pattern::Literal {
+                token,
+                value: Literal::String(value),
+            }),
+            TokenType::True => Ok(Pattern::Literal {
+                token,
+                value: Literal::Bool(true),
+            }),
+            TokenType::False => Ok(Pattern::Literal {
+                token,
+                value: Literal::Bool(false),
+            }),
+            TokenType::Identifier if is_type_name(&token) => {
+                let mut path = vec![token.clone()];
+                while self.match_token(&TokenType::ColonColon) {
+                    path.push(self.consume(&TokenType::Identifier, "Expect name after '::'.")?);
+                }
+                let arguments = if self.match_token(&TokenType::LeftParen) {
+                    Some(self.parameters()?)
+                } else {
+                    None
+                };
+                Ok(Pattern::Named {
+                    span: self.span_from(&token),
+                    path,
+                    arguments,
+                })
+            }
+            TokenType::Identifier | TokenType::SelfKey => {
+                let pattern = if self.match_token(&TokenType::Is) {
+                    Some(Box::new(self.pattern()?))
+                } else {
+                    None
+                };
+                Ok(Pattern::Binding {
+                    name: token,
+                    pattern,
+                })
+            }
+            TokenType::LeftParen => self.tuple_pattern(token),
+            TokenType::LeftBrace => self.record_pattern(token),
+            _ => Err(CompileError::at(&token, "Expect pattern.")),
+        }
+    }
+
+    fn tuple_pattern(&mut self, paren: Token) -> ParseResult<Pattern> {
+        let mut elements = vec![];
+        let mut trailing_comma = false;
+        while !self.check(&TokenType::RightParen) && !self.is_at_end() {
+            elements.push(self.pattern()?);
+            trailing_comma = self.match_token(&TokenType::Comma);
+            if !trailing_comma {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightParen, "Expect ')' after tuple pattern.")?;
+
+        match elements.len() {
+            0 => Ok(Pattern::Literal {
+                token: paren,
+                value: Literal::Unit,
+            }),
+            1 if !trailing_comma => Ok(elements.remove(0)),
+            _ => Ok(Pattern::Tuple {
+                span: self.span_from(&paren),
+                paren,
+                elements,
+            }),
+        }
+    }
+
+    fn record_pattern(&mut self, brace: Token) -> ParseResult<Pattern> {
+        let mut fields = vec![];
+        let mut open = false;
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            if self.match_token(&TokenType::DotDotDot) {
+                open = true;
+                break;
+            }
+
+            let name = self.consume(&TokenType::Identifier, "Expect field name.")?;
+            let pattern = if self.match_token(&TokenType::Colon) {
+                Some(self.pattern()?)
+            } else {
+                None
+            };
+            fields.push(FieldPattern { name, pattern });
+
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after record pattern.")?;
+
+        Ok(Pattern::Record {
+            span: self.span_from(&brace),
+            brace,
+            fields,
+            open,
+        })
+    }
+
+    // Expression parsing functions
+    fn expression(&mut self) -> ParseResult<Expr> {
+        if self.check(&TokenType::If) {
+            self.if_expression()
+        } else {
+            self.or()
+        }
+    }
+
+    fn if_expression(&mut self) -> ParseResult<Expr> {
+        let keyword = self.advance();
+        let condition = self.expression()?;
+        self.consume(&TokenType::Question, "Expect '?' after condition.")?;
+        let then_branch = self.expression()?;
+        self.consume(&TokenType::Else, "Expect 'else' after conditional branch.")?;
+        let else_branch = self.expression()?;
+
+        Ok(Expr::If {
+            keyword,
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+        })
+    }
+
+    fn or(&mut self) -> ParseResult<Expr> {
+        self.binary(&[TokenType::Or], Parser::and)
+    }
+
+    fn and(&mut self) -> ParseResult<Expr> {
+        self.binary(&[TokenType::And], Parser::equality)
+    }
+
+    fn equality(&mut self) -> ParseResult<Expr> {
+        self.binary(
+            &[TokenType::EqualEqual, TokenType::BangEqual],
+            Parser::comparison,
+        )
+    }
+
+    fn comparison(&mut self) -> ParseResult<Expr> {
+        let operators = [
+            TokenType::Less,
+            TokenType::LessEqual,
+            TokenType::Greater,
+            TokenType::GreaterEqual,
+        ];
+        let mut expr = self.term()?;
+
+        loop {
+            if operators.iter().any(|operator| self.check(operator)) {
+                let operator = self.advance();
+                let right = self.term()?;
+                expr = Expr::Binary {
+                    left: Box::new(expr),
+                    operator,
+                    right: Box::new(right),
+                };
+            } else if self.check(&TokenType::Is) {
+                let keyword = self.advance();
+                let pattern = self.pattern()?;
+                expr = Expr::Is {
+                    value: Box::new(expr),
+                    keyword,
+                    pattern,
+                };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn term(&mut self) -> ParseResult<Expr> {
+        self.binary(&[TokenType::Plus, TokenType::Minus], Parser::factor)
+    }
+
+    fn factor(&mut self) -> ParseResult<Expr> {
+        self.binary(
+            &[TokenType::Star, TokenType::Slash, TokenType::Percent],
+            Parser::unary,
+        )
+    }
+
+    /// Parses a left-associative chain of `operand`s
+    /// separated by any of the given `operators`
+    fn binary<F>(&mut self, operators: &[TokenType], operand: F) -> ParseResult<Expr>
+    where
+        F: Fn(&mut Parser) -> ParseResult<Expr>,
+    {
+        let mut expr = operand(self)?;
+
+        while operators.iter().any(|operator| self.check(operator)) {
+            let operator = self.advance();
+            let right = operand(self)?;
+            expr = Expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> ParseResult<Expr> {
+        if self.check(&TokenType::Minus) || self.check(&TokenType::Bang) {
+            let operator = self.advance();
+            let operand = self.unary()?;
+            Ok(Expr::Unary {
+                operator,
+                operand: Box::new(operand),
+            })
+        } else {
+            self.call()
+        }
+    }
+
+    fn call(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.check(&TokenType::LeftParen) {
+                let paren = self.advance();
+                let arguments = self.arguments()?;
+                expr = Expr::Call {
+                    span: expr.span().to(self.previous().span()),
+                    callee: Box::new(expr),
+                    paren,
+                    arguments,
+                };
+            } else if self.match_token(&TokenType::Dot) {
+                let name = self.consume(&TokenType::Identifier, "Expect field name after '.'.")?;
+                expr = Expr::Field {
+                    object: Box::new(expr),
+                    name,
+                };
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Parses a comma-separated list of expressions,
+    /// including the closing parenthesis
+    fn arguments(&mut self) -> ParseResult<Vec<Expr>> {
+        let allow_struct = self.allow_struct;
+        self.allow_struct = true;
+
+        let mut arguments = vec![];
+        while !self.check(&TokenType::RightParen) && !self.is_at_end() {
+            arguments.push(self.expression()?);
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightParen, "Expect ')' after arguments.")?;
+        self.allow_struct = allow_struct;
+
+        Ok(arguments)
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
+        if self.check(&TokenType::LeftBrace) {
+            return if self.at_record_literal() {
+                let brace = self.advance();
+                let fields = self.field_inits()?;
+                Ok(Expr::Record {
+                    span: self.span_from(&brace),
+                    brace,
+                    fields,
+                })
+            } else {
+                self.block().map(Expr::Block)
+            };
+        }
+
+        let token = self.advance();
+        match token.token_type() {
+            TokenType::Int(value) => Ok(Expr::Literal {
+                token,
+                value: Literal::Int(value),
+            }),
+            TokenType::Float(value) => Ok(Expr::Literal {
+                token,
+                value: Literal::Float(value),
+            }),
+            TokenType::String(value) => Ok(Expr::Literal {
+                token,
+                value: Literal::String(value),
+            }),
+            TokenType::True => Ok(Expr::Literal {
+                token,
+                value: Literal::Bool(true),
+            }),
+            TokenType::False => Ok(Expr::Literal {
+                token,
+                value: Literal::Bool(false),
+            }),
+            TokenType::InterpolationStart(text) => self.interpolation(token, text),
+            TokenType::SelfKey => Ok(Expr::SelfValue(token)),
+            TokenType::Identifier
+                if self.allow_struct
+                    && is_type_name(&token)
+                    && self.check(&TokenType::LeftBrace) =>
+            {
+                self.advance();
+                let fields = self.field_inits()?;
+                Ok(Expr::Struct {
+                    span: self.span_from(&token),
+                    name: token,
+                    fields,
+                })
+            }
+            TokenType::Identifier => {
+                let mut path = vec![token];
+                while self.match_token(&TokenType::ColonColon) {
+                    path.push(self.consume(&TokenType::Identifier, "Expect name after '::'.")?);
+                }
+                Ok(Expr::Variable { path })
+            }
+            TokenType::Dollar => {
+                let name = self.consume(&TokenType::Identifier, "Expect builtin name after '$'.")?;
+                Ok(Expr::Builtin { dollar: token, name })
+            }
+            TokenType::LeftParen => self.parenthesized(token),
+            _ => Err(CompileError::at(&token, "Expect expression.")),
+        }
+    }
+
+    /// Parses the expressions and string segments of an
+    /// interpolated string, up to and including its end
+    fn interpolation(&mut self, token: Token, text: String) -> ParseResult<Expr> {
+        let allow_struct = self.allow_struct;
+        self.allow_struct = true;
+
+        let mut parts = vec![];
+        let mut text = text;
+        loop {
+            if !text.is_empty() {
+                parts.push(StringPart::Literal(text));
+            }
+            parts.push(StringPart::Expression(Box::new(self.expression()?)));
+
+            let segment = self.advance();
+            match segment.token_type() {
+                TokenType::InterpolationSegment(next) => text = next,
+                TokenType::InterpolationEnd(last) => {
+                    if !last.is_empty() {
+                        parts.push(StringPart::Literal(last));
+                    }
+                    break;
+                }
+                _ => {
+                    return Err(CompileError::at(
+                        &segment,
+                        "Expect '}' after interpolated expression.",
+                    ))
+                }
+            }
+        }
+        self.allow_struct = allow_struct;
+
+        Ok(Expr::Interpolation {
+            span: self.span_from(&token),
+            token,
+            parts,
+        })
+    }
+
+    /// Parses a unit literal, a grouping or a tuple
+    fn parenthesized(&mut self, paren: Token) -> ParseResult<Expr> {
+        let allow_struct = self.allow_struct;
+        self.allow_struct = true;
+
+        let mut elements = vec![];
+        let mut trailing_comma = false;
+        while !self.check(&TokenType::RightParen) && !self.is_at_end() {
+            elements.push(self.expression()?);
+            trailing_comma = self.match_token(&TokenType::Comma);
+            if !trailing_comma {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightParen, "Expect ')' after expression.")?;
+        self.allow_struct = allow_struct;
+
+        match elements.len() {
+            0 => Ok(Expr::Literal {
+                token: paren,
+                value: Literal::Unit,
+            }),
+            1 if !trailing_comma => Ok(Expr::Grouping {
+                expression: Box::new(elements.remove(0)),
+                span: self.span_from(&paren),
+            }),
+            _ => Ok(Expr::Tuple {
+                span: self.span_from(&paren),
+                paren,
+                elements,
+            }),
+        }
+    }
+
+    /// Parses the fields of a record or struct literal,
+    /// including the closing brace
+    fn field_inits(&mut self) -> ParseResult<Vec<FieldInit>> {
+        let allow_struct = self.allow_struct;
+        self.allow_struct = true;
+
+        let mut fields = vec![];
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let name = self.consume(&TokenType::Identifier, "Expect field name.")?;
+            self.consume(&TokenType::Colon, "Expect ':' after field name.")?;
+            let value = self.expression()?;
+            fields.push(FieldInit { name, value });
+
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after fields.")?;
+        self.allow_struct = allow_struct;
+
+        Ok(fields)
+    }
+
+    /// A `{` starts a record literal rather than a block
+    /// if it is empty or starts with `identifier :`
+    fn at_record_literal(&self) -> bool {
+        matches!(
+            (self.peek(1).token_type(), self.peek(2).token_type()),
+            (TokenType::RightBrace, _) | (TokenType::Identifier, TokenType::Colon)
+        )
+    }
+
+    // Token handling functions
+    fn peek(&self, distance: usize) -> &Token {
+        let index = (self.current + distance).min(self.tokens.len() - 1);
+        &self.tokens[index]
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current.saturating_sub(1)]
+    }
+
+    /// The span from `start` to the end of the last consumed token
+    fn span_from(&self, start: &Token) -> Span {
+        start.span().to(self.previous().span())
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.peek(0).token_type() == TokenType::EOF
+    }
+
+    fn check(&self, t_type: &TokenType) -> bool {
+        self.peek(0).token_type() == *t_type
+    }
+
+    fn check_lexeme(&self, lexeme: &str) -> bool {
+        self.peek(0).lexeme() == lexeme
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.peek(0).clone();
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+        token
+    }
+
+    fn match_token(&mut self, t_type: &TokenType) -> bool {
+        if self.check(t_type) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn consume(&mut self, t_type: &TokenType, message: &str) -> ParseResult<Token> {
+        if self.check(t_type) {
+            Ok(self.advance())
+        } else {
+            Err(self.error_at_current(message))
+        }
+    }
+
+    fn error_at_current(&self, message: &str) -> CompileError {
+        CompileError::at(self.peek(0), message)
+    }
+
+    /// Skips tokens until the start of the next item
+    /// so that one error doesn't cascade into many
+    fn synchronize(&mut self) {
+        while !self.is_at_end()
+            && !self.check(&TokenType::Function)
+            && !self.check(&TokenType::Let)
+            && !self.check(&TokenType::Struct)
+            && !self.check(&TokenType::Enum)
+            && !self.check(&TokenType::Trait)
+            && !self.check(&TokenType::Impure)
+        {
+            self.advance();
+        }
+    }
+}
+
+/// Type and constructor names are capitalized,
+/// everything else in a pattern is a binding
+fn is_type_name(token: &Token) -> bool {
+    token
+        .lexeme()
+        .chars()
+        .next()
+        .is_some_and(char::is_uppercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::build_scanner;
+
+    #[test]
+    fn test_struct_declaration() {
+        let program = parse(build_scanner(
+            "struct Point { x is Int, y is Int, }
+            define Point {
+                let ORIGIN = Point {x: 1, y: 1};
+                Point(x, y);
+                fn sum(self) { self.x + self.y }
+            }",
+        ))
+        .unwrap();
+
+        let point = match &program.items[0] {
+            Item::Struct(point) => point,
+            item => panic!("expected a struct, found {:?}", item),
+        };
+        assert_eq!("Point", point.name.lexeme());
+        assert_eq!(2, point.fields.len());
+
+        let members = &point.definition.as_ref().unwrap().members;
+        assert!(matches!(members[0], StructMember::Const(_)));
+        assert!(matches!(
+            members[1],
+            StructMember::Constructor(Constructor { body: None, .. })
+        ));
+        assert!(matches!(members[2], StructMember::Function(_)));
+    }
+
+    #[test]
+    fn test_enum_declaration() {
+        let program = parse(build_scanner(
+            "enum Shape { Circle(radius is Float), Square(side is Float), Empty, }
+            define Shape {
+                fn empty() { Empty }
+                fn radius(Shape::Circle(r)) { r }
+            }",
+        ))
+        .unwrap();
+
+        match &program.items[0] {
+            Item::Enum(shape) => {
+                assert_eq!(3, shape.variants.len());
+                assert_eq!(1, shape.variants[0].payload.len());
+                assert!(shape.variants[2].payload.is_empty());
+
+                let members = &shape.definition.as_ref().unwrap().members;
+                assert_eq!(2, members.len());
+                match &members[1] {
+                    EnumMember::Function(function) => assert!(matches!(
+                        &function.params[0],
+                        Pattern::Named { path, arguments: Some(_), .. } if path.len() == 2
+                    )),
+                    member => panic!("expected a function, found {:?}", member),
+                }
+            }
+            item => panic!("expected an enum, found {:?}", item),
+        }
+    }
+
+    #[test]
+    fn test_trait_declaration() {
+        let program = parse(build_scanner("trait Show { } fn main() { }")).unwrap();
+        match &program.items[0] {
+            Item::Trait(show) => assert_eq!("Show", show.name.lexeme()),
+            item => panic!("expected a trait, found {:?}", item),
+        }
+
+        let errors = parse(build_scanner("trait Show { fn show(self); }")).unwrap_err();
+        assert_eq!(
+            "Expect '}', since a trait can't have members.",
+            errors[0].message()
+        );
+    }
+
+    #[test]
+    fn test_function_definition() {
+        let program = parse(build_scanner(
+            "impure fn print_point(point) where point is {x, y, ...} {
+                let (a, b) = (1, 2);
+                assert a == 1;
+                IO::println(point.x);
+            }",
+        ))
+        .unwrap();
+
+        let function = match &program.items[0] {
+            Item::Function(function) => function,
+            item => panic!("expected a function, found {:?}", item),
+        };
+        assert!(function.impure);
+        assert!(matches!(
+            function.guard,
+            Some(Expr::Is {
+                pattern: Pattern::Record { open: true, .. },
+                ..
+            })
+        ));
+        assert_eq!(3, function.body.statements.len());
+        assert!(function.body.value.is_none());
+
+        match &function.body.statements[2] {
+            Stmt::Expression(Expr::Call { callee, .. }) => match &**callee {
+                Expr::Variable { path } => assert_eq!(2, path.len()),
+                callee => panic!("expected a path, found {:?}", callee),
+            },
+            statement => panic!("expected a call, found {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn test_expression_precedence() {
+        let program = parse(build_scanner("fn f() { 1 + 2 * 3 == 7 and true }")).unwrap();
+
+        let value = match &program.items[0] {
+            Item::Function(function) => function.body.value.as_ref().unwrap(),
+            item => panic!("expected a function, found {:?}", item),
+        };
+        assert_eq!("(and (== (+ 1 (* 2 3)) 7) true)", print(value));
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let program = parse(build_scanner(
+            "impure fn print_point(point) { IO::println(\"x: ${ point(1) }, y: ${ point.y }\") }",
+        ))
+        .unwrap();
+
+        let value = match &program.items[0] {
+            Item::Function(function) => function.body.value.as_ref().unwrap(),
+            item => panic!("expected a function, found {:?}", item),
+        };
+        match &**value {
+            Expr::Call { arguments, .. } => match &arguments[0] {
+                Expr::Interpolation { parts, .. } => {
+                    assert_eq!(4, parts.len());
+                    assert_eq!(StringPart::Literal("x: ".to_string()), parts[0]);
+                    assert!(matches!(
+                        &parts[1],
+                        StringPart::Expression(expr) if matches!(**expr, Expr::Call { .. })
+                    ));
+                    assert_eq!(StringPart::Literal(", y: ".to_string()), parts[2]);
+                    assert!(matches!(
+                        &parts[3],
+                        StringPart::Expression(expr) if matches!(**expr, Expr::Field { .. })
+                    ));
+                }
+                argument => panic!("expected an interpolation, found {:?}", argument),
+            },
+            value => panic!("expected a call, found {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_spans() {
+        let code = "fn f(p) { 1 + f(2, 3).x * Point { x: 1 } }";
+        let program = parse(build_scanner(code)).unwrap();
+        let function = match &program.items[0] {
+            Item::Function(function) => function,
+            item => panic!("expected a function, found {:?}", item),
+        };
+        let source = |span: Span| &code[span.start().offset()..span.end().offset()];
+
+        assert_eq!(
+            "{ 1 + f(2, 3).x * Point { x: 1 } }",
+            source(function.body.span)
+        );
+        let value = function.body.value.as_ref().unwrap();
+        assert_eq!("1 + f(2, 3).x * Point { x: 1 }", source(value.span()));
+        match &**value {
+            Expr::Binary { right, .. } => match &**right {
+                Expr::Binary { left, right, .. } => {
+                    assert_eq!("f(2, 3).x", source(left.span()));
+                    assert_eq!("Point { x: 1 }", source(right.span()));
+                }
+                expr => panic!("expected a product, found {:?}", expr),
+            },
+            expr => panic!("expected a sum, found {:?}", expr),
+        }
+    }
+
+    #[test]
+    fn test_multiple_errors() {
+        let errors = parse(build_scanner("fn a( { 1 } fn b() { 1 + } struct")).unwrap_err();
+        assert_eq!(3, errors.len());
+    }
+
+    fn print(expr: &Expr) -> String {
+        match expr {
+            Expr::Literal { token, .. } => token.lexeme(),
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => format!("({} {} {})", operator.lexeme(), print(left), print(right)),
+            _ => panic!("cannot print {:?}", expr),
+        }
+    }
+}
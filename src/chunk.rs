@@ -1,7 +1,10 @@
 // TODO Move this to VM module
 
-use crate::value::{Value, ValueArray};
+use crate::scanner::Span;
+use crate::decision::Match;
+use crate::value::{Pattern, Template, Value, ValueArray};
 use std::convert::From;
+use std::rc::Rc;
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum OpCode {
@@ -13,6 +16,36 @@ pub enum OpCode {
     Subtract,
     Multiply,
     Divide,
+    Pop,
+    GetLocal,
+    GetGlobal,
+    DefineGlobal,
+    Call,
+    EndScope,
+    Construct,
+    GetField,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Tuple,
+    Record,
+    Destructure,
+    Not,
+    Jump,
+    JumpIfFalse,
+    CheckBool,
+    Modulo,
+    ToInt,
+    ToFloat,
+    ToString,
+    Interpolate,
+    Is,
+    Print,
+    ReadLine,
+    Assert,
     UnexpectedEndOfChunk,
     Invalid(u8),
 }
@@ -28,6 +61,36 @@ impl OpCode {
             OpCode::Subtract => 5,
             OpCode::Multiply => 6,
             OpCode::Divide => 7,
+            OpCode::Pop => 8,
+            OpCode::GetLocal => 9,
+            OpCode::GetGlobal => 10,
+            OpCode::DefineGlobal => 11,
+            OpCode::Call => 12,
+            OpCode::EndScope => 13,
+            OpCode::Construct => 14,
+            OpCode::GetField => 15,
+            OpCode::Equal => 16,
+            OpCode::NotEqual => 17,
+            OpCode::Greater => 18,
+            OpCode::GreaterEqual => 19,
+            OpCode::Less => 20,
+            OpCode::LessEqual => 21,
+            OpCode::Tuple => 22,
+            OpCode::Record => 23,
+            OpCode::Destructure => 24,
+            OpCode::Not => 25,
+            OpCode::Jump => 26,
+            OpCode::JumpIfFalse => 27,
+            OpCode::CheckBool => 28,
+            OpCode::Modulo => 29,
+            OpCode::ToInt => 30,
+            OpCode::ToFloat => 31,
+            OpCode::ToString => 32,
+            OpCode::Interpolate => 33,
+            OpCode::Is => 34,
+            OpCode::Print => 35,
+            OpCode::ReadLine => 36,
+            OpCode::Assert => 37,
             OpCode::UnexpectedEndOfChunk => 255,
             OpCode::Invalid(code) => *code,
         }
@@ -45,49 +108,161 @@ impl From<u8> for OpCode {
             5 => OpCode::Subtract,
             6 => OpCode::Multiply,
             7 => OpCode::Divide,
+            8 => OpCode::Pop,
+            9 => OpCode::GetLocal,
+            10 => OpCode::GetGlobal,
+            11 => OpCode::DefineGlobal,
+            12 => OpCode::Call,
+            13 => OpCode::EndScope,
+            14 => OpCode::Construct,
+            15 => OpCode::GetField,
+            16 => OpCode::Equal,
+            17 => OpCode::NotEqual,
+            18 => OpCode::Greater,
+            19 => OpCode::GreaterEqual,
+            20 => OpCode::Less,
+            21 => OpCode::LessEqual,
+            22 => OpCode::Tuple,
+            23 => OpCode::Record,
+            24 => OpCode::Destructure,
+            25 => OpCode::Not,
+            26 => OpCode::Jump,
+            27 => OpCode::JumpIfFalse,
+            28 => OpCode::CheckBool,
+            29 => OpCode::Modulo,
+            30 => OpCode::ToInt,
+            31 => OpCode::ToFloat,
+            32 => OpCode::ToString,
+            33 => OpCode::Interpolate,
+            34 => OpCode::Is,
+            35 => OpCode::Print,
+            36 => OpCode::ReadLine,
+            37 => OpCode::Assert,
             255 => OpCode::UnexpectedEndOfChunk,
             _ => OpCode::Invalid(byte),
         }
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct Chunk {
     code: Vec<u8>,
-    lines: Vec<u32>,
+    // The offset of the first byte of each instruction,
+    // along with the span of the code it was compiled from
+    spans: Vec<(usize, Span)>,
     constants: ValueArray,
+    // The names of the fields that are looked up by the chunk
+    names: Vec<Rc<str>>,
+    // The structs that are constructed by the chunk
+    templates: Vec<Rc<Template>>,
+    // The names of the fields of the records that are built by the chunk
+    records: Vec<Vec<String>>,
+    // The patterns that values are destructured or tested with
+    patterns: Vec<Match>,
 }
 
 impl Chunk {
     pub fn new() -> Self {
         Chunk {
             code: vec![],
-            lines: vec![], // TODO Implement compressed line storage (see chapter 1 of part 3)
+            spans: vec![],
             constants: ValueArray::new(),
+            names: vec![],
+            templates: vec![],
+            records: vec![],
+            patterns: vec![],
         }
     }
 
-    pub fn write_chunk(mut self, op_code: &OpCode, line: u32) -> Self {
-        self.write_byte(op_code.to_byte(), line);
+    pub fn write_chunk(mut self, op_code: &OpCode, span: Span) -> Self {
+        self.write_instruction(&[op_code.to_byte()], span);
         self
     }
 
-    pub fn write_constant(mut self, constant: Value, line: u32) -> Self {
+    pub fn write_constant(mut self, constant: Value, span: Span) -> Self {
         let location = self.constants.write_value(constant);
-        if location <= u8::max_value() as usize {
-            self.write_byte(OpCode::Constant.to_byte(), line);
-            self.write_byte(location as u8, line);
+        if location <= u8::MAX as usize {
+            self.write_instruction(&[OpCode::Constant.to_byte(), location as u8], span);
         } else {
-            self.write_byte(OpCode::ConstantLong.to_byte(), line);
-            self.write_byte((location >> 8) as u8, line);
-            self.write_byte(location as u8, line);
+            self.write_instruction(
+                &[
+                    OpCode::ConstantLong.to_byte(),
+                    (location >> 8) as u8,
+                    location as u8,
+                ],
+                span,
+            );
         }
         self
     }
 
-    fn write_byte(&mut self, byte: u8, line: u32) {
-        self.code.push(byte);
-        self.lines.push(line);
+    /// Writes an instruction that takes one or more bytes as operands
+    pub fn write_operands(mut self, op_code: &OpCode, operands: &[u8], span: Span) -> Self {
+        let bytes: Vec<u8> = std::iter::once(op_code.to_byte())
+            .chain(operands.iter().cloned())
+            .collect();
+        self.write_instruction(&bytes, span);
+        self
+    }
+
+    /// Adds a field name to the chunk, returning
+    /// its index for use as an operand
+    pub fn add_name(&mut self, name: Rc<str>) -> usize {
+        match self.names.iter().position(|existing| *existing == name) {
+            Some(index) => index,
+            None => {
+                self.names.push(name);
+                self.names.len() - 1
+            }
+        }
+    }
+
+    /// Adds a template to the chunk, returning
+    /// its index for use as an operand
+    pub fn add_template(&mut self, template: Rc<Template>) -> usize {
+        match self.templates.iter().position(|existing| Rc::ptr_eq(existing, &template)) {
+            Some(index) => index,
+            None => {
+                self.templates.push(template);
+                self.templates.len() - 1
+            }
+        }
+    }
+
+    /// Adds the field names of a record to the chunk,
+    /// returning their index for use as an operand
+    pub fn add_record(&mut self, fields: Vec<String>) -> usize {
+        match self.records.iter().position(|existing| *existing == fields) {
+            Some(index) => index,
+            None => {
+                self.records.push(fields);
+                self.records.len() - 1
+            }
+        }
+    }
+
+    /// Adds a pattern to the chunk, returning
+    /// its index for use as an operand
+    pub fn add_pattern(&mut self, pattern: Pattern) -> usize {
+        match self.patterns.iter().position(|existing| *existing.pattern() == pattern) {
+            Some(index) => index,
+            None => {
+                self.patterns.push(Match::new(pattern));
+                self.patterns.len() - 1
+            }
+        }
+    }
+
+    /// Overwrites the two byte operand at `offset`,
+    /// such as the offset of a jump once its target is known
+    pub fn patch_short(&mut self, offset: usize, operand: usize) {
+        self.code[offset] = (operand >> 8) as u8;
+        self.code[offset + 1] = operand as u8;
+    }
+
+    fn write_instruction(&mut self, bytes: &[u8], span: Span) {
+        self.spans.push((self.code.len(), span));
+        self.code.extend_from_slice(bytes);
     }
 
     pub fn get_size(&self) -> usize {
@@ -98,6 +273,33 @@ impl Chunk {
         self.code.get(offset).cloned()
     }
 
+    /// Reads the two byte operand at `offset`
+    pub fn get_short(&self, offset: usize) -> Option<usize> {
+        let first_byte = self.get_byte(offset)? as usize;
+        let second_byte = self.get_byte(offset + 1)? as usize;
+        Some((first_byte << 8) + second_byte)
+    }
+
+    pub fn get_name(&self, index: usize) -> Option<&str> {
+        self.names.get(index).map(|name| &**name)
+    }
+
+    pub fn get_template(&self, index: usize) -> Option<Rc<Template>> {
+        self.templates.get(index).cloned()
+    }
+
+    pub fn get_record(&self, index: usize) -> Option<&[String]> {
+        self.records.get(index).map(Vec::as_slice)
+    }
+
+    pub fn get_match(&self, index: usize) -> Option<&Match> {
+        self.patterns.get(index)
+    }
+
+    pub fn get_constants_size(&self) -> usize {
+        self.constants.get_size()
+    }
+
     pub fn get_constant(&self, constant_index: usize) -> Option<Value> {
         self.constants.get_constant(constant_index)
     }
@@ -110,8 +312,20 @@ impl Chunk {
         self.get_constant(((constant_index_first_byte) << 8) + (constant_index_second_byte))
     }
 
+    /// The span of the instruction that the byte at `offset` is part of
+    pub fn get_span(&self, offset: usize) -> Option<Span> {
+        if offset >= self.code.len() {
+            return None;
+        }
+
+        let instruction = self.spans.partition_point(|(start, _)| *start <= offset);
+        self.spans
+            .get(instruction.checked_sub(1)?)
+            .map(|(_, span)| *span)
+    }
+
     pub fn get_line(&self, offset: usize) -> Option<u32> {
-        self.lines.get(offset).cloned()
+        self.get_span(offset).map(|span| span.start().line())
     }
 }
 
@@ -124,7 +338,7 @@ mod tests {
     #[test]
     fn test_chunks() {
         let chunk = Chunk::new();
-        let chunk = chunk.write_chunk(&OpCode::Return, 1);
+        let chunk = chunk.write_chunk(&OpCode::Return, Span::on_line(1));
         assert_eq!(OpCode::Return.to_byte(), chunk.get_byte(0).unwrap());
     }
 
@@ -139,11 +353,41 @@ mod tests {
         map.insert(OpCode::Subtract, 5);
         map.insert(OpCode::Multiply, 6);
         map.insert(OpCode::Divide, 7);
+        map.insert(OpCode::Pop, 8);
+        map.insert(OpCode::GetLocal, 9);
+        map.insert(OpCode::GetGlobal, 10);
+        map.insert(OpCode::DefineGlobal, 11);
+        map.insert(OpCode::Call, 12);
+        map.insert(OpCode::EndScope, 13);
+        map.insert(OpCode::Construct, 14);
+        map.insert(OpCode::GetField, 15);
+        map.insert(OpCode::Equal, 16);
+        map.insert(OpCode::NotEqual, 17);
+        map.insert(OpCode::Greater, 18);
+        map.insert(OpCode::GreaterEqual, 19);
+        map.insert(OpCode::Less, 20);
+        map.insert(OpCode::LessEqual, 21);
+        map.insert(OpCode::Tuple, 22);
+        map.insert(OpCode::Record, 23);
+        map.insert(OpCode::Destructure, 24);
+        map.insert(OpCode::Not, 25);
+        map.insert(OpCode::Jump, 26);
+        map.insert(OpCode::JumpIfFalse, 27);
+        map.insert(OpCode::CheckBool, 28);
+        map.insert(OpCode::Modulo, 29);
+        map.insert(OpCode::ToInt, 30);
+        map.insert(OpCode::ToFloat, 31);
+        map.insert(OpCode::ToString, 32);
+        map.insert(OpCode::Interpolate, 33);
+        map.insert(OpCode::Is, 34);
+        map.insert(OpCode::Print, 35);
+        map.insert(OpCode::ReadLine, 36);
+        map.insert(OpCode::Assert, 37);
         map.insert(OpCode::Invalid(254), 254);
         map.insert(OpCode::UnexpectedEndOfChunk, 255);
 
         map.iter().for_each(|(code, byte)| {
-            let chunk = Chunk::new().write_chunk(code, 1);
+            let chunk = Chunk::new().write_chunk(code, Span::on_line(1));
             assert_eq!(*byte, chunk.get_byte(0).unwrap());
             assert_eq!(*code, OpCode::from(*byte));
         });
@@ -152,7 +396,7 @@ mod tests {
     #[test]
     fn test_constant() {
         let chunk = Chunk::new();
-        let chunk = chunk.write_constant(Value::float(1.2), 1);
+        let chunk = chunk.write_constant(Value::float(1.2), Span::on_line(1));
 
         assert_eq!(OpCode::Constant.to_byte(), chunk.get_byte(0).unwrap());
         assert!(Value::compare_values(
@@ -166,8 +410,8 @@ mod tests {
     #[test]
     fn test_long_constant() {
         let chunk = Chunk::new();
-        let chunk = write_constants(chunk, u8::max_value() as usize + 1);
-        let chunk = chunk.write_constant(Value::float(0f64), 1);
+        let chunk = write_constants(chunk, u8::MAX as usize + 1);
+        let chunk = chunk.write_constant(Value::float(0f64), Span::on_line(1));
 
         assert_eq!(OpCode::ConstantLong.to_byte(), chunk.get_byte(512).unwrap());
         assert!(Value::compare_values(
@@ -181,12 +425,27 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_spans() {
+        let chunk = Chunk::new()
+            .write_constant(Value::float(1.0), Span::on_line(1))
+            .write_chunk(&OpCode::Negate, Span::on_line(2))
+            .write_chunk(&OpCode::Return, Span::on_line(2));
+
+        // The operand of a constant shares the span of its instruction
+        assert_eq!(Some(Span::on_line(1)), chunk.get_span(1));
+        assert_eq!(Some(2), chunk.get_line(2));
+        assert_eq!(Some(2), chunk.get_line(3));
+        assert_eq!(None, chunk.get_span(4));
+    }
+
     // A recursive function that writes 'fill' number of constants to a chunk
     fn write_constants(chunk: Chunk, fill: usize) -> Chunk {
         if fill == 0 {
             chunk
         } else {
-            write_constants(chunk.write_constant(Value::float(fill as f64), 1), fill - 1)
+            let chunk = chunk.write_constant(Value::float(fill as f64), Span::on_line(1));
+            write_constants(chunk, fill - 1)
         }
     }
 }
@@ -1,6 +1,2850 @@
-use crate::chunk::Chunk;
-use crate::scanner::Scanner;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::mem;
+use std::rc::Rc;
 
-pub fn compile(_scanner: Scanner) -> Chunk {
-    Chunk::new()
+use crate::analysis;
+use crate::ast::StructMember;
+use crate::ast::Variant;
+use crate::ast::{self, Block, ConstDef, Constructor, Expr, FieldInit, FunctionDef, Item};
+use crate::ast::{EnumDecl, EnumMember, Literal, Pattern, Program, Stmt, StringPart, StructDecl};
+use crate::chunk::{Chunk, OpCode};
+use crate::scanner::{Position, Scanner, Span, Token, TokenType};
+use crate::value::{self, Clause, Function, Interner, Template, Value};
+
+/// The largest number of constants a single chunk can address
+/// (`OpCode::ConstantLong` takes a two byte operand)
+const MAX_CONSTANTS: usize = u16::MAX as usize + 1;
+
+/// The largest number of values that a two byte operand can address
+const MAX_SHORT: usize = u16::MAX as usize + 1;
+
+/// `OpCode::GetLocal` takes a one byte operand
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+
+/// `OpCode::Call` takes a one byte operand
+const MAX_ARGUMENTS: usize = u8::MAX as usize;
+
+/// A function that is built into the language, whose calls
+/// are compiled into the instructions that run it
+struct Builtin {
+    name: &'static str,
+    // What the builtin is called in errors about its arguments
+    description: &'static str,
+    arity: usize,
+    // Whether the builtin has side effects, so that guards can't call it
+    impure: bool,
+    // Run after the arguments are on the stack
+    op_codes: &'static [OpCode],
+}
+
+static BUILTINS: [Builtin; 4] = [
+    Builtin {
+        name: "Int",
+        description: "A conversion",
+        arity: 1,
+        impure: false,
+        op_codes: &[OpCode::ToInt],
+    },
+    Builtin {
+        name: "Float",
+        description: "A conversion",
+        arity: 1,
+        impure: false,
+        op_codes: &[OpCode::ToFloat],
+    },
+    Builtin {
+        name: "IO::println",
+        description: "'IO::println'",
+        arity: 1,
+        impure: true,
+        op_codes: &[OpCode::ToString, OpCode::Print],
+    },
+    Builtin {
+        name: "IO::read_line",
+        description: "'IO::read_line'",
+        arity: 0,
+        impure: true,
+        op_codes: &[OpCode::ReadLine],
+    },
+];
+
+/// Compiles the tokens produced by `scanner` into a chunk
+/// that evaluates a single expression and returns its value
+///
+/// # Examples
+/// ```
+/// use lucent_lang::compiler;
+/// use lucent_lang::scanner;
+/// use lucent_lang::value::Value;
+/// use lucent_lang::virtual_machine::{VMResult, VM};
+///
+/// let chunk = compiler::compile(scanner::build_scanner("(1 + 2) * 3")).unwrap();
+///
+/// match VM::new().interpret(&chunk) {
+///     VMResult::Okay(value) => assert_eq!(Value::int(9), value),
+///     _ => panic!("expression failed to run"),
+/// }
+/// ```
+pub fn compile(scanner: Scanner) -> Result<Chunk, Vec<CompileError>> {
+    let expression = ast::parse_expression(scanner)?;
+
+    let mut compiler = Compiler::new();
+    compiler.expression(&expression);
+    compiler.emit_op(&OpCode::Return, expression.span());
+    compiler.finish()
+}
+
+/// Compiles a whole program into a chunk that defines every
+/// item in the program, then calls `main` and returns its value
+///
+/// # Examples
+/// ```
+/// use lucent_lang::compiler;
+/// use lucent_lang::scanner;
+/// use lucent_lang::value::Value;
+/// use lucent_lang::virtual_machine::{VMResult, VM};
+///
+/// let code = "fn main() { double(2) } fn double(x) { x * 2 }";
+/// let chunk = compiler::compile_program(scanner::build_scanner(code)).unwrap();
+///
+/// match VM::new().interpret(&chunk) {
+///     VMResult::Okay(value) => assert_eq!(Value::int(4), value),
+///     _ => panic!("program failed to run"),
+/// }
+/// ```
+pub fn compile_program(scanner: Scanner) -> Result<Chunk, Vec<CompileError>> {
+    compile_program_with_warnings(scanner).0
+}
+
+/// Compiles a whole program like `compile_program`, along with
+/// warnings about code that compiles but is probably wrong
+///
+/// # Examples
+/// ```
+/// use lucent_lang::compiler;
+/// use lucent_lang::scanner;
+///
+/// let code = "fn f(_) { 1 } fn f(0) { 2 }";
+/// let (chunk, warnings) = compiler::compile_program_with_warnings(scanner::build_scanner(code));
+///
+/// assert!(chunk.is_ok());
+/// assert_eq!(1, warnings.len());
+/// ```
+pub fn compile_program_with_warnings(
+    scanner: Scanner,
+) -> (Result<Chunk, Vec<CompileError>>, Vec<Warning>) {
+    let program = match ast::parse(scanner) {
+        Ok(program) => program,
+        Err(errors) => return (Err(errors), vec![]),
+    };
+
+    let mut compiler = Compiler::new();
+    compiler.program(&program);
+    let warnings = mem::take(&mut compiler.warnings);
+    (compiler.finish(), warnings)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    message: String,
+    lexeme: String,
+    span: Span,
+}
+
+impl CompileError {
+    pub(crate) fn at(token: &Token, message: &str) -> Self {
+        CompileError {
+            message: message.to_string(),
+            lexeme: token.lexeme(),
+            span: token.span(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    pub fn lexeme(&self) -> String {
+        self.lexeme.clone()
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn position(&self) -> Position {
+        self.span.start()
+    }
+
+    pub fn line(&self) -> u32 {
+        self.span.start().line()
+    }
+
+    pub fn column(&self) -> u32 {
+        self.span.start().column()
+    }
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.lexeme.is_empty() {
+            write!(
+                f,
+                "[line {}:{}] Error at end: {}",
+                self.line(),
+                self.column(),
+                self.message
+            )
+        } else {
+            write!(
+                f,
+                "[line {}:{}] Error at '{}': {}",
+                self.line(),
+                self.column(),
+                self.lexeme,
+                self.message
+            )
+        }
+    }
+}
+
+/// A problem in a program that doesn't stop it from compiling
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    message: String,
+    span: Span,
+}
+
+impl Warning {
+    pub(crate) fn at(token: &Token, message: &str) -> Self {
+        Warning {
+            message: message.to_string(),
+            span: token.span(),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn line(&self) -> u32 {
+        self.span.start().line()
+    }
+
+    pub fn column(&self) -> u32 {
+        self.span.start().column()
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}:{}] Warning: {}",
+            self.line(),
+            self.column(),
+            self.message
+        )
+    }
+}
+
+/// A function, constructor or constant that is defined
+/// in the program, along with the global it is stored in
+enum Definition<'a> {
+    /// Every clause of a function, in the order they were defined
+    Function {
+        name: String,
+        clauses: Vec<&'a FunctionDef>,
+    },
+    /// Every constructor of a struct, which are the clauses of one function
+    Constructor {
+        template: Rc<Template>,
+        constructors: Vec<&'a Constructor>,
+    },
+    Variant {
+        template: Rc<Template>,
+        variant: &'a Variant,
+    },
+    Const {
+        name: String,
+        definition: &'a ConstDef,
+    },
+}
+
+/// A function that a call might run, which is recorded
+/// to find out which functions have side effects
+#[derive(PartialEq, Eq, Hash)]
+enum Callee {
+    Function(String),
+    /// Any method with the name, since the type that it's called on isn't known
+    Method(String),
+}
+
+impl Callee {
+    /// Whether the callee is one of the functions in `functions`
+    fn is_in(&self, functions: &HashSet<String>) -> bool {
+        match self {
+            Callee::Function(name) => functions.contains(name),
+            Callee::Method(name) => {
+                let method = format!("::{}", name);
+                functions.iter().any(|function| function.ends_with(&method))
+            }
+        }
+    }
+}
+
+struct Local {
+    name: String,
+    // The stack slot of the local, relative
+    // to the first argument of the function
+    slot: usize,
+}
+
+struct Compiler {
+    // The global slot of every function, constructor and constant
+    globals: HashMap<String, usize>,
+    // The templates of structs, by name
+    templates: HashMap<String, Rc<Template>>,
+    // The variants of each enum, in the order they were declared
+    enums: analysis::Enums,
+    // The templates of enum variants, by their qualified name
+    variants: HashMap<String, Rc<Template>>,
+    // The names of traits, which are types without any values
+    traits: HashSet<String>,
+    // The names of the functions that are declared impure
+    impure: HashSet<String>,
+    // The text of string literals and field names
+    strings: Interner,
+    chunk: Chunk,
+    // The local variables of the function being compiled, innermost last
+    locals: Vec<Local>,
+    // The number of values that the code compiled so far
+    // leaves on the stack above the function's arguments
+    depth: usize,
+    // Whether a guard is being compiled, which can't have side effects
+    in_guard: bool,
+    // The function whose clauses are being compiled
+    function: Option<String>,
+    // What the body of each function calls, including builtins
+    calls: HashMap<String, HashSet<Callee>>,
+    // What guards call, which is checked once every function has been compiled
+    guard_calls: Vec<(Callee, Token)>,
+    errors: Vec<CompileError>,
+    warnings: Vec<Warning>,
+}
+
+impl Compiler {
+    fn new() -> Self {
+        Compiler {
+            globals: HashMap::new(),
+            templates: HashMap::new(),
+            enums: HashMap::new(),
+            variants: HashMap::new(),
+            traits: HashSet::new(),
+            impure: HashSet::new(),
+            strings: Interner::new(),
+            chunk: Chunk::new(),
+            locals: vec![],
+            depth: 0,
+            in_guard: false,
+            function: None,
+            calls: HashMap::new(),
+            guard_calls: vec![],
+            errors: vec![],
+            warnings: vec![],
+        }
+    }
+
+    fn finish(self) -> Result<Chunk, Vec<CompileError>> {
+        if self.errors.is_empty() {
+            Ok(self.chunk)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    // Item compiling functions
+    fn program(&mut self, program: &Program) {
+        let definitions = self.declare(program);
+
+        // Functions are defined before any constants so
+        // that constants can call any function
+        let (functions, constants): (Vec<_>, Vec<_>) = definitions
+            .iter()
+            .partition(|definition| !matches!(definition, Definition::Const { .. }));
+
+        for definition in functions.into_iter().chain(constants) {
+            self.definition(definition);
+        }
+        self.function = None;
+        self.check_guards();
+
+        // A program without a `main` function evaluates to unit
+        let main = program.items.iter().find_map(|item| match item {
+            Item::Function(function) if function.name.lexeme() == "main" => Some(function),
+            _ => None,
+        });
+        match main {
+            Some(main) => {
+                let span = main.name.span();
+                self.emit_short(&OpCode::GetGlobal, self.globals["main"], span);
+                self.emit_operands(&OpCode::Call, &[0], span);
+                self.emit_op(&OpCode::Return, span);
+            }
+            None => {
+                let start = Position::new(0, 1, 1, 1);
+                let span = Span::new(Default::default(), start, start);
+                self.chunk = mem::take(&mut self.chunk)
+                    .write_constant(Value::unit(), span)
+                    .write_chunk(&OpCode::Return, span);
+            }
+        }
+    }
+
+    /// Gives every item in the program a global slot, so that
+    /// items can refer to items that are defined after them
+    fn declare<'a>(&mut self, program: &'a Program) -> Vec<Definition<'a>> {
+        let mut definitions = vec![];
+        let mut structs = vec![];
+
+        for item in program.items.iter() {
+            match item {
+                Item::Struct(declaration) => {
+                    if let Some(template) = self.declare_struct(declaration, &mut definitions) {
+                        structs.push((template, declaration));
+                    }
+                }
+                Item::Enum(declaration) => self.declare_enum(declaration, &mut definitions),
+                Item::Trait(declaration) => {
+                    if self.declare_type(&declaration.name) {
+                        self.traits.insert(declaration.name.lexeme());
+                    }
+                }
+                Item::Function(function) => {
+                    self.declare_function(function.name.lexeme(), function, &mut definitions);
+                }
+                Item::Const(definition) => {
+                    if let Some(name) = self.binding_name(&definition.pattern) {
+                        if self.declare_global(name, &name.lexeme()).is_some() {
+                            definitions.push(Definition::Const {
+                                name: name.lexeme(),
+                                definition,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        self.declare_field_types(&structs);
+        definitions
+    }
+
+    /// Declares a struct and the members of its define block,
+    /// returning its template if it is a new type
+    fn declare_struct<'a>(
+        &mut self,
+        declaration: &'a StructDecl,
+        definitions: &mut Vec<Definition<'a>>,
+    ) -> Option<Rc<Template>> {
+        let type_name = declaration.name.lexeme();
+        if !self.declare_type(&declaration.name) {
+            return None;
+        }
+
+        let mut fields: Vec<String> = vec![];
+        for field in declaration.fields.iter() {
+            let name = match field {
+                Pattern::Binding { name, .. } => Some(name),
+                _ => {
+                    self.error_at(&pattern_token(field), "Expect a field name.");
+                    None
+                }
+            };
+            match name {
+                Some(name) if fields.contains(&name.lexeme()) => {
+                    self.error_at(name, "A struct can't have two fields with the same name.")
+                }
+                Some(name) => fields.push(name.lexeme()),
+                None => (),
+            }
+        }
+
+        let members = declaration
+            .definition
+            .as_ref()
+            .map_or(&[][..], |definition| &definition.members[..]);
+
+        let mut methods = vec![];
+        let mut constructors = vec![];
+        for member in members.iter() {
+            match member {
+                StructMember::Function(function) => {
+                    let name = format!("{}::{}", type_name, function.name.lexeme());
+                    if let Some(slot) = self.declare_function(name, function, definitions) {
+                        methods.push((function.name.lexeme(), slot));
+                    }
+                }
+                StructMember::Const(definition) => {
+                    if let Some(binding) = self.binding_name(&definition.pattern) {
+                        let name = format!("{}::{}", type_name, binding.lexeme());
+                        if self.declare_global(binding, &name).is_some() {
+                            definitions.push(Definition::Const { name, definition });
+                        }
+                    }
+                }
+                // Only the first constructor needs a global,
+                // since the others are clauses of the same function
+                StructMember::Constructor(constructor) => {
+                    if !constructors.is_empty()
+                        || self.declare_global(&constructor.name, &type_name).is_some()
+                    {
+                        constructors.push(constructor);
+                    }
+                }
+            }
+        }
+
+        let template = Rc::new(Template::new(&type_name, fields, methods));
+        self.templates.insert(type_name, Rc::clone(&template));
+        if !constructors.is_empty() {
+            definitions.push(Definition::Constructor {
+                template: Rc::clone(&template),
+                constructors,
+            });
+        }
+        Some(template)
+    }
+
+    /// Compiles the types of the fields of every struct, which is done
+    /// once every type is declared so that they can refer to each other
+    fn declare_field_types(&mut self, structs: &[(Rc<Template>, &StructDecl)]) {
+        let mut types = HashMap::new();
+        for (template, declaration) in structs.iter() {
+            let compiled: Vec<(value::Pattern, String)> = declaration
+                .fields
+                .iter()
+                .map(|field| match field {
+                    Pattern::Binding {
+                        pattern: Some(pattern),
+                        ..
+                    } => {
+                        let compiled = self
+                            .test_pattern(pattern, "The type of a field")
+                            .unwrap_or(value::Pattern::Wildcard);
+                        (compiled, pattern.to_string())
+                    }
+                    _ => (value::Pattern::Wildcard, "_".to_string()),
+                })
+                .collect();
+            types.insert(template.name().to_string(), compiled);
+        }
+
+        // A struct that can only be built from an instance of itself can never be
+        // built, and giving it its types would make its template refer to itself
+        for (template, declaration) in structs.iter() {
+            let name = template.name();
+            let mut visited = HashSet::new();
+            if types[name]
+                .iter()
+                .any(|(pattern, _)| needs_struct(pattern, name, &types, &mut visited))
+            {
+                let message = format!(
+                    "'{}' can never be built, since its fields need a '{}'.",
+                    name, name
+                );
+                self.error_at(&declaration.name, &message);
+            } else {
+                template.set_types(types[name].clone());
+            }
+        }
+    }
+
+    fn declare_enum<'a>(
+        &mut self,
+        declaration: &'a EnumDecl,
+        definitions: &mut Vec<Definition<'a>>,
+    ) {
+        let enum_name = declaration.name.lexeme();
+        if !self.declare_type(&declaration.name) {
+            return;
+        }
+        self.enums.insert(enum_name.clone(), vec![]);
+
+        let members = declaration
+            .definition
+            .as_ref()
+            .map_or(&[][..], |definition| &definition.members[..]);
+
+        // Every variant shares the methods of the enum
+        let mut methods = vec![];
+        for member in members.iter() {
+            match member {
+                EnumMember::Function(function) => {
+                    let name = format!("{}::{}", enum_name, function.name.lexeme());
+                    if let Some(slot) = self.declare_function(name, function, definitions) {
+                        methods.push((function.name.lexeme(), slot));
+                    }
+                }
+                EnumMember::Const(definition) => {
+                    if let Some(binding) = self.binding_name(&definition.pattern) {
+                        let name = format!("{}::{}", enum_name, binding.lexeme());
+                        if self.declare_global(binding, &name).is_some() {
+                            definitions.push(Definition::Const { name, definition });
+                        }
+                    }
+                }
+            }
+        }
+
+        for variant in declaration.variants.iter() {
+            let name = format!("{}::{}", enum_name, variant.name.lexeme());
+            if self.declare_global(&variant.name, &name).is_none() {
+                continue;
+            }
+
+            // Payloads are matched by position, so their
+            // fields are only named for constructing them
+            let fields = variant
+                .payload
+                .iter()
+                .enumerate()
+                .map(|(index, pattern)| match pattern {
+                    Pattern::Binding { name, .. } => name.lexeme(),
+                    _ => index.to_string(),
+                })
+                .collect();
+            let template = Rc::new(Template::variant(
+                &enum_name,
+                &variant.name.lexeme(),
+                fields,
+                methods.clone(),
+            ));
+            self.variants.insert(name, Rc::clone(&template));
+            if let Some(variants) = self.enums.get_mut(&enum_name) {
+                variants.push(Rc::clone(&template));
+            }
+            definitions.push(Definition::Variant { template, variant });
+        }
+    }
+
+    /// Declares a function, or adds a clause to the function
+    /// if one with the same name has already been declared
+    ///
+    /// Returns the global slot of the function
+    /// the first time that it is declared.
+    fn declare_function<'a>(
+        &mut self,
+        name: String,
+        function: &'a FunctionDef,
+        definitions: &mut Vec<Definition<'a>>,
+    ) -> Option<usize> {
+        if function.impure {
+            self.impure.insert(name.clone());
+        }
+
+        let existing = definitions
+            .iter_mut()
+            .find_map(|definition| match definition {
+                Definition::Function {
+                    name: existing,
+                    clauses,
+                } if *existing == name => Some(clauses),
+                _ => None,
+            });
+        if let Some(clauses) = existing {
+            clauses.push(function);
+            return None;
+        }
+
+        let slot = self.declare_global(&function.name, &name)?;
+        definitions.push(Definition::Function {
+            name,
+            clauses: vec![function],
+        });
+        Some(slot)
+    }
+
+    /// Reports an error if a struct, enum or trait called `name` already exists
+    fn declare_type(&mut self, name: &Token) -> bool {
+        let type_name = name.lexeme();
+        if self.templates.contains_key(&type_name)
+            || self.enums.contains_key(&type_name)
+            || self.traits.contains(&type_name)
+        {
+            let message = format!("The type '{}' is already defined.", type_name);
+            self.error_at(name, &message);
+            return false;
+        }
+        true
+    }
+
+    /// Gives `name` a global slot, reporting an error
+    /// at `token` if it already has one
+    fn declare_global(&mut self, token: &Token, name: &str) -> Option<usize> {
+        if self.globals.contains_key(name) {
+            let message = format!("'{}' is already defined.", name);
+            self.error_at(token, &message);
+            return None;
+        }
+        if self.globals.len() >= MAX_SHORT {
+            self.error_at(token, "Too many functions and constants in one program.");
+            return None;
+        }
+
+        let slot = self.globals.len();
+        self.globals.insert(name.to_string(), slot);
+        Some(slot)
+    }
+
+    fn definition(&mut self, definition: &Definition) {
+        // Calls are recorded against the function that makes them
+        self.function = match definition {
+            Definition::Function { name, .. } => Some(name.clone()),
+            Definition::Constructor { template, .. } => Some(template.name().to_string()),
+            _ => None,
+        };
+
+        match definition {
+            Definition::Function { name, clauses } => {
+                let arity = clauses[0].params.len();
+                let compiled = clauses
+                    .iter()
+                    .map(|function| {
+                        if function.params.len() != arity {
+                            let message = format!(
+                                "Every clause of '{}' needs {} parameters.",
+                                function.name.lexeme(),
+                                arity
+                            );
+                            self.error_at(&function.name, &message);
+                        }
+
+                        let body = &function.body;
+                        let signature = signature(&function.name.lexeme(), &function.params);
+                        self.clause(
+                            &signature,
+                            &function.params,
+                            function.guard.as_ref(),
+                            body.span,
+                            |compiler| compiler.block(body),
+                        )
+                    })
+                    .collect::<Vec<Clause>>();
+                if clauses
+                    .iter()
+                    .all(|function| function.params.len() == arity)
+                {
+                    self.check_clauses(clauses, &compiled);
+                }
+                let function = Function::with_clauses(name, arity, compiled);
+                self.define_global(name, Value::function(function), &clauses[0].name);
+            }
+            Definition::Constructor {
+                template,
+                constructors,
+            } => {
+                let arity = constructors[0].params.len();
+                let clauses = constructors
+                    .iter()
+                    .map(|constructor| {
+                        if constructor.params.len() != arity {
+                            let message = format!(
+                                "Every constructor of '{}' needs {} parameters.",
+                                template.name(),
+                                arity
+                            );
+                            self.error_at(&constructor.name, &message);
+                        }
+                        self.constructor(template, constructor)
+                    })
+                    .collect();
+                let function = Function::with_clauses(template.name(), arity, clauses);
+                self.define_global(
+                    template.name(),
+                    Value::function(function),
+                    &constructors[0].name,
+                );
+            }
+            Definition::Variant { template, variant } if variant.payload.is_empty() => {
+                let slot = self.globals[&template.qualified_name()];
+                self.emit_construct(template, variant.name.span());
+                self.emit_short(&OpCode::DefineGlobal, slot, variant.name.span());
+            }
+            Definition::Variant { template, variant } => {
+                let name = template.qualified_name();
+                let span = variant.name.span();
+                let signature = signature(&variant.name.lexeme(), &variant.payload);
+                let function =
+                    self.function(&name, &signature, &variant.payload, span, |compiler| {
+                        for slot in 0..variant.payload.len() {
+                            compiler.emit_operands(&OpCode::GetLocal, &[slot as u8], span);
+                        }
+                        compiler.emit_construct(template, span);
+                    });
+                self.define_global(&name, Value::function(function), &variant.name);
+            }
+            Definition::Const { name, definition } => {
+                self.expression(&definition.value);
+                self.depth -= 1;
+                let slot = self.globals[name];
+                self.emit_short(&OpCode::DefineGlobal, slot, definition.span());
+            }
+        }
+    }
+
+    /// Warns about clauses that can never run and about arguments
+    /// of enum and struct types that no clause accepts
+    fn check_clauses(&mut self, clauses: &[&FunctionDef], compiled: &[Clause]) {
+        let patterns: Vec<Vec<value::Pattern>> = compiled
+            .iter()
+            .map(|clause| clause.patterns().to_vec())
+            .collect();
+        let guarded: Vec<usize> = compiled
+            .iter()
+            .enumerate()
+            .filter(|(_, clause)| clause.guard().is_some())
+            .map(|(index, _)| index)
+            .collect();
+        let analysis = analysis::analyze(&patterns, &guarded, &self.enums);
+
+        for index in analysis.unreachable {
+            let message = format!(
+                "The clause {} can never run, since earlier clauses match everything it does.",
+                compiled[index].signature()
+            );
+            self.warnings
+                .push(Warning::at(&clauses[index].name, &message));
+        }
+
+        if !analysis.missing.is_empty() {
+            let name = clauses[0].name.lexeme();
+            let missing: Vec<String> = analysis
+                .missing
+                .iter()
+                .map(|arguments| format!("{}({})", name, arguments.join(", ")))
+                .collect();
+            let message = format!("No clause of '{}' matches {}.", name, missing.join(" or "));
+            self.warnings.push(Warning::at(&clauses[0].name, &message));
+        }
+    }
+
+    fn define_global(&mut self, name: &str, value: Value, token: &Token) {
+        self.emit_constant(value, token);
+        let span = token.span();
+        let slot = self.globals[name];
+        self.emit_short(&OpCode::DefineGlobal, slot, span);
+    }
+
+    /// Compiles a function with a single clause
+    fn function<F>(
+        &mut self,
+        name: &str,
+        signature: &str,
+        params: &[Pattern],
+        span: Span,
+        body: F,
+    ) -> Function
+    where
+        F: FnOnce(&mut Compiler),
+    {
+        let clause = self.clause(signature, params, None, span, body);
+        Function::with_clauses(name, params.len(), vec![clause])
+    }
+
+    /// Compiles the body of a clause into its own chunk, and its guard
+    /// into another, with its parameters as their first local variables
+    fn clause<F>(
+        &mut self,
+        signature: &str,
+        params: &[Pattern],
+        guard: Option<&Expr>,
+        span: Span,
+        body: F,
+    ) -> Clause
+    where
+        F: FnOnce(&mut Compiler),
+    {
+        let chunk = mem::take(&mut self.chunk);
+        let locals = mem::take(&mut self.locals);
+        let depth = mem::replace(&mut self.depth, params.len());
+
+        // Parameters that are names refer to the arguments themselves,
+        // while names inside of patterns are bound after the arguments
+        let mut patterns = vec![];
+        for (slot, param) in params.iter().enumerate() {
+            let pattern = match param {
+                Pattern::Binding { name, pattern } => {
+                    self.locals.push(Local {
+                        name: name.lexeme(),
+                        slot,
+                    });
+                    match pattern {
+                        Some(pattern) => self.pattern(pattern),
+                        None => value::Pattern::Wildcard,
+                    }
+                }
+                _ => self.pattern(param),
+            };
+            patterns.push(pattern);
+        }
+        if params.len() > MAX_ARGUMENTS {
+            self.error_at(
+                &pattern_token(&params[MAX_ARGUMENTS]),
+                "Can't have more than 255 parameters.",
+            );
+        }
+
+        let guard = guard.map(|guard| {
+            self.in_guard = true;
+            self.expression(guard);
+            self.in_guard = false;
+            self.emit_op(&OpCode::Return, guard.span());
+            self.depth -= 1;
+            mem::take(&mut self.chunk)
+        });
+
+        body(self);
+        self.emit_op(&OpCode::Return, span);
+
+        let chunk = mem::replace(&mut self.chunk, chunk);
+        self.locals = locals;
+        self.depth = depth;
+        match guard {
+            Some(guard) => Clause::guarded(signature, patterns, guard, chunk),
+            None => Clause::new(signature, patterns, chunk),
+        }
+    }
+
+    /// Compiles a constructor into a clause, which builds the struct
+    /// directly from its parameters if it doesn't have a body
+    fn constructor(&mut self, template: &Rc<Template>, constructor: &Constructor) -> Clause {
+        let span = constructor
+            .body
+            .as_ref()
+            .map_or(constructor.name.span(), |body| body.span);
+        self.clause(
+            &signature(template.name(), &constructor.params),
+            &constructor.params,
+            None,
+            span,
+            |compiler| match &constructor.body {
+                Some(body) => compiler.block(body),
+                None => {
+                    for field in template.fields() {
+                        match compiler.resolve_local(field) {
+                            Some(slot) => compiler.emit_operands(
+                                &OpCode::GetLocal,
+                                &[slot as u8],
+                                constructor.name.span(),
+                            ),
+                            None => {
+                                let message =
+                                    format!("Expect a parameter for the field '{}'.", field);
+                                compiler.error_at(&constructor.name, &message);
+                            }
+                        }
+                    }
+                    compiler.emit_construct(template, constructor.name.span());
+                }
+            },
+        )
+    }
+
+    fn block(&mut self, block: &Block) {
+        let locals = self.locals.len();
+        let depth = self.depth;
+
+        for statement in block.statements.iter() {
+            match statement {
+                Stmt::Let(definition) => self.let_statement(definition),
+                Stmt::Assert { keyword, condition } => {
+                    self.assertion(keyword, condition, statement.span())
+                }
+                Stmt::Expression(expression) => {
+                    self.expression(expression);
+                    self.emit_op(&OpCode::Pop, expression.span());
+                    self.depth -= 1;
+                }
+            }
+        }
+
+        match &block.value {
+            Some(value) => self.expression(value),
+            None => {
+                self.emit_constant(Value::unit(), &block.brace);
+                self.depth += 1;
+            }
+        }
+
+        // Only the value of the block is left on the stack
+        let scope = self.depth - depth - 1;
+        if scope > 0 {
+            self.emit_operands(&OpCode::EndScope, &[scope as u8], block.span);
+        }
+        self.locals.truncate(locals);
+        self.depth = depth + 1;
+    }
+
+    /// Compiles an `assert`, which stops the program with an
+    /// error if its condition is false when it is run
+    fn assertion(&mut self, keyword: &Token, condition: &Expr, span: Span) {
+        self.expression(condition);
+        match known_type(condition) {
+            Some(known) if known != "Bool" => {
+                let message = format!("An assertion must be Bool, but it is {}.", known);
+                self.error_at(keyword, &message);
+            }
+            _ => self.emit_op(&OpCode::Assert, span),
+        }
+        self.depth -= 1;
+    }
+
+    /// Compiles a `let`, which leaves its value on the stack
+    /// followed by the values that its pattern binds
+    fn let_statement(&mut self, definition: &ConstDef) {
+        self.expression(&definition.value);
+
+        match &definition.pattern {
+            Pattern::Binding {
+                name,
+                pattern: None,
+            } => self.add_local(name),
+            pattern => {
+                let compiled = self.pattern(pattern);
+                if let Some(refutable) = self.refutable(pattern, &compiled) {
+                    let message =
+                        format!("A 'let' can't use the refutable pattern '{}'.", refutable);
+                    self.error_at(&pattern_token(refutable), &message);
+                }
+                let index = self.chunk.add_pattern(compiled);
+                self.emit_short(&OpCode::Destructure, index, pattern.span());
+            }
+        }
+    }
+
+    // Pattern compiling functions
+    /// Compiles a pattern that is matched when a function is called,
+    /// adding a local variable for each name that it binds
+    fn pattern(&mut self, pattern: &Pattern) -> value::Pattern {
+        match pattern {
+            Pattern::Wildcard(_) => value::Pattern::Wildcard,
+            Pattern::Binding { name, pattern } => {
+                self.depth += 1;
+                self.add_local(name);
+                let pattern = match pattern {
+                    Some(pattern) => self.pattern(pattern),
+                    None => value::Pattern::Wildcard,
+                };
+                value::Pattern::Bind(Box::new(pattern))
+            }
+            Pattern::Named {
+                path, arguments, ..
+            } => self.named_pattern(path, arguments.as_deref()),
+            Pattern::Literal { token, value } => match value {
+                Literal::Int(value) => value::Pattern::Literal(Value::int(*value)),
+                Literal::Float(value) => value::Pattern::Literal(Value::float(*value)),
+                Literal::Bool(value) => value::Pattern::Literal(Value::bool(*value)),
+                Literal::String(value) => {
+                    value::Pattern::Literal(Value::string(self.strings.intern(value)))
+                }
+                Literal::Unit => {
+                    self.unsupported(token);
+                    value::Pattern::Wildcard
+                }
+            },
+            Pattern::Tuple { elements, .. } => value::Pattern::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.pattern(element))
+                    .collect(),
+            ),
+            Pattern::Record { fields, open, .. } => {
+                let mut compiled: Vec<(String, value::Pattern)> = vec![];
+                for field in fields.iter() {
+                    let name = field.name.lexeme();
+                    if compiled.iter().any(|(other, _)| *other == name) {
+                        let message = format!("The field '{}' is matched more than once.", name);
+                        self.error_at(&field.name, &message);
+                    }
+
+                    // A field without a pattern is bound to a local with its name
+                    let pattern = match &field.pattern {
+                        Some(pattern) => self.pattern(pattern),
+                        None => {
+                            self.depth += 1;
+                            self.add_local(&field.name);
+                            value::Pattern::Bind(Box::new(value::Pattern::Wildcard))
+                        }
+                    };
+                    compiled.push((name, pattern));
+                }
+                value::Pattern::Record {
+                    fields: compiled,
+                    open: *open,
+                }
+            }
+        }
+    }
+
+    /// Compiles a pattern that matches a type, or one
+    /// that takes apart a struct or variant by position
+    fn named_pattern(&mut self, path: &[Token], arguments: Option<&[Pattern]>) -> value::Pattern {
+        let name = path_name(path);
+        let last = &path[path.len() - 1];
+
+        let template = match self.templates.get(&name).cloned() {
+            Some(template) => Some(template),
+            None => self.resolve_variant(&name, last),
+        };
+        let template = match template {
+            Some(template) => template,
+            None => {
+                let pattern = match name.as_str() {
+                    "Float" => value::Pattern::Float,
+                    "Int" => value::Pattern::Int,
+                    "Bool" => value::Pattern::Bool,
+                    "Unit" => value::Pattern::Unit,
+                    "String" => value::Pattern::String,
+                    _ if self.enums.contains_key(&name) => value::Pattern::Enum(name.clone()),
+                    _ if self.traits.contains(&name) => {
+                        let message = format!("The trait '{}' can't be used as a pattern.", name);
+                        self.error_at(last, &message);
+                        return value::Pattern::Wildcard;
+                    }
+                    _ => {
+                        let message = format!("Unknown type '{}'.", name);
+                        self.error_at(last, &message);
+                        return value::Pattern::Wildcard;
+                    }
+                };
+                if arguments.is_some() {
+                    let message = format!("'{}' doesn't have any fields to match.", name);
+                    self.error_at(last, &message);
+                }
+                return pattern;
+            }
+        };
+
+        let arguments = arguments.map(|arguments| {
+            if arguments.len() != template.fields().len() {
+                let message = format!(
+                    "'{}' has {} fields but the pattern matches {}.",
+                    template.qualified_name(),
+                    template.fields().len(),
+                    arguments.len()
+                );
+                self.error_at(last, &message);
+            }
+            arguments
+                .iter()
+                .map(|argument| self.pattern(argument))
+                .collect()
+        });
+        value::Pattern::Template(template, arguments)
+    }
+
+    /// Finds a part of `pattern` that only matches some of the values of its
+    /// type, such as a literal or a variant of an enum with other variants
+    fn refutable<'a>(
+        &self,
+        pattern: &'a Pattern,
+        compiled: &value::Pattern,
+    ) -> Option<&'a Pattern> {
+        let parts: Vec<(&Pattern, &value::Pattern)> = match (pattern, compiled) {
+            (_, value::Pattern::Literal(_)) => return Some(pattern),
+            (_, value::Pattern::Template(template, fields)) => {
+                let variants = template
+                    .enum_name()
+                    .and_then(|name| self.enums.get(name))
+                    .map_or(0, Vec::len);
+                if variants > 1 {
+                    return Some(pattern);
+                }
+                match (pattern, fields) {
+                    (
+                        Pattern::Named {
+                            arguments: Some(arguments),
+                            ..
+                        },
+                        Some(fields),
+                    ) => arguments.iter().zip(fields.iter()).collect(),
+                    _ => vec![],
+                }
+            }
+            (
+                Pattern::Binding {
+                    pattern: Some(pattern),
+                    ..
+                },
+                value::Pattern::Bind(compiled),
+            ) => vec![(&**pattern, &**compiled)],
+            (Pattern::Tuple { elements, .. }, value::Pattern::Tuple(compiled)) => {
+                elements.iter().zip(compiled.iter()).collect()
+            }
+            (
+                Pattern::Record { fields, .. },
+                value::Pattern::Record {
+                    fields: compiled, ..
+                },
+            ) => fields
+                .iter()
+                .zip(compiled.iter())
+                .filter_map(|(field, (_, compiled))| {
+                    field.pattern.as_ref().map(|pattern| (pattern, compiled))
+                })
+                .collect(),
+            _ => vec![],
+        };
+
+        parts
+            .into_iter()
+            .find_map(|(pattern, compiled)| self.refutable(pattern, compiled))
+    }
+
+    /// Finds the variant called `name`, which can be left unqualified
+    /// as long as only one enum has a variant with that name
+    fn resolve_variant(&mut self, name: &str, token: &Token) -> Option<Rc<Template>> {
+        if let Some(template) = self.variants.get(name) {
+            return Some(Rc::clone(template));
+        }
+
+        let mut candidates = self
+            .variants
+            .values()
+            .filter(|template| template.name() == name);
+        let template = candidates.next().cloned();
+        if candidates.next().is_some() {
+            let message = format!(
+                "More than one enum has a variant called '{}', so it needs the name of its enum.",
+                name
+            );
+            self.error_at(token, &message);
+        }
+        template
+    }
+
+    // Expression compiling functions
+    /// Compiles an expression, which leaves one more
+    /// value on the stack when it is run
+    fn expression(&mut self, expression: &Expr) {
+        let depth = self.depth;
+
+        match expression {
+            Expr::Literal { token, value } => self.literal(token, value),
+            Expr::Variable { path } => self.variable(path),
+            // Builtins are compiled into instructions, so they aren't values
+            Expr::Builtin { name, .. } => {
+                let message = match dollar_builtin(&name.lexeme()) {
+                    Some(_) => format!("The builtin '${}' can only be called.", name.lexeme()),
+                    None => format!("Unknown builtin '${}'.", name.lexeme()),
+                };
+                self.error_at(name, &message);
+            }
+            Expr::SelfValue(token) => match self.resolve_local("self") {
+                Some(slot) => self.emit_operands(&OpCode::GetLocal, &[slot as u8], token.span()),
+                None => self.error_at(token, "Can't use 'self' outside of a method."),
+            },
+            Expr::Grouping { expression, .. } => self.expression(expression),
+            Expr::Unary { operator, operand } => {
+                self.expression(operand);
+                match operator.token_type() {
+                    TokenType::Minus => self.emit_op(&OpCode::Negate, expression.span()),
+                    TokenType::Bang => self.emit_op(&OpCode::Not, expression.span()),
+                    _ => self.unsupported(operator),
+                }
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } if matches!(operator.token_type(), TokenType::And | TokenType::Or) => {
+                self.logical(left, operator, right)
+            }
+            Expr::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                self.expression(left);
+                self.expression(right);
+                let op_code = match operator.token_type() {
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::Percent => OpCode::Modulo,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    TokenType::BangEqual => OpCode::NotEqual,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    _ => return self.unsupported(operator),
+                };
+                self.emit_op(&op_code, expression.span());
+            }
+            Expr::Call {
+                callee,
+                paren,
+                arguments,
+                ..
+            } => {
+                if let Some(builtin) = self.builtin(callee) {
+                    self.call_builtin(builtin, paren, arguments, expression.span());
+                } else {
+                    match &**callee {
+                        Expr::Variable { path }
+                            if self.resolve_local(&path_name(path)).is_none() =>
+                        {
+                            let callee = Callee::Function(path_name(path));
+                            self.record_call(callee, &path[path.len() - 1]);
+                        }
+                        Expr::Field { name, .. } => {
+                            self.record_call(Callee::Method(name.lexeme()), name)
+                        }
+                        _ => (),
+                    }
+                    self.expression(callee);
+                    for argument in arguments.iter() {
+                        self.expression(argument);
+                    }
+                    if arguments.len() > MAX_ARGUMENTS {
+                        self.error_at(paren, "Can't have more than 255 arguments.");
+                    }
+                    let count = arguments.len() as u8;
+                    self.emit_operands(&OpCode::Call, &[count], expression.span());
+                }
+            }
+            Expr::Field { object, name } => {
+                self.expression(object);
+                let name = self.strings.intern(&name.lexeme());
+                let index = self.chunk.add_name(name);
+                self.emit_short(&OpCode::GetField, index, expression.span());
+            }
+            Expr::Struct { name, fields, span } => self.struct_literal(name, fields, *span),
+            Expr::Block(block) => self.block(block),
+            Expr::Tuple {
+                paren, elements, ..
+            } => {
+                for element in elements.iter() {
+                    self.expression(element);
+                }
+                if elements.len() > MAX_ARGUMENTS {
+                    self.error_at(paren, "Can't have more than 255 elements in a tuple.");
+                }
+                self.emit_operands(&OpCode::Tuple, &[elements.len() as u8], expression.span());
+            }
+            Expr::Record { fields, .. } => {
+                for (index, field) in fields.iter().enumerate() {
+                    let name = field.name.lexeme();
+                    if fields[..index]
+                        .iter()
+                        .any(|other| other.name.lexeme() == name)
+                    {
+                        let message = format!("The field '{}' is given more than once.", name);
+                        self.error_at(&field.name, &message);
+                    }
+                    self.expression(&field.value);
+                }
+                let names = fields.iter().map(|field| field.name.lexeme()).collect();
+                let index = self.chunk.add_record(names);
+                self.emit_short(&OpCode::Record, index, expression.span());
+            }
+            Expr::Interpolation { token, parts, span } => self.interpolation(token, parts, *span),
+            Expr::Is { value, pattern, .. } => {
+                self.expression(value);
+                self.is_test(pattern);
+            }
+            Expr::If {
+                keyword,
+                condition,
+                then_branch,
+                else_branch,
+            } => self.if_expression(keyword, condition, then_branch, else_branch),
+        }
+
+        self.depth = depth + 1;
+    }
+
+    /// Compiles a conditional, where the condition is popped by
+    /// whichever branch runs, and the branch leaves its value
+    fn if_expression(&mut self, keyword: &Token, condition: &Expr, then: &Expr, other: &Expr) {
+        let depth = self.depth;
+        let span = keyword.span();
+
+        self.expression(condition);
+        let else_jump = self.emit_jump(&OpCode::JumpIfFalse, span);
+        self.emit_op(&OpCode::Pop, span);
+        self.depth = depth;
+        self.expression(then);
+        let end_jump = self.emit_jump(&OpCode::Jump, span);
+
+        self.patch_jump(else_jump, keyword);
+        self.emit_op(&OpCode::Pop, span);
+        self.depth = depth;
+        self.expression(other);
+        self.patch_jump(end_jump, keyword);
+    }
+
+    /// Compiles `and` or `or`, which only runs its right operand
+    /// if the left one doesn't decide the result by itself
+    fn logical(&mut self, left: &Expr, operator: &Token, right: &Expr) {
+        let depth = self.depth;
+        let span = operator.span();
+
+        self.bool_operand(left, operator);
+        let end_jump = if operator.token_type() == TokenType::And {
+            self.emit_jump(&OpCode::JumpIfFalse, span)
+        } else {
+            let right_jump = self.emit_jump(&OpCode::JumpIfFalse, span);
+            let end_jump = self.emit_jump(&OpCode::Jump, span);
+            self.patch_jump(right_jump, operator);
+            end_jump
+        };
+        self.emit_op(&OpCode::Pop, span);
+        self.depth = depth;
+        self.bool_operand(right, operator);
+        self.patch_jump(end_jump, operator);
+    }
+
+    /// Compiles an operand that has to be a bool, which is checked when
+    /// the program is compiled if its type is known, or else when it runs
+    fn bool_operand(&mut self, operand: &Expr, operator: &Token) {
+        self.expression(operand);
+        match known_type(operand) {
+            Some(known) if known == "Bool" => (),
+            Some(known) => {
+                let message = format!(
+                    "The operands of '{}' must be Bool, but one is {}.",
+                    operator.lexeme(),
+                    known
+                );
+                self.error_at(operator, &message);
+            }
+            None => self.emit_op(&OpCode::CheckBool, operand.span()),
+        }
+    }
+
+    fn literal(&mut self, token: &Token, literal: &Literal) {
+        let value = match literal {
+            Literal::Int(value) => Value::int(*value),
+            Literal::Float(value) => Value::float(*value),
+            Literal::Bool(value) => Value::bool(*value),
+            Literal::Unit => Value::unit(),
+            Literal::String(value) => Value::string(self.strings.intern(value)),
+        };
+        self.emit_constant(value, token);
+    }
+
+    /// Joins the parts of an interpolated string, converting the
+    /// values of the expressions in it to strings
+    fn interpolation(&mut self, token: &Token, parts: &[StringPart], span: Span) {
+        if parts.len() > u8::MAX as usize {
+            self.error_at(token, "Too many parts in an interpolated string.");
+        }
+        for part in parts.iter() {
+            match part {
+                StringPart::Literal(text) => {
+                    let text = self.strings.intern(text);
+                    self.emit_constant(Value::string(text), token);
+                    self.depth += 1;
+                }
+                StringPart::Expression(expression) => {
+                    // Values with a `to_string` method are converted by calling it
+                    self.record_call(Callee::Method("to_string".to_string()), token);
+                    self.expression(expression);
+                    self.emit_op(&OpCode::ToString, expression.span());
+                }
+            }
+        }
+        self.emit_operands(&OpCode::Interpolate, &[parts.len() as u8], span);
+    }
+
+    /// Replaces the value on the stack with whether it matches `pattern`
+    fn is_test(&mut self, pattern: &Pattern) {
+        // The test is part of an expression, so it has nowhere to put local variables
+        if let Some(compiled) = self.test_pattern(pattern, "An 'is' pattern") {
+            let index = self.chunk.add_pattern(compiled);
+            self.emit_short(&OpCode::Is, index, pattern.span());
+        }
+    }
+
+    /// Compiles a pattern that only tests a value without binding any names,
+    /// reporting an error that starts with `kind` if it binds one
+    fn test_pattern(&mut self, pattern: &Pattern, kind: &str) -> Option<value::Pattern> {
+        if let Some(name) = pattern_binding(pattern) {
+            let message = format!("{} can't bind the name '{}'.", kind, name.lexeme());
+            self.error_at(&name, &message);
+            return None;
+        }
+
+        // The fields of a record pattern like `{x, y}` are compiled
+        // as bindings, which are dropped since nothing uses them
+        let locals = self.locals.len();
+        let depth = self.depth;
+        let compiled = self.pattern(pattern);
+        self.locals.truncate(locals);
+        self.depth = depth;
+        Some(compiled)
+    }
+
+    /// Records that the code being compiled calls `callee`, so that
+    /// guards can be checked once it's known which functions are impure
+    fn record_call(&mut self, callee: Callee, token: &Token) {
+        if self.in_guard {
+            self.guard_calls.push((callee, token.clone()));
+        } else if let Some(function) = &self.function {
+            self.calls
+                .entry(function.clone())
+                .or_default()
+                .insert(callee);
+        }
+    }
+
+    /// Reports an error for each call in a guard to a function that
+    /// has side effects, which it has if it is declared impure or if
+    /// it calls a builtin or another function that has them
+    fn check_guards(&mut self) {
+        let mut impure = self.impure.clone();
+        impure.extend(
+            BUILTINS
+                .iter()
+                .filter(|builtin| builtin.impure)
+                .map(|builtin| builtin.name.to_string()),
+        );
+        loop {
+            let found: Vec<String> = self
+                .calls
+                .iter()
+                .filter(|(function, callees)| {
+                    !impure.contains(*function)
+                        && callees.iter().any(|callee| callee.is_in(&impure))
+                })
+                .map(|(function, _)| function.clone())
+                .collect();
+            if found.is_empty() {
+                break;
+            }
+            impure.extend(found);
+        }
+
+        for (callee, token) in mem::take(&mut self.guard_calls) {
+            if callee.is_in(&impure) {
+                let message = match callee {
+                    Callee::Function(name) => {
+                        format!("A guard can't call the impure function '{}'.", name)
+                    }
+                    Callee::Method(name) => {
+                        format!("A guard can't call the impure method '{}'.", name)
+                    }
+                };
+                self.error_at(&token, &message);
+            }
+        }
+    }
+
+    /// The builtin that `callee` names, such as the conversion `Int`
+    /// or `IO::println`, if no variable or function has its name
+    ///
+    /// A builtin can also be named with a `$` and without its
+    /// module, like `$println`, whatever else has its name.
+    fn builtin(&self, callee: &Expr) -> Option<&'static Builtin> {
+        let name = match callee {
+            Expr::Variable { path } => path_name(path),
+            Expr::Builtin { name, .. } => return dollar_builtin(&name.lexeme()),
+            _ => return None,
+        };
+        if self.resolve_local(&name).is_some() || self.globals.contains_key(&name) {
+            return None;
+        }
+        BUILTINS.iter().find(|builtin| builtin.name == name)
+    }
+
+    /// Compiles a call like `Float(n)` or `IO::println(a)` into the instructions
+    /// that run the builtin on its arguments
+    fn call_builtin(&mut self, builtin: &Builtin, paren: &Token, arguments: &[Expr], span: Span) {
+        self.record_call(Callee::Function(builtin.name.to_string()), paren);
+        if arguments.len() != builtin.arity {
+            let message = format!(
+                "{} takes {} argument{} but got {}.",
+                builtin.description,
+                builtin.arity,
+                if builtin.arity == 1 { "" } else { "s" },
+                arguments.len()
+            );
+            self.error_at(paren, &message);
+            return;
+        }
+
+        for argument in arguments.iter() {
+            self.expression(argument);
+        }
+        for op_code in builtin.op_codes.iter() {
+            self.emit_op(op_code, span);
+        }
+    }
+
+    fn variable(&mut self, path: &[Token]) {
+        let name = path_name(path);
+        let last = &path[path.len() - 1];
+        let span = path[0].span().to(last.span());
+
+        if let Some(slot) = self.resolve_local(&name) {
+            self.emit_operands(&OpCode::GetLocal, &[slot as u8], span);
+        } else if let Some(slot) = self.globals.get(&name).cloned() {
+            self.emit_short(&OpCode::GetGlobal, slot, span);
+        } else if let Some(variant) = self.resolve_variant(&name, last) {
+            let slot = self.globals[&variant.qualified_name()];
+            self.emit_short(&OpCode::GetGlobal, slot, span);
+        } else {
+            let message = format!("Undefined variable '{}'.", name);
+            self.error_at(&path[path.len() - 1], &message);
+        }
+    }
+
+    /// Compiles a struct literal, whose fields are stored in
+    /// the order that they are declared in the struct
+    fn struct_literal(&mut self, name: &Token, fields: &[FieldInit], span: Span) {
+        let template = match self.templates.get(&name.lexeme()) {
+            Some(template) => Rc::clone(template),
+            None => {
+                let message = format!("Undefined struct '{}'.", name.lexeme());
+                return self.error_at(name, &message);
+            }
+        };
+
+        for (index, field) in fields.iter().enumerate() {
+            let field_name = field.name.lexeme();
+            if template.field_index(&field_name).is_none() {
+                let message = format!(
+                    "'{}' doesn't have a field called '{}'.",
+                    template.name(),
+                    field_name
+                );
+                self.error_at(&field.name, &message);
+            } else if fields[..index]
+                .iter()
+                .any(|other| other.name.lexeme() == field_name)
+            {
+                let message = format!("The field '{}' is given more than once.", field_name);
+                self.error_at(&field.name, &message);
+            }
+        }
+
+        for field_name in template.fields() {
+            if !fields
+                .iter()
+                .any(|field| field.name.lexeme() == *field_name)
+            {
+                let message = format!(
+                    "Missing the field '{}' of '{}'.",
+                    field_name,
+                    template.name()
+                );
+                self.error_at(name, &message);
+            }
+        }
+
+        // The fields are evaluated in the order they are written, so
+        // if that isn't the order of the struct, they are copied into it
+        let in_order = fields
+            .iter()
+            .map(|field| field.name.lexeme())
+            .eq(template.fields().iter().cloned());
+        let start = self.depth;
+        for field in fields.iter() {
+            self.expression(&field.value);
+        }
+        if !in_order {
+            if self.depth > MAX_LOCALS {
+                return self.error_at(name, "Too many local variables in function.");
+            }
+            for field_name in template.fields() {
+                if let Some(index) = fields
+                    .iter()
+                    .position(|field| field.name.lexeme() == *field_name)
+                {
+                    self.emit_operands(&OpCode::GetLocal, &[(start + index) as u8], span);
+                }
+            }
+        }
+
+        self.emit_construct(&template, span);
+        if !in_order {
+            self.emit_operands(&OpCode::EndScope, &[fields.len() as u8], span);
+        }
+    }
+
+    // Variable handling functions
+    fn add_local(&mut self, name: &Token) {
+        if self.depth > MAX_LOCALS {
+            return self.error_at(name, "Too many local variables in function.");
+        }
+        self.locals.push(Local {
+            name: name.lexeme(),
+            slot: self.depth - 1,
+        });
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<usize> {
+        self.locals
+            .iter()
+            .rev()
+            .find(|local| local.name == name)
+            .map(|local| local.slot)
+    }
+
+    /// The name bound by a pattern that can only bind a name
+    fn binding_name<'a>(&mut self, pattern: &'a Pattern) -> Option<&'a Token> {
+        match pattern {
+            Pattern::Binding {
+                name,
+                pattern: None,
+            } => Some(name),
+            _ => {
+                self.error_at(&pattern_token(pattern), "Expect a name.");
+                None
+            }
+        }
+    }
+
+    // Code emitting functions
+    fn emit_op(&mut self, op_code: &OpCode, span: Span) {
+        self.chunk = mem::take(&mut self.chunk).write_chunk(op_code, span);
+    }
+
+    fn emit_operands(&mut self, op_code: &OpCode, operands: &[u8], span: Span) {
+        self.chunk = mem::take(&mut self.chunk).write_operands(op_code, operands, span);
+    }
+
+    /// Emits an instruction with a two byte operand
+    fn emit_short(&mut self, op_code: &OpCode, operand: usize, span: Span) {
+        self.emit_operands(op_code, &[(operand >> 8) as u8, operand as u8], span);
+    }
+
+    /// Emits a jump with a placeholder offset, returning
+    /// the position of the offset so that it can be patched
+    fn emit_jump(&mut self, op_code: &OpCode, span: Span) -> usize {
+        self.emit_short(op_code, MAX_SHORT - 1, span);
+        self.chunk.get_size() - 2
+    }
+
+    /// Makes the jump with its offset at `offset` jump to the next instruction
+    fn patch_jump(&mut self, offset: usize, token: &Token) {
+        let jump = self.chunk.get_size() - offset - 2;
+        if jump >= MAX_SHORT {
+            self.error_at(token, "Too much code to jump over.");
+        }
+        self.chunk.patch_short(offset, jump);
+    }
+
+    fn emit_constant(&mut self, value: Value, token: &Token) {
+        self.chunk = mem::take(&mut self.chunk).write_constant(value, token.span());
+
+        if self.chunk.get_constants_size() > MAX_CONSTANTS {
+            self.error_at(token, "Too many constants in one chunk.");
+        }
+    }
+
+    fn emit_construct(&mut self, template: &Rc<Template>, span: Span) {
+        let index = self.chunk.add_template(Rc::clone(template));
+        self.emit_short(&OpCode::Construct, index, span);
+    }
+
+    // Error handling functions
+    fn error_at(&mut self, token: &Token, message: &str) {
+        self.errors.push(CompileError::at(token, message));
+    }
+
+    fn unsupported(&mut self, token: &Token) {
+        let message = format!("'{}' is not supported yet.", token.lexeme());
+        self.error_at(token, &message);
+    }
+}
+
+/// A clause as it would be written in the source, such as `fib(a is Int)`
+fn signature(name: &str, params: &[Pattern]) -> String {
+    let params: Vec<String> = params.iter().map(Pattern::to_string).collect();
+    format!("{}({})", name, params.join(", "))
+}
+
+/// The builtin that `$name` refers to, which is named without its module
+fn dollar_builtin(name: &str) -> Option<&'static Builtin> {
+    BUILTINS
+        .iter()
+        .find(|builtin| builtin.name.rsplit("::").next() == Some(name))
+}
+
+/// The name of a path, such as `Shape::Circle`
+fn path_name(path: &[Token]) -> String {
+    path.iter()
+        .map(Token::lexeme)
+        .collect::<Vec<String>>()
+        .join("::")
+}
+
+/// The type of the value of `expression`, if it can be
+/// known without knowing the values of any variables
+fn known_type(expression: &Expr) -> Option<String> {
+    let same = |a: Option<String>, b: Option<String>| match (a, b) {
+        (Some(a), Some(b)) if a == b => Some(a),
+        _ => None,
+    };
+
+    match expression {
+        Expr::Literal { value, .. } => Some(
+            match value {
+                Literal::Int(_) => "Int",
+                Literal::Float(_) => "Float",
+                Literal::String(_) => "String",
+                Literal::Bool(_) => "Bool",
+                Literal::Unit => "Unit",
+            }
+            .to_string(),
+        ),
+        Expr::Grouping { expression, .. } => known_type(expression),
+        Expr::Unary { operator, operand } => match operator.token_type() {
+            TokenType::Bang => Some("Bool".to_string()),
+            _ => known_type(operand),
+        },
+        Expr::Binary {
+            left,
+            operator,
+            right,
+        } => match operator.token_type() {
+            TokenType::Plus
+            | TokenType::Minus
+            | TokenType::Star
+            | TokenType::Slash
+            | TokenType::Percent => same(known_type(left), known_type(right)),
+            _ => Some("Bool".to_string()),
+        },
+        Expr::Interpolation { .. } => Some("String".to_string()),
+        Expr::Is { .. } => Some("Bool".to_string()),
+        Expr::Tuple { .. } => Some("a tuple".to_string()),
+        Expr::Record { .. } => Some("a record".to_string()),
+        Expr::Struct { name, .. } => Some(name.lexeme()),
+        Expr::If {
+            then_branch,
+            else_branch,
+            ..
+        } => same(known_type(then_branch), known_type(else_branch)),
+        Expr::Block(block) => match &block.value {
+            Some(value) => known_type(value),
+            None => Some("Unit".to_string()),
+        },
+        _ => None,
+    }
+}
+
+/// The first token of a pattern, for reporting errors
+fn pattern_token(pattern: &Pattern) -> Token {
+    match pattern {
+        Pattern::Wildcard(token) | Pattern::Literal { token, .. } => token.clone(),
+        Pattern::Binding { name, .. } => name.clone(),
+        Pattern::Named { path, .. } => path[0].clone(),
+        Pattern::Tuple { paren, .. } => paren.clone(),
+        Pattern::Record { brace, .. } => brace.clone(),
+    }
+}
+
+/// The first name that `pattern` binds, if it binds any, where
+/// a field of a record pattern without a pattern of its own
+/// only says that the record has the field
+fn pattern_binding(pattern: &Pattern) -> Option<Token> {
+    match pattern {
+        Pattern::Wildcard(_) | Pattern::Literal { .. } => None,
+        Pattern::Binding { name, .. } => Some(name.clone()),
+        Pattern::Named { arguments, .. } => arguments
+            .as_ref()
+            .and_then(|arguments| arguments.iter().find_map(pattern_binding)),
+        Pattern::Tuple { elements, .. } => elements.iter().find_map(pattern_binding),
+        Pattern::Record { fields, .. } => fields
+            .iter()
+            .find_map(|field| field.pattern.as_ref().and_then(pattern_binding)),
+    }
+}
+
+/// Whether a value can only match `pattern` if it contains an instance of
+/// the struct `name`, either directly or in the fields of other structs
+fn needs_struct(
+    pattern: &value::Pattern,
+    name: &str,
+    types: &HashMap<String, Vec<(value::Pattern, String)>>,
+    visited: &mut HashSet<String>,
+) -> bool {
+    let parts: Vec<&value::Pattern> = match pattern {
+        value::Pattern::Bind(pattern) => vec![pattern],
+        value::Pattern::Tuple(patterns) => patterns.iter().collect(),
+        value::Pattern::Record { fields, .. } => {
+            fields.iter().map(|(_, pattern)| pattern).collect()
+        }
+        value::Pattern::Template(template, fields) => {
+            let mut parts: Vec<&value::Pattern> = fields.iter().flatten().collect();
+            if template.enum_name().is_none() {
+                if template.name() == name {
+                    return true;
+                }
+                // The types of each other struct only need to be looked at once
+                if visited.insert(template.name().to_string()) {
+                    let fields = types
+                        .get(template.name())
+                        .map_or(&[][..], |fields| &fields[..]);
+                    parts.extend(fields.iter().map(|(pattern, _)| pattern));
+                }
+            }
+            parts
+        }
+        _ => vec![],
+    };
+
+    parts
+        .into_iter()
+        .any(|part| needs_struct(part, name, types, visited))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::build_scanner;
+    use crate::value::DataType;
+    use crate::virtual_machine::{DebugFlag, DebugFlags, RuntimeError, VMResult, VM};
+
+    #[test]
+    fn test_emitted_code() {
+        let chunk = compile(build_scanner("1 + 2")).unwrap();
+
+        assert_eq!(OpCode::Constant.to_byte(), chunk.get_byte(0).unwrap());
+        assert_eq!(OpCode::Constant.to_byte(), chunk.get_byte(2).unwrap());
+        assert_eq!(OpCode::Add.to_byte(), chunk.get_byte(4).unwrap());
+        assert_eq!(OpCode::Return.to_byte(), chunk.get_byte(5).unwrap());
+        assert_eq!(6, chunk.get_size());
+    }
+
+    #[test]
+    fn test_emitted_jumps() {
+        let chunk = compile(build_scanner("if 1 < 2 ? 3 else 4")).unwrap();
+
+        // The condition, then a jump over the first branch to the second
+        assert_eq!(OpCode::JumpIfFalse.to_byte(), chunk.get_byte(5).unwrap());
+        assert_eq!(Some(6), chunk.get_short(6));
+        assert_eq!(OpCode::Jump.to_byte(), chunk.get_byte(11).unwrap());
+        assert_eq!(Some(3), chunk.get_short(12));
+        assert_eq!(OpCode::Return.to_byte(), chunk.get_byte(17).unwrap());
+    }
+
+    #[test]
+    fn test_precedence() {
+        evaluates_to("1 + 2 * 3", Value::int(7));
+        evaluates_to("(1 + 2) * 3", Value::int(9));
+        evaluates_to("8 / 4 * 2", Value::int(4));
+        evaluates_to("8 - 4 - 2", Value::int(2));
+        evaluates_to("-2 - -3", Value::int(1));
+        evaluates_to("-(1 + 2) * 2", Value::int(-6));
+    }
+
+    #[test]
+    fn test_errors() {
+        let errors = compile(build_scanner("1 +")).unwrap_err();
+        assert_eq!(1, errors.len());
+        assert_eq!("Expect expression.", errors[0].message());
+
+        let errors = compile(build_scanner("(1 + 2")).unwrap_err();
+        assert_eq!("Expect ')' after expression.", errors[0].message());
+
+        let errors = compile(build_scanner("1 2")).unwrap_err();
+        assert_eq!("Expect end of expression.", errors[0].message());
+        assert_eq!(3, errors[0].column());
+    }
+
+    #[test]
+    fn test_lexical_errors() {
+        // Lexical errors are reported even after a syntax error
+        let errors = compile(build_scanner("1 + € 2 3 @ 0x")).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(CompileError::message).collect();
+        assert_eq!(
+            vec![
+                "Invalid character: €",
+                "Expect end of expression.",
+                "Invalid character: @",
+                "Expect digits after '0x' in number literal.",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let code = "1 + -(2 * 3)";
+        let chunk = compile(build_scanner(code)).unwrap();
+        let source = |offset: usize| {
+            let span = chunk.get_span(offset).unwrap();
+            &code[span.start().offset()..span.end().offset()]
+        };
+
+        assert_eq!("1", source(0));
+        assert_eq!("2", source(2));
+        assert_eq!("2 * 3", source(6));
+        assert_eq!("-(2 * 3)", source(7));
+        assert_eq!("1 + -(2 * 3)", source(8));
+    }
+
+    #[test]
+    fn test_structs() {
+        let code = "
+            struct Point { x is Int, y is Int, }
+            fn main() { let p = Point { y: 3, x: 2 }; p.x * 10 + p.y }
+        ";
+        runs_to(code, Value::int(23));
+
+        let value = run("struct Point { x, y } fn main() { Point { x: 1, y: 2 } }");
+        assert_eq!("Point { x: 1, y: 2 }", value.to_string());
+
+        // Fields are evaluated in the order they are written
+        let code = "
+            struct Point { x, y }
+            fn main() { let a = 1; let p = Point { y: a + 1, x: a }; (a, p, p.x * 10 + p.y) }
+        ";
+        assert_eq!("(1, Point { x: 1, y: 2 }, 12)", run(code).to_string());
+        assert_eq!(
+            "Division by zero.",
+            runtime_error("struct P { x, y } fn main() { P { y: 1 / 0, x: Int(1e300) } }")
+                .message()
+        );
+    }
+
+    #[test]
+    fn test_define_blocks() {
+        let code = "
+            struct Point { x, y }
+            define Point {
+                let origin = Point(0, 0);
+                Point(x, y);
+                fn diagonal(size) { Point { x: size, y: size } }
+                fn sum(self) { self.x + self.y }
+                fn add(self, other) { Point { x: self.x + other.x, y: self.y + other.y } }
+            }
+            fn main() {
+                let p = Point(1, 2).add(Point::diagonal(3));
+                p.sum() + Point::origin.sum() + Point::sum(p)
+            }
+        ";
+        runs_to(code, Value::int(18));
+
+        // Each constructor is a clause of the same function
+        let code = "
+            struct Point { x, y }
+            define Point {
+                Point(x is Int, y is Int);
+                Point(x is Float, y is Float) { Point(Int(x), Int(y)) }
+            }
+            fn main() {
+                let p = Point(1.0, 2.0);
+                let q = Point(3, 4);
+                p.x + p.y * 10 + q.x * 100 + q.y * 1000
+            }
+        ";
+        runs_to(code, Value::int(4321));
+    }
+
+    #[test]
+    fn test_struct_errors() {
+        assert_eq!(
+            vec![
+                "'Point' doesn't have a field called 'z'.",
+                "The field 'x' is given more than once.",
+                "Missing the field 'y' of 'Point'.",
+            ],
+            messages("struct Point { x, y } fn main() { Point { x: 1, z: 2, x: 3 } }")
+        );
+        assert_eq!(
+            vec!["Undefined struct 'Line'."],
+            messages("fn main() { Line { a: 1 } }")
+        );
+        assert_eq!(
+            vec!["'Point::f' is already defined."],
+            messages("struct Point { x } define Point { let f = 1; fn f() { 2 } }")
+        );
+        assert_eq!(
+            vec!["Expect a parameter for the field 'y'."],
+            messages("struct Point { x, y } define Point { Point(x); }")
+        );
+        assert_eq!(
+            vec!["Every constructor of 'Point' needs 2 parameters."],
+            messages("struct Point { x, y } define Point { Point(x, y); Point(p) { p } }")
+        );
+    }
+
+    #[test]
+    fn test_struct_field_types() {
+        // Fields can have the types of structs that are declared later
+        let code = "
+            struct Line { start is Point, end is Point }
+            struct Point { x is Int, y is Int }
+            define Point { Point(x, y); }
+            fn main() { Line { start: Point(0, 1), end: Point { x: 2, y: 3 } }.end.y }
+        ";
+        runs_to(code, Value::int(3));
+
+        let error = runtime_error("struct P { x is Int, y }\nfn main() { P { x: 1.5, y: 2 } }");
+        assert_eq!(
+            "The field 'x' of 'P' must match 'Int', but got 1.5.",
+            error.message()
+        );
+        assert_eq!(Some(2), error.span().map(|span| span.start().line()));
+        assert_eq!(
+            "The field 'y' of 'P' must match '(Int, _)', but got (1, 2, 3).",
+            runtime_error(
+                "struct P { x, y is (Int, _) } define P { P(x, y); } fn main() { P(1, (1, 2, 3)) }"
+            )
+            .message()
+        );
+
+        assert_eq!(
+            vec!["The type of a field can't bind the name 'a'."],
+            messages("struct P { x is (a, Int) } fn main() { 1 }")
+        );
+        assert_eq!(
+            vec![
+                "'Node' can never be built, since its fields need a 'Node'.",
+                "'A' can never be built, since its fields need a 'A'.",
+                "'B' can never be built, since its fields need a 'B'.",
+            ],
+            messages(
+                "
+                struct Node { value, next is Node }
+                struct A { b is (Int, B) }
+                struct B { a is A }
+                fn main() { 1 }
+                "
+            )
+        );
+    }
+
+    #[test]
+    fn test_enums() {
+        let code = "
+            enum Shape { Circle(radius is Float), Rectangle(width, height), Empty, }
+            define Shape {
+                let unit_square = Rectangle(1, 1);
+                fn width(Shape::Rectangle(width, _)) { width }
+                fn radius(self is Circle) { self.diameter() / 2.0 }
+                fn diameter(Circle(r)) { r * 2.0 }
+            }
+            fn main() { Shape::Circle(3.0).radius() + Float(Shape::width(Shape::unit_square)) }
+        ";
+        runs_to(code, Value::float(4.0));
+
+        let value = run("enum Shape { Square(Float), Empty } fn main() { Shape::Square(2.0) }");
+        assert_eq!("Shape::Square(2.0)", value.to_string());
+        let value = run("enum Shape { Square(Float), Empty } fn main() { Empty }");
+        assert_eq!("Shape::Empty", value.to_string());
+
+        let code = "enum A { X } enum B { X } fn is_a(a is A) { 1 } fn main() { is_a(A::X) }";
+        runs_to(code, Value::int(1));
+    }
+
+    #[test]
+    fn test_enum_errors() {
+        assert_eq!(
+            vec![
+                "More than one enum has a variant called 'X', so it needs the name of its enum.",
+                "Unknown type 'Y'.",
+                "'A::Z' has 1 fields but the pattern matches 2.",
+            ],
+            messages("enum A { X, Z(a) } enum B { X } fn f(X, Y, Z(a, b)) { 1 }")
+        );
+        assert_eq!(
+            vec!["The type 'A' is already defined."],
+            messages("struct A { x } enum A { X }")
+        );
+
+        let code = "
+            enum Shape { Circle(radius is Float), Empty }
+            fn radius(Shape::Circle(r)) { r }
+            fn main() { radius(Shape::Empty) }
+        ";
+        assert_eq!(
+            "No clause of 'radius' matches (Shape::Empty). Tried:\n    radius(Shape::Circle(r))",
+            runtime_error(code).message()
+        );
+    }
+
+    #[test]
+    fn test_traits() {
+        runs_to("trait Show { } fn main() { 1 }", Value::int(1));
+        assert_eq!(
+            vec!["The type 'Show' is already defined."],
+            messages("trait Show { } struct Show { x }")
+        );
+        assert_eq!(
+            vec!["The trait 'Show' can't be used as a pattern."],
+            messages("trait Show { } fn f(a is Show) { a }")
+        );
+    }
+
+    #[test]
+    fn test_clauses() {
+        let code = "
+            fn fib(0) { 0 }
+            fn fib(1) { 1 }
+            fn fib(a is Int) { fib(a - 1) + fib(a - 2) }
+            fn main() { fib(10) }
+        ";
+        runs_to(code, Value::int(55));
+
+        let code = "
+            enum Shape { Circle(Float), Rectangle(Float, Float), Empty }
+            define Shape {
+                fn area(Circle(r)) { 3.0 * r * r }
+                fn area(Rectangle(w, h)) { w * h }
+                fn area(_) { 0.0 }
+            }
+            fn main() { Shape::Circle(1.0).area() + Shape::Rectangle(2.0, 3.0).area() + Shape::Empty.area() }
+        ";
+        runs_to(code, Value::float(9.0));
+    }
+
+    #[test]
+    fn test_clause_errors() {
+        assert_eq!(
+            "Every clause of 'f' needs 1 parameters.",
+            messages("fn f(a) { a } fn f(a, b) { a }")[0]
+        );
+
+        let code = "fn half(0) { 0 } fn half(n is Int, ) { n / 2 } fn main() { half(-1.5) }";
+        assert_eq!(
+            "No clause of 'half' matches (-1.5). Tried:\n    half(0)\n    half(n is Int)",
+            runtime_error(code).message()
+        );
+    }
+
+    #[test]
+    fn test_guards() {
+        let code = "
+            fn sign(n) where n > 0 { 1 }
+            fn sign(n) where n < 0 { -1 }
+            fn sign(_) { 0 }
+            fn main() { sign(5) * 100 + sign(-5) * 10 + sign(0) }
+        ";
+        runs_to(code, Value::int(90));
+
+        // Guards see the names bound by patterns, and the next
+        // clause is tried when the guard is false
+        let code = "
+            enum Shape { Circle(Float), Empty }
+            fn size(Shape::Circle(r)) where r >= 10.0 { 2 }
+            fn size(Shape::Circle(r)) { 1 }
+            fn size(_) { 0 }
+            fn main() { size(Shape::Circle(20.0)) * 100 + size(Shape::Circle(1.0)) * 10 + size(Shape::Empty) }
+        ";
+        runs_to(code, Value::int(210));
+
+        let code = "
+            fn kind(point) where point is (Int, Int) { 2 }
+            fn kind(point) where point is {x: Float, ...} and !(point is {x: 0.0, ...}) { 1 }
+            fn kind(point) where point is {x, y} { 3 }
+            fn kind(_) { 0 }
+            fn main() {
+                let (x, y) = (4, 5);
+                kind((1, 2)) * 100 + kind({x: 1.5, y: 2}) * 10 + kind({x: 0.0}) + kind({x: x, y: y}) * (x + y)
+            }
+        ";
+        runs_to(code, Value::int(237));
+
+        runs_to("fn main() { 1 + 1 == 2 }", Value::bool(true));
+        runs_to("fn main() { 1 != 1 }", Value::bool(false));
+    }
+
+    #[test]
+    fn test_guard_errors() {
+        let code = "impure fn log(a) { a } fn f(n) where log(n) > 0 { n } fn main() { f(1) }";
+        assert_eq!(
+            "A guard can't call the impure function 'log'.",
+            messages(code)[0]
+        );
+
+        let code = "
+            struct Point { x, y }
+            define Point { impure fn log(self) { true } }
+            fn f(p) where p.log() { p }
+            fn main() { f(Point { x: 1, y: 2 }) }
+        ";
+        assert_eq!(
+            "A guard can't call the impure method 'log'.",
+            messages(code)[0]
+        );
+
+        // Functions that call impure functions have side effects
+        // too, even if they aren't declared impure
+        let code = "
+            fn log(a) { IO::println(a); true }
+            fn check(a) { log(a) }
+            fn f(n) where log(n) { n }
+            fn g(n) where check(n) { n }
+            fn main() { f(1) }
+        ";
+        assert_eq!(
+            vec![
+                "A guard can't call the impure function 'log'.",
+                "A guard can't call the impure function 'check'.",
+            ],
+            messages(code)
+        );
+        let code = "
+            struct P { x }
+            define P { fn to_string(self) { IO::println(self.x); \"P\" } }
+            fn f(p) where \"${p}\" == \"P\" { p }
+            fn main() { f(P { x: 1 }) }
+        ";
+        assert_eq!(
+            vec!["A guard can't call the impure method 'to_string'."],
+            messages(code)
+        );
+        let code = "
+            fn positive(n) { n > 0 }
+            fn f(n) where positive(n) { n }
+            fn f(_) { 0 }
+            fn main() { f(2) * 10 + f(-2) }
+        ";
+        runs_to(code, Value::int(20));
+
+        let code = "fn f(p) where p is (x, _) { p } fn main() { f((1, 2)) }";
+        assert_eq!(
+            "An 'is' pattern can't bind the name 'x'.",
+            messages(code)[0]
+        );
+
+        let code = "fn f(n) where n { n } fn main() { f(1) }";
+        assert_eq!(
+            "A guard must be true or false.",
+            runtime_error(code).message()
+        );
+
+        // A call that no clause accepts is reported at the call
+        let code = "fn positive(n) where n > 0 { n }\nfn main() { positive(-1) }";
+        let error = runtime_error(code);
+        assert_eq!(
+            "No clause of 'positive' matches (-1). Tried:\n    positive(n)",
+            error.message()
+        );
+        assert_eq!(Some(2), error.span().map(|span| span.start().line()));
+
+        // Including a call to a `to_string` method from an interpolated string
+        let code = "
+            struct P { x }
+            define P { fn to_string(self) where self.x > 0 { \"P\" } }
+            fn main() { let p = P { x: 0 }; \"${(p)}\" }
+        ";
+        let error = runtime_error(code);
+        assert_eq!(
+            "No clause of 'P::to_string' matches (P { x: 0 }). Tried:\n    to_string(self)",
+            error.message()
+        );
+        let start = error.span().map(|span| span.start().offset()).unwrap();
+        assert_eq!("(p)", &code[start..start + 3]);
+    }
+
+    #[test]
+    fn test_tuples() {
+        runs_to(
+            "fn main() { (1, (2, 3)) }",
+            Value::tuple(vec![
+                Value::int(1),
+                Value::tuple(vec![Value::int(2), Value::int(3)]),
+            ]),
+        );
+        runs_to("fn main() { let p = (0, 1); p(1) + p(0) }", Value::int(1));
+
+        let code = "
+            fn sum((a, b)) { a + b }
+            fn sum((a, b, c)) { a + b + c }
+            fn first(p is (Int, Int)) { p(0) }
+            fn first(_) { 0 }
+            fn main() { sum((1, 2)) * 100 + sum((1, 2, 3)) * 10 + first((1.5, 2)) }
+        ";
+        runs_to(code, Value::int(360));
+
+        let error = runtime_error("fn main() {\n    let p = (1, 2);\n    p(2)\n}");
+        assert_eq!(
+            "Index 2 is out of range for a tuple with 2 elements.",
+            error.message()
+        );
+        assert_eq!(Some(3), error.span().map(|span| span.start().line()));
+        assert_eq!(
+            "A tuple index must be an Int.",
+            runtime_error("fn main() { (1, 2)(0.5) }").message()
+        );
+        assert_eq!(
+            "A tuple takes 1 index but got 2.",
+            runtime_error("fn main() { (1, 2)(0, 1) }").message()
+        );
+    }
+
+    #[test]
+    fn test_records() {
+        runs_to(
+            "fn main() { {y: 2, x: 1} }",
+            Value::record(vec![
+                ("x".to_string(), Value::int(1)),
+                ("y".to_string(), Value::int(2)),
+            ]),
+        );
+        runs_to(
+            "fn main() { let p = {x: 1, y: 2}; p.x * 10 + p.y }",
+            Value::int(12),
+        );
+        runs_to(
+            "fn main() { {x: 1, y: 2} == {y: 2, x: 1} }",
+            Value::bool(true),
+        );
+        runs_to("fn main() { {x: 1} == {x: 1, y: 2} }", Value::bool(false));
+
+        // Closed patterns only match records with exactly their fields
+        let code = "
+            fn size({x, y}) { x + y }
+            fn size({x: a, y: b, z: c}) { a + b + c }
+            fn size({x, ...}) { x }
+            fn size(_) { 0 }
+            fn main() {
+                size({x: 1, y: 2}) * 1000 + size({x: 1, y: 1, z: 1}) * 100
+                    + size({x: 1, w: 2}) * 10 + size({y: 1})
+            }
+        ";
+        runs_to(code, Value::int(3310));
+
+        // A record with the fields of several open patterns can match any of them
+        let code = "
+            fn f({x: 0, ...}) { 1 }
+            fn f({y: 0, ...}) { 2 }
+            fn f(_) { 3 }
+            fn main() { f({x: 0, y: 0}) * 100 + f({x: 1, y: 0}) * 10 + f({x: 1, y: 1}) }
+        ";
+        runs_to(code, Value::int(123));
+    }
+
+    #[test]
+    fn test_record_errors() {
+        assert_eq!(
+            "The field 'x' is given more than once.",
+            messages("fn main() { {x: 1, x: 2} }")[0]
+        );
+        assert_eq!(
+            "The field 'x' is matched more than once.",
+            messages("fn f({x, x: y}) { y }")[0]
+        );
+    }
+
+    #[test]
+    fn test_let_patterns() {
+        let code = "
+            fn main() {
+                let (x, y) = (1, 2);
+                let {x: a, y: b} = {x: 10, y: 20};
+                let (c, {d, ...}) = (100, {d: 200, e: 300});
+                x + y + a + b + c + d
+            }
+        ";
+        runs_to(code, Value::int(333));
+
+        let code = "
+            struct Point { x, y }
+            enum Wrapper { Wrap(Int) }
+            fn main() {
+                let Point(a, b) = Point { x: 2, y: 3 };
+                let Wrap(c) = Wrap(4);
+                let n is Int = a * b * c;
+                n
+            }
+        ";
+        runs_to(code, Value::int(24));
+    }
+
+    #[test]
+    fn test_let_errors() {
+        assert_eq!(
+            "A 'let' can't use the refutable pattern '0'.",
+            messages("fn main() { let (0, y) = (0, 1); y }")[0]
+        );
+        assert_eq!(
+            "A 'let' can't use the refutable pattern 'Shape::Circle(r)'.",
+            messages("enum Shape { Circle(Float), Empty } fn main() { let Shape::Circle(r) = Shape::Empty; r }")[0]
+        );
+
+        let error = runtime_error("fn main() {\n    let (x, y) = (1, 2, 3);\n    x\n}");
+        assert_eq!(
+            "The value (1, 2, 3) doesn't match the pattern.",
+            error.message()
+        );
+        assert_eq!(Some(2), error.span().map(|span| span.start().line()));
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let code = "
+            fn fib(a is Int) {
+                if a == 0 ? 0
+                else if a == 1 ? 1
+                else fib(a - 1) + fib(a - 2)
+            }
+            fn main() { fib(10) }
+        ";
+        runs_to(code, Value::int(55));
+
+        runs_to("fn main() { !(1 > 2) }", Value::bool(true));
+        runs_to(
+            "fn main() { let a = 3; if a >= 3 ? { let b = a * 2; b } else a }",
+            Value::int(6),
+        );
+
+        let code = "
+            fn describe(true) { 1 }
+            fn describe(false) { 0 }
+            fn main() { describe(1 <= 1) * 10 + describe(1 != 1) }
+        ";
+        runs_to(code, Value::int(10));
+
+        assert_eq!(
+            "The condition must be true or false.",
+            runtime_error("fn main() { if 1 ? 2 else 3 }").message()
+        );
+    }
+
+    #[test]
+    fn test_assertions() {
+        runs_to(
+            "fn main() { let a = (1, 2); assert a(0) == 1; assert(a(1) > a(0)); a(1) }",
+            Value::int(2),
+        );
+
+        let code = "fn main() {\n    let a = 1;\n    assert a == 2;\n    a\n}";
+        let error = runtime_error(code);
+        assert_eq!("Assertion failed.", error.message());
+        let span = error.span().unwrap();
+        assert_eq!(
+            "assert a == 2",
+            &code[span.start().offset()..span.end().offset()]
+        );
+
+        assert_eq!(
+            "An assertion must be true or false.",
+            runtime_error("fn one() { 1 } fn main() { assert one() }").message()
+        );
+        assert_eq!(
+            vec!["An assertion must be Bool, but it is Int."],
+            messages("fn main() { assert 1 + 2; }")
+        );
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        runs_to("fn main() { 1 < 2 and 2 < 3 }", Value::bool(true));
+        runs_to("fn main() { 1 > 2 or 2 > 3 }", Value::bool(false));
+        runs_to("fn main() { true and false or true }", Value::bool(true));
+
+        // The right operand doesn't run once the left one decides the result
+        let code = "
+            fn fail(x) where x > 0 { true }
+            fn main() { (false and fail(0)) or (true or fail(0)) }
+        ";
+        runs_to(code, Value::bool(true));
+
+        let code = "
+            fn digit(n) where n >= 0 and n < 10 { true }
+            fn digit(_) { false }
+            fn main() { digit(5) and !digit(10) and !digit(-1) }
+        ";
+        runs_to(code, Value::bool(true));
+
+        assert_eq!(
+            "The operands of 'and' must be Bool, but one is Int.",
+            messages("fn main() { true and 1 }")[0]
+        );
+        assert_eq!(
+            "The operands of 'or' must be Bool, but one is a tuple.",
+            messages("fn main() { (1, 2) or true }")[0]
+        );
+
+        assert_eq!(
+            "Operand must be true or false.",
+            runtime_error("fn f(a) { a } fn main() { true and f(1) }").message()
+        );
+    }
+
+    #[test]
+    fn test_numbers() {
+        runs_to("fn main() { 7 / 2 * 10 + 7 % 2 }", Value::int(31));
+        runs_to("fn main() { -7 / 2 * 10 + -7 % 2 }", Value::int(-31));
+        runs_to("fn main() { 7.0 / 2.0 }", Value::float(3.5));
+        runs_to(
+            "fn main() { Float(7) / 2.0 + Float(Int(2.9)) }",
+            Value::float(5.5),
+        );
+        runs_to("fn main() { Int(-2.5) }", Value::int(-2));
+        runs_to("fn main() { 1 == 1.0 }", Value::bool(false));
+
+        let code = "
+            fn kind(n is Int) { 1 }
+            fn kind(n is Float) { 2 }
+            fn kind(_) { 3 }
+            fn main() { kind(1) * 100 + kind(1.0) * 10 + kind(true) }
+        ";
+        runs_to(code, Value::int(123));
+
+        assert_eq!(
+            "Integer overflow.",
+            runtime_error("fn main() { 9223372036854775807 + 1 }").message()
+        );
+        assert_eq!(
+            "Integer overflow.",
+            runtime_error("fn main() { -(-9223372036854775807 - 1) }").message()
+        );
+        assert_eq!(
+            "Division by zero.",
+            runtime_error("fn main() { 1 / 0 }").message()
+        );
+        assert_eq!(
+            "Division by zero.",
+            runtime_error("fn main() { 1 % 0 }").message()
+        );
+        assert_eq!(
+            "Integer overflow.",
+            runtime_error("fn main() { (-9223372036854775807 - 1) / -1 }").message()
+        );
+        runs_to(
+            "fn main() { (-9223372036854775807 - 1) % -1 }",
+            Value::int(0),
+        );
+        assert_eq!(
+            "Operands must be two Ints, two Floats or two Strings.",
+            runtime_error("fn main() { 2 * 1.5 }").message()
+        );
+        assert_eq!(
+            "The Float is out of range for an Int.",
+            runtime_error("fn main() { Int(1e300) }").message()
+        );
+
+        assert_eq!(
+            "A conversion takes 1 argument but got 2.",
+            messages("fn main() { Float(1, 2) }")[0]
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let string = |text: &str| Value::string(Rc::from(text));
+        runs_to(
+            r#"fn main() { "Hello" + ", " + "world!" }"#,
+            string("Hello, world!"),
+        );
+        runs_to(r#"fn main() { "ab" == "a" + "b" }"#, Value::bool(true));
+        runs_to(
+            r#"fn main() { let n = 2; "${n} + ${1.5} is ${Float(n) + 1.5}, ${(true, "a")}" }"#,
+            string("2 + 1.5 is 3.5, (true, \"a\")"),
+        );
+
+        // Values whose type has a `to_string` method are interpolated with it
+        let code = r#"
+            struct Point { x, y }
+            define Point {
+                fn to_string(self) { "<${self.x}, ${self.y}>" }
+            }
+            fn main() { "p = ${Point { x: 1, y: Point { x: 2, y: 3 } }}!" }
+        "#;
+        runs_to(code, string("p = <1, <2, 3>>!"));
+
+        let code = r#"
+            fn greet("hi") { 1 }
+            fn greet(s is String) { 2 }
+            fn greet(_) { 3 }
+            fn main() { greet("hi") * 100 + greet("hello") * 10 + greet(1) }
+        "#;
+        runs_to(code, Value::int(123));
+
+        // Equal literals share their text
+        match run(r#"fn main() { ("text", "text") }"#).data() {
+            DataType::Tuple(elements) => match (elements[0].data(), elements[1].data()) {
+                (DataType::String(a), DataType::String(b)) => assert!(Rc::ptr_eq(a, b)),
+                _ => panic!("expected two strings"),
+            },
+            _ => panic!("expected a tuple"),
+        }
+
+        assert_eq!(
+            "Operands must be two Ints, two Floats or two Strings.",
+            runtime_error(r#"fn main() { "a" + 1 }"#).message()
+        );
+        let code = r#"
+            struct Id { id }
+            define Id { fn to_string(self) { self.id } }
+            fn main() { "${Id { id: 1 }}" }
+        "#;
+        assert_eq!(
+            "A 'to_string' method must return a String.",
+            runtime_error(code).message()
+        );
+    }
+
+    #[test]
+    fn test_builtins() {
+        runs_to(
+            r#"fn main() { IO::println("${1} and ${2}") }"#,
+            Value::unit(),
+        );
+
+        // Builtins are only used if nothing else has their name
+        let code = "
+            struct IO { }
+            define IO { fn println(a) { a } }
+            fn main() { IO::println(1) }
+        ";
+        runs_to(code, Value::int(1));
+
+        assert_eq!(
+            vec!["'IO::println' takes 1 argument but got 0."],
+            messages("fn main() { IO::println() }")
+        );
+        assert_eq!(
+            vec!["'IO::read_line' takes 0 arguments but got 1."],
+            messages("fn main() { IO::read_line(1) }")
+        );
+        assert_eq!(
+            vec!["A guard can't call the impure function 'IO::println'."],
+            messages("fn f(n) where IO::println(n) == () { n } fn main() { f(1) }")
+        );
+
+        // A `$` names a builtin without its module, whatever else has its name
+        let code = "
+            struct IO { }
+            define IO { fn println(a) { a } }
+            fn Int(a) { a }
+            fn main() { $println(IO::println(1)); $Int(2.5) + Int(1) }
+        ";
+        runs_to(code, Value::int(3));
+        assert_eq!(
+            vec![
+                "Unknown builtin '$print'.",
+                "The builtin '$println' can only be called.",
+            ],
+            messages("fn main() { $print(1); let p = $println; p }")
+        );
+        assert_eq!(
+            vec!["A guard can't call the impure function 'IO::println'."],
+            messages("fn f(n) where $println(n) == () { n } fn main() { f(1) }")
+        );
+    }
+
+    #[test]
+    fn test_clause_warnings() {
+        let warnings = |code: &str| -> Vec<String> {
+            let (chunk, warnings) = compile_program_with_warnings(build_scanner(code));
+            assert!(chunk.is_ok());
+            warnings.iter().map(Warning::message).collect()
+        };
+
+        let code = "
+            enum Shape { Circle(Float), Rectangle(Float, Float), Empty }
+            fn area(Shape::Circle(r)) { r }
+            fn area(s is Shape) { 0 }
+            fn area(Shape::Empty) { 0 }
+        ";
+        assert_eq!(
+            vec!["The clause area(Shape::Empty) can never run, since earlier clauses match everything it does."],
+            warnings(code)
+        );
+
+        let code = "
+            enum Shape { Circle(Float), Rectangle(Float, Float), Empty }
+            fn area(Shape::Circle(r), 0) { r }
+            fn area(Shape::Rectangle(w, h), n) { w * h }
+        ";
+        assert_eq!(
+            vec!["No clause of 'area' matches area(Shape::Empty, _)."],
+            warnings(code)
+        );
+
+        let code = "struct Point { x, y } fn f(Point(0, y)) { y } fn f(p is Point) { 0 }";
+        assert!(warnings(code).is_empty());
+
+        let code = "enum Bit { Zero, One } fn f((Bit::One, n)) { n }";
+        assert_eq!(
+            vec!["No clause of 'f' matches f((Bit::Zero, _))."],
+            warnings(code)
+        );
+
+        let code = "fn f({x, ...}) { x } fn f({x, y}) { y }";
+        assert_eq!(
+            vec!["The clause f({x, y}) can never run, since earlier clauses match everything it does."],
+            warnings(code)
+        );
+
+        assert_eq!(
+            vec!["No clause of 'f' matches f(false)."],
+            warnings("fn f(true) { 1 }")
+        );
+        assert_eq!(
+            vec![
+                "The clause f(true) can never run, since earlier clauses match everything it does."
+            ],
+            warnings("fn f(b is Bool) { 1 } fn f(true) { 2 }")
+        );
+
+        // A guarded clause doesn't cover the clauses after it
+        let code = "enum Bit { Zero, One } fn f(b is Bit) where 1 > 0 { 1 } fn f(Bit::One) { 0 }";
+        assert_eq!(
+            vec!["No clause of 'f' matches f(Bit::Zero)."],
+            warnings(code)
+        );
+    }
+
+    #[test]
+    fn test_field_errors() {
+        assert_eq!(
+            "Undefined field 'y'.",
+            runtime_error("struct Point { x } fn main() { Point { x: 1 }.y }").message()
+        );
+    }
+
+    fn run(code: &str) -> Value {
+        let chunk = compile_program(build_scanner(code)).unwrap();
+        // Collecting as often as possible finds objects that are freed too soon
+        let debug = DebugFlags::new().set_flag(&DebugFlag::StressGc, true);
+        match VM::new_debugger(debug).interpret(&chunk) {
+            VMResult::Okay(value) => value,
+            _ => panic!("'{}' resulted in an error", code),
+        }
+    }
+
+    fn runs_to(code: &str, expected: Value) {
+        assert_eq!(expected, run(code));
+    }
+
+    /// The messages of the errors that compiling `code` reports
+    fn messages(code: &str) -> Vec<String> {
+        let errors = compile_program(build_scanner(code)).unwrap_err();
+        errors.iter().map(CompileError::message).collect()
+    }
+
+    /// The error that running `code` stops with
+    fn runtime_error(code: &str) -> RuntimeError {
+        let chunk = compile_program(build_scanner(code)).unwrap();
+        match VM::new().interpret(&chunk) {
+            VMResult::RuntimeError(error) => error,
+            _ => panic!("'{}' didn't result in a runtime error", code),
+        }
+    }
+
+    fn evaluates_to(code: &str, expected: Value) {
+        let chunk = compile(build_scanner(code)).unwrap();
+        match VM::new().interpret(&chunk) {
+            VMResult::Okay(value) => assert!(Value::compare_values(expected, value)),
+            _ => panic!("'{}' resulted in an error", code),
+        }
+    }
 }
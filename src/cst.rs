@@ -0,0 +1,310 @@
+//! A lossless concrete syntax tree
+//!
+//! Unlike the AST, the tree keeps every token of the source,
+//! including whitespace, comments and errors, so the source can
+//! be reproduced exactly from it. The tree only groups tokens
+//! into top-level items, brackets and interpolated strings,
+//! which is all that formatting and refactoring tools need to
+//! find their way around the source.
+
+use crate::scanner::{self, Token, TokenType};
+
+/// Builds the concrete syntax tree of `code`
+///
+/// # Examples
+/// ```
+/// use lucent_lang::cst::{self, NodeKind};
+///
+/// let code = "// One\nfn one() { 1 } /* done */\n";
+/// let tree = cst::parse(code);
+///
+/// assert_eq!(code, tree.text());
+/// assert_eq!(1, tree.nodes().filter(|node| node.kind() == NodeKind::Item).count());
+/// ```
+pub fn parse(code: &str) -> Node {
+    let mut builder = Builder {
+        stack: vec![Node::new(NodeKind::Program)],
+        trivia: vec![],
+    };
+
+    for token in scanner::build_trivia_scanner(code) {
+        builder.token(token);
+    }
+
+    builder.finish()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Program,
+    /// A struct, enum, trait, define block, function or constant
+    Item,
+    /// `(` and `)` and the tokens between them
+    Parenthesized,
+    /// `{` and `}` and the tokens between them
+    Braced,
+    /// An interpolated string, from its start to its end
+    Interpolation,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Node(Node),
+    Token(Token),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    kind: NodeKind,
+    children: Vec<Element>,
+}
+
+impl Node {
+    fn new(kind: NodeKind) -> Self {
+        Node {
+            kind,
+            children: vec![],
+        }
+    }
+
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    pub fn children(&self) -> &[Element] {
+        &self.children
+    }
+
+    /// The nodes directly inside of this node
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.children.iter().filter_map(|child| match child {
+            Element::Node(node) => Some(node),
+            Element::Token(_) => None,
+        })
+    }
+
+    /// Every token in this node and the nodes inside it, in order
+    pub fn tokens(&self) -> Vec<&Token> {
+        let mut tokens = vec![];
+        let mut stack = vec![self.children.iter()];
+
+        // Walk the tree with an explicit stack, since
+        // brackets can be nested arbitrarily deeply
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(Element::Token(token)) => tokens.push(token),
+                Some(Element::Node(node)) => stack.push(node.children.iter()),
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+
+        tokens
+    }
+
+    /// The source code of the node, exactly as it was parsed
+    pub fn text(&self) -> String {
+        self.tokens().iter().map(|token| token.lexeme()).collect()
+    }
+}
+
+impl Drop for Node {
+    // Drop nested nodes one at a time, since dropping them
+    // recursively could overflow the stack
+    fn drop(&mut self) {
+        let mut children = std::mem::take(&mut self.children);
+        while let Some(child) = children.pop() {
+            if let Element::Node(mut node) = child {
+                children.append(&mut node.children);
+            }
+        }
+    }
+}
+
+struct Builder {
+    // The nodes that haven't been closed yet, innermost last
+    stack: Vec<Node>,
+    // Trivia between items, which is only added to the tree
+    // once it is clear which item it belongs to
+    trivia: Vec<Token>,
+}
+
+impl Builder {
+    fn token(&mut self, token: Token) {
+        let t_type = token.token_type();
+
+        if t_type.is_trivia() && self.at_top_level() {
+            self.trivia.push(token);
+            return;
+        }
+
+        let starts_item = self.starts_item(&t_type);
+        if self.at_top_level() && (starts_item || t_type == TokenType::EOF) {
+            // Comments and blank lines before an item
+            // aren't part of the previous item
+            self.close_item();
+            self.flush_trivia();
+            if starts_item {
+                self.stack.push(Node::new(NodeKind::Item));
+            }
+        } else {
+            self.flush_trivia();
+        }
+
+        match t_type {
+            TokenType::LeftParen => self.open(NodeKind::Parenthesized, token),
+            TokenType::LeftBrace => self.open(NodeKind::Braced, token),
+            TokenType::InterpolationStart(_) => self.open(NodeKind::Interpolation, token),
+            TokenType::RightParen => self.close(NodeKind::Parenthesized, token),
+            TokenType::RightBrace => self.close(NodeKind::Braced, token),
+            TokenType::InterpolationEnd(_) => self.close(NodeKind::Interpolation, token),
+            _ => self.push(Element::Token(token)),
+        }
+    }
+
+    fn finish(mut self) -> Node {
+        self.close_item();
+        self.flush_trivia();
+        while self.stack.len() > 1 {
+            self.pop();
+        }
+        self.stack.remove(0)
+    }
+
+    /// Whether the builder is between items
+    /// or directly inside of an item
+    fn at_top_level(&self) -> bool {
+        matches!(
+            self.stack.last().map(Node::kind),
+            Some(NodeKind::Program) | Some(NodeKind::Item)
+        )
+    }
+
+    fn starts_item(&self, t_type: &TokenType) -> bool {
+        match t_type {
+            TokenType::Struct | TokenType::Enum | TokenType::Trait => true,
+            TokenType::Define | TokenType::Let => true,
+            TokenType::Impure => true,
+            // `impure fn` is a single item
+            TokenType::Function => !self.previous_is(&TokenType::Impure),
+            _ => false,
+        }
+    }
+
+    fn previous_is(&self, t_type: &TokenType) -> bool {
+        let item = self.stack.last().filter(|node| node.kind == NodeKind::Item);
+        match item.and_then(|item| item.children.last()) {
+            Some(Element::Token(token)) => token.token_type() == *t_type,
+            _ => false,
+        }
+    }
+
+    fn flush_trivia(&mut self) {
+        for token in std::mem::take(&mut self.trivia) {
+            self.push(Element::Token(token));
+        }
+    }
+
+    fn open(&mut self, kind: NodeKind, token: Token) {
+        let mut node = Node::new(kind);
+        node.children.push(Element::Token(token));
+        self.stack.push(node);
+    }
+
+    /// Closes the innermost node of the given kind with `token`,
+    /// along with any unclosed nodes inside of it
+    ///
+    /// A closing token without a matching opening token
+    /// is kept where it is, so that no tokens are lost.
+    fn close(&mut self, kind: NodeKind, token: Token) {
+        if !self.stack.iter().any(|node| node.kind == kind) {
+            self.push(Element::Token(token));
+            return;
+        }
+
+        while self.stack.last().map(Node::kind) != Some(kind) {
+            self.pop();
+        }
+        self.push(Element::Token(token));
+        self.pop();
+    }
+
+    fn close_item(&mut self) {
+        if self.stack.last().map(Node::kind) == Some(NodeKind::Item) {
+            self.pop();
+        }
+    }
+
+    /// Adds the innermost node to the node that contains it
+    fn pop(&mut self) {
+        if let Some(node) = self.stack.pop() {
+            self.push(Element::Node(node));
+        }
+    }
+
+    fn push(&mut self, element: Element) {
+        if let Some(node) = self.stack.last_mut() {
+            node.children.push(element);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lossless() {
+        let sources = [
+            "",
+            "  \n\t// just a comment",
+            "struct Point { x is Float, y is Float }\r\ndefine Point {\n\tfn sum(self) { self.x + self.y } /* sum /* nested */ */\n}\n",
+            "impure fn greet(name) { IO::println(\"hi ${ name }, ${ \"${ 1 }\" }!\") }",
+            "fn broken( { ) } } € \"unterminated\n0x /* never closed",
+        ];
+
+        for source in sources.iter() {
+            assert_eq!(*source, parse(source).text());
+        }
+    }
+
+    #[test]
+    fn test_items() {
+        let tree =
+            parse("// First\nfn one() { 1 }\n\n/// Second\nimpure fn two() { 2 }\nlet x = 1;");
+        let items: Vec<String> = tree.nodes().map(Node::text).collect();
+        assert_eq!(
+            vec!["fn one() { 1 }", "impure fn two() { 2 }", "let x = 1;"],
+            items
+        );
+
+        // The trivia between items is part of the program
+        let comments = tree.children().iter().filter(|child| match child {
+            Element::Token(token) => token.token_type().is_comment(),
+            Element::Node(_) => false,
+        });
+        assert_eq!(2, comments.count());
+    }
+
+    #[test]
+    fn test_nesting() {
+        let tree = parse("fn f() { g(\"${ (1) }\") }");
+        let item = tree.nodes().next().unwrap();
+        let kinds: Vec<NodeKind> = item.nodes().map(Node::kind).collect();
+        assert_eq!(vec![NodeKind::Parenthesized, NodeKind::Braced], kinds);
+
+        let body = item.nodes().nth(1).unwrap();
+        let call = body.nodes().next().unwrap();
+        assert_eq!("(\"${ (1) }\")", call.text());
+        let interpolation = call.nodes().next().unwrap();
+        assert_eq!(NodeKind::Interpolation, interpolation.kind());
+        assert_eq!("(1)", interpolation.nodes().next().unwrap().text());
+    }
+
+    #[test]
+    fn test_deep_nesting() {
+        let source = "(".repeat(100_000) + &")".repeat(100_000);
+        assert_eq!(source, parse(&source).text());
+    }
+}
@@ -0,0 +1,674 @@
+//! Decision trees for choosing the clause of a function,
+//! and for matching a single value against a pattern
+//!
+//! The patterns of every clause are compiled together into a tree
+//! that tests each argument, and each field of an argument, at most
+//! once. Every switch in the tree splits the values at one position
+//! into disjoint cases, and the clauses that can still match in each
+//! case are compiled into the case's subtree.
+//!
+//! A pattern outside of a function, as in a `let` or an `is` test,
+//! is compiled the same way, as a function with one clause and one
+//! parameter.
+
+use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+use crate::value::{DataType, Pattern, Template, Value};
+
+/// The position of a value inside of the arguments, made up of
+/// the index of an argument and then a step into each field
+pub type Path = Vec<Step>;
+
+/// One step from a value to a value inside of it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// An argument, or a field of a tuple, struct or variant by position
+    Index(usize),
+    /// A field of a record by name
+    Field(String),
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Step::Index(index) => write!(f, "{}", index),
+            Step::Field(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// Runs the clause with the given index
+    Clause(usize),
+    /// Runs the clause with the given index if its guard is true,
+    /// and otherwise continues with the clauses after it
+    Guard {
+        clause: usize,
+        otherwise: Rc<Decision>,
+    },
+    /// No clause matches the arguments
+    Fail,
+    /// Looks at the value at `path` and continues with the first case that
+    /// the value belongs to, or with `default` if it doesn't belong to any
+    Switch {
+        path: Path,
+        cases: Vec<(Case, Decision)>,
+        default: Box<Decision>,
+    },
+}
+
+/// A set of values that a switch can tell apart from the others
+///
+/// A value can belong to more than one case, such as `0` and `Int`,
+/// so the cases of a switch are ordered from most to least specific.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Case {
+    Literal(Value),
+    Template(Rc<Template>),
+    /// Tuples with the given number of elements
+    Tuple(usize),
+    /// Records with exactly the given fields, in order of their
+    /// names, or with at least those fields if the case is open
+    Record {
+        fields: Vec<String>,
+        open: bool,
+    },
+    Int,
+    Float,
+    String,
+    Bool,
+    Enum(String),
+    Unit,
+}
+
+impl Case {
+    fn contains(&self, value: &Value) -> bool {
+        match (self, value.data()) {
+            (Case::Literal(literal), _) => literal == value,
+            (Case::Template(template), _) => value
+                .template()
+                .is_some_and(|other| Rc::ptr_eq(template, other)),
+            (Case::Tuple(length), DataType::Tuple(elements)) => elements.len() == *length,
+            (Case::Record { fields, open }, DataType::Record(_)) => {
+                let names = value.record_fields().unwrap_or_default();
+                (*open || names.len() == fields.len())
+                    && fields.iter().all(|field| names.contains(&field.as_str()))
+            }
+            (Case::Int, DataType::Int(_))
+            | (Case::Float, DataType::Float(_))
+            | (Case::String, DataType::String(_))
+            | (Case::Bool, DataType::Bool(_))
+            | (Case::Unit, DataType::Unit) => true,
+            (Case::Enum(name), DataType::Variant(_)) => value
+                .template()
+                .is_some_and(|template| template.enum_name() == Some(name)),
+            _ => false,
+        }
+    }
+
+    // The order that cases are tested in, most specific first,
+    // where open records with more fields are more specific
+    fn priority(&self) -> (u8, usize) {
+        match self {
+            Case::Literal(_) => (0, 0),
+            Case::Template(_) => (1, 0),
+            Case::Tuple(_) => (2, 0),
+            Case::Record { open: false, .. } => (3, 0),
+            Case::Record { fields, open: true } => (4, usize::MAX - fields.len()),
+            Case::Int => (5, 0),
+            Case::Float => (6, 0),
+            Case::String => (7, 0),
+            Case::Bool => (8, 0),
+            Case::Enum(_) => (9, 0),
+            Case::Unit => (10, 0),
+        }
+    }
+
+    /// The case of the values that `pattern` tests for
+    pub(crate) fn of(pattern: &Pattern) -> Option<Case> {
+        match pattern {
+            Pattern::Wildcard | Pattern::Bind(_) => None,
+            Pattern::Literal(value) => Some(Case::Literal(value.clone())),
+            Pattern::Float => Some(Case::Float),
+            Pattern::Int => Some(Case::Int),
+            Pattern::String => Some(Case::String),
+            Pattern::Bool => Some(Case::Bool),
+            Pattern::Unit => Some(Case::Unit),
+            Pattern::Template(template, _) => Some(Case::Template(Rc::clone(template))),
+            Pattern::Enum(name) => Some(Case::Enum(name.clone())),
+            Pattern::Tuple(elements) => Some(Case::Tuple(elements.len())),
+            Pattern::Record { fields, open } => {
+                let mut fields: Vec<String> = fields.iter().map(|(name, _)| name.clone()).collect();
+                fields.sort();
+                Some(Case::Record {
+                    fields,
+                    open: *open,
+                })
+            }
+        }
+    }
+
+    /// Whether every value that falls into this case matches `pattern`
+    ///
+    /// Only the cases that a switch was built from can fall into it, so
+    /// a value in the `Int` case is never equal to a literal pattern of
+    /// the same switch.
+    pub(crate) fn implies(&self, pattern: &Pattern) -> bool {
+        match (self, pattern) {
+            (Case::Literal(literal), Pattern::Literal(other)) => literal == other,
+            (Case::Literal(literal), Pattern::Int) => literal.is_int(),
+            (Case::Literal(literal), Pattern::Float) => literal.is_float(),
+            (Case::Literal(literal), Pattern::Bool) => literal.is_bool(),
+            (Case::Literal(literal), Pattern::String) => literal.is_string(),
+            (Case::Int, Pattern::Int) | (Case::Float, Pattern::Float) => true,
+            (Case::String, Pattern::String) | (Case::Unit, Pattern::Unit) => true,
+            (Case::Bool, Pattern::Bool) => true,
+            (Case::Template(template), Pattern::Template(other, _)) => Rc::ptr_eq(template, other),
+            (Case::Template(template), Pattern::Enum(name)) => template.enum_name() == Some(name),
+            (Case::Enum(name), Pattern::Enum(other)) => name == other,
+            (Case::Tuple(length), Pattern::Tuple(elements)) => *length == elements.len(),
+            (
+                Case::Record { fields, open },
+                Pattern::Record {
+                    fields: other,
+                    open: other_open,
+                },
+            ) => {
+                other.iter().all(|(name, _)| fields.contains(name))
+                    && (*other_open || (!open && fields.len() == other.len()))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Display for Case {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Case::Literal(value) => write!(f, "{}", value),
+            Case::Template(template) => write!(f, "{}", template.qualified_name()),
+            Case::Tuple(length) => write!(f, "({})", vec!["_"; *length].join(", ")),
+            Case::Record { fields, open } => {
+                let mut fields = fields.clone();
+                if *open {
+                    fields.push("...".to_string());
+                }
+                write!(f, "{{{}}}", fields.join(", "))
+            }
+            Case::Int => write!(f, "Int"),
+            Case::Float => write!(f, "Float"),
+            Case::String => write!(f, "String"),
+            Case::Bool => write!(f, "Bool"),
+            Case::Enum(name) => write!(f, "{}", name),
+            Case::Unit => write!(f, "Unit"),
+        }
+    }
+}
+
+impl Decision {
+    /// Builds the decision tree for clauses with the given patterns,
+    /// which are tried in order
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::decision::Decision;
+    /// use lucent_lang::value::{Pattern, Value};
+    ///
+    /// let tree = Decision::new(&[
+    ///     vec![Pattern::Literal(Value::int(0))],
+    ///     vec![Pattern::Literal(Value::int(1))],
+    ///     vec![Pattern::Int],
+    /// ]);
+    ///
+    /// assert_eq!(Some(1), tree.select(&[Value::int(1)]));
+    /// assert_eq!(Some(2), tree.select(&[Value::int(5)]));
+    /// assert_eq!(None, tree.select(&[Value::float(0.5)]));
+    /// ```
+    pub fn new(clauses: &[Vec<Pattern>]) -> Self {
+        Decision::with_guards(clauses, &[])
+    }
+
+    /// Builds the decision tree for clauses with the given patterns,
+    /// where the clauses in `guarded` only run if their guard is true
+    pub fn with_guards(clauses: &[Vec<Pattern>], guarded: &[usize]) -> Self {
+        let rows = clauses
+            .iter()
+            .enumerate()
+            .map(|(clause, patterns)| {
+                let mut row = Row {
+                    clause,
+                    guarded: guarded.contains(&clause),
+                    columns: vec![],
+                };
+                for (index, pattern) in patterns.iter().enumerate() {
+                    row.add(vec![Step::Index(index)], pattern);
+                }
+                row
+            })
+            .collect();
+
+        Decision::build(rows)
+    }
+
+    fn build(rows: Vec<Row>) -> Self {
+        // The first clause matches once nothing is left to test
+        let path = match rows.first() {
+            None => return Decision::Fail,
+            Some(row) => match row.columns.first() {
+                None if row.guarded => {
+                    return Decision::Guard {
+                        clause: row.clause,
+                        otherwise: Rc::new(Decision::build(rows[1..].to_vec())),
+                    }
+                }
+                None => return Decision::Clause(row.clause),
+                Some((path, _)) => path.clone(),
+            },
+        };
+
+        let mut cases: Vec<Case> = vec![];
+        for row in rows.iter() {
+            if let Some(case) = row.pattern(&path).and_then(Case::of) {
+                if !cases.contains(&case) {
+                    cases.push(case);
+                }
+            }
+        }
+        add_record_unions(&mut cases);
+        cases.sort_by_key(Case::priority);
+
+        let cases = cases
+            .into_iter()
+            .map(|case| {
+                let rows = rows
+                    .iter()
+                    .filter_map(|row| row.specialize(&path, &case))
+                    .collect();
+                (case, Decision::build(rows))
+            })
+            .collect();
+        let default = rows
+            .iter()
+            .filter(|row| row.pattern(&path).is_none())
+            .cloned()
+            .collect();
+
+        Decision::Switch {
+            path,
+            cases,
+            default: Box::new(Decision::build(default)),
+        }
+    }
+
+    /// The index of the first clause whose patterns accept `arguments`,
+    /// whether or not its guard is true
+    pub fn select(&self, arguments: &[Value]) -> Option<usize> {
+        self.search(arguments).map(|(clause, _)| clause)
+    }
+
+    /// The index of the first clause whose patterns accept `arguments`,
+    /// along with the tree to search next if the clause's guard is false
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::decision::Decision;
+    /// use lucent_lang::value::{Pattern, Value};
+    ///
+    /// let tree = Decision::with_guards(&[vec![Pattern::Int], vec![Pattern::Wildcard]], &[0]);
+    ///
+    /// let (clause, otherwise) = tree.search(&[Value::int(2)]).unwrap();
+    /// assert_eq!(0, clause);
+    /// assert_eq!(Some((1, None)), otherwise.unwrap().search(&[Value::int(2)]));
+    /// ```
+    pub fn search(&self, arguments: &[Value]) -> Option<(usize, Option<&Rc<Decision>>)> {
+        let mut decision = self;
+        loop {
+            match decision {
+                Decision::Clause(clause) => return Some((*clause, None)),
+                Decision::Guard { clause, otherwise } => return Some((*clause, Some(otherwise))),
+                Decision::Fail => return None,
+                Decision::Switch {
+                    path,
+                    cases,
+                    default,
+                } => {
+                    let value = value_at(arguments, path)?;
+                    decision = cases
+                        .iter()
+                        .find(|(case, _)| case.contains(value))
+                        .map_or(&**default, |(_, decision)| decision);
+                }
+            }
+        }
+    }
+}
+
+/// A pattern that a single value is matched against, compiled
+/// into a tree along with where to find the values it binds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pattern: Pattern,
+    decision: Decision,
+    bindings: Vec<Path>,
+}
+
+impl Match {
+    pub fn new(pattern: Pattern) -> Self {
+        Match {
+            decision: Decision::new(&[vec![pattern.clone()]]),
+            bindings: binding_paths(std::slice::from_ref(&pattern)),
+            pattern,
+        }
+    }
+
+    pub fn pattern(&self) -> &Pattern {
+        &self.pattern
+    }
+
+    pub fn decision(&self) -> &Decision {
+        &self.decision
+    }
+
+    /// The values that the pattern binds, in the order that they
+    /// become local variables, or `None` if `value` doesn't match
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::decision::Match;
+    /// use lucent_lang::value::{Pattern, Value};
+    ///
+    /// let pair = Match::new(Pattern::Tuple(vec![
+    ///     Pattern::Bind(Box::new(Pattern::Int)),
+    ///     Pattern::Wildcard,
+    /// ]));
+    ///
+    /// let value = Value::tuple(vec![Value::int(1), Value::float(2.0)]);
+    /// assert_eq!(Some(vec![Value::int(1)]), pair.bind(&value));
+    /// assert_eq!(None, pair.bind(&Value::int(1)));
+    /// ```
+    pub fn bind(&self, value: &Value) -> Option<Vec<Value>> {
+        let arguments = std::slice::from_ref(value);
+        self.decision.select(arguments)?;
+        self.bindings
+            .iter()
+            .map(|path| value_at(arguments, path).cloned())
+            .collect()
+    }
+}
+
+/// Adds a case for the fields of each combination of open record cases,
+/// since a record with all of their fields belongs to every one of them
+/// and can match the patterns of any of them
+fn add_record_unions(cases: &mut Vec<Case>) {
+    let open: Vec<Vec<String>> = cases
+        .iter()
+        .filter_map(|case| match case {
+            Case::Record { fields, open: true } => Some(fields.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut index = 0;
+    while index < cases.len() {
+        if let Case::Record { fields, open: true } = cases[index].clone() {
+            for other in open.iter() {
+                let mut union = fields.clone();
+                union.extend(other.iter().cloned());
+                union.sort();
+                union.dedup();
+                let case = Case::Record {
+                    fields: union,
+                    open: true,
+                };
+                if !cases.contains(&case) {
+                    cases.push(case);
+                }
+            }
+        }
+        index += 1;
+    }
+}
+
+/// The value at `path`, if it exists
+pub fn value_at<'a>(arguments: &'a [Value], path: &[Step]) -> Option<&'a Value> {
+    let mut value = match path.first()? {
+        Step::Index(index) => arguments.get(*index)?,
+        Step::Field(_) => return None,
+    };
+    for step in path[1..].iter() {
+        value = match step {
+            Step::Index(index) => value.fields()?.get(*index)?,
+            Step::Field(name) => value.named_field(name)?,
+        };
+    }
+    Some(value)
+}
+
+/// The paths of the values bound by `patterns`, in
+/// the order that they become local variables
+pub fn binding_paths(patterns: &[Pattern]) -> Vec<Path> {
+    fn visit(path: Path, pattern: &Pattern, paths: &mut Vec<Path>) {
+        match pattern {
+            Pattern::Bind(pattern) => {
+                paths.push(path.clone());
+                visit(path, pattern, paths);
+            }
+            Pattern::Template(_, Some(fields)) | Pattern::Tuple(fields) => {
+                for (index, field) in fields.iter().enumerate() {
+                    let mut path = path.clone();
+                    path.push(Step::Index(index));
+                    visit(path, field, paths);
+                }
+            }
+            Pattern::Record { fields, .. } => {
+                for (name, field) in fields.iter() {
+                    let mut path = path.clone();
+                    path.push(Step::Field(name.clone()));
+                    visit(path, field, paths);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    let mut paths = vec![];
+    for (index, pattern) in patterns.iter().enumerate() {
+        visit(vec![Step::Index(index)], pattern, &mut paths);
+    }
+    paths
+}
+
+/// The tests that are left for one clause
+#[derive(Debug, Clone)]
+struct Row {
+    clause: usize,
+    // Whether the clause has a guard
+    guarded: bool,
+    // Patterns that match anything are left out
+    columns: Vec<(Path, Pattern)>,
+}
+
+impl Row {
+    fn add(&mut self, path: Path, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard => (),
+            Pattern::Bind(pattern) => self.add(path, pattern),
+            _ => self.columns.push((path, pattern.clone())),
+        }
+    }
+
+    fn pattern(&self, path: &[Step]) -> Option<&Pattern> {
+        self.columns
+            .iter()
+            .find(|(other, _)| other[..] == *path)
+            .map(|(_, pattern)| pattern)
+    }
+
+    /// The row that is left once the value at `path` is known to
+    /// be in `case`, or `None` if the row can't match the value
+    fn specialize(&self, path: &[Step], case: &Case) -> Option<Row> {
+        let index = match self
+            .columns
+            .iter()
+            .position(|(other, _)| other[..] == *path)
+        {
+            Some(index) => index,
+            None => return Some(self.clone()),
+        };
+        let pattern = &self.columns[index].1;
+        if !case.implies(pattern) {
+            return None;
+        }
+
+        let mut row = self.clone();
+        row.columns.remove(index);
+        match pattern {
+            Pattern::Template(_, Some(fields)) | Pattern::Tuple(fields) => {
+                for (index, field) in fields.iter().enumerate() {
+                    let mut path = path.to_vec();
+                    path.push(Step::Index(index));
+                    row.add(path, field);
+                }
+            }
+            Pattern::Record { fields, .. } => {
+                for (name, field) in fields.iter() {
+                    let mut path = path.to_vec();
+                    path.push(Step::Field(name.clone()));
+                    row.add(path, field);
+                }
+            }
+            _ => (),
+        }
+        Some(row)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(name: &str, fields: usize) -> Rc<Template> {
+        let fields = (0..fields).map(|index| index.to_string()).collect();
+        Rc::new(Template::variant("Shape", name, fields, vec![]))
+    }
+
+    #[test]
+    fn test_overlapping_cases() {
+        let tree = Decision::new(&[
+            vec![Pattern::Literal(Value::float(0.5))],
+            vec![Pattern::Int],
+            vec![Pattern::Float],
+            vec![Pattern::Wildcard],
+        ]);
+
+        assert_eq!(Some(0), tree.select(&[Value::float(0.5)]));
+        assert_eq!(Some(1), tree.select(&[Value::int(2)]));
+        assert_eq!(Some(2), tree.select(&[Value::float(2.0)]));
+        assert_eq!(Some(2), tree.select(&[Value::float(2.5)]));
+        assert_eq!(Some(3), tree.select(&[Value::unit()]));
+    }
+
+    #[test]
+    fn test_nested_patterns() {
+        let circle = variant("Circle", 1);
+        let square = variant("Square", 1);
+        let tree = Decision::new(&[
+            vec![Pattern::Template(
+                Rc::clone(&circle),
+                Some(vec![Pattern::Literal(Value::float(0.0))]),
+            )],
+            vec![Pattern::Template(
+                Rc::clone(&circle),
+                Some(vec![Pattern::Wildcard]),
+            )],
+            vec![Pattern::Enum("Shape".to_string())],
+        ]);
+
+        let point = Value::variant(Rc::clone(&circle), vec![Value::float(0.0)]);
+        assert_eq!(Some(0), tree.select(&[point]));
+        let circle = Value::variant(circle, vec![Value::float(1.0)]);
+        assert_eq!(Some(1), tree.select(&[circle]));
+        let square = Value::variant(square, vec![Value::float(1.0)]);
+        assert_eq!(Some(2), tree.select(&[square]));
+        assert_eq!(None, tree.select(&[Value::float(1.0)]));
+    }
+
+    #[test]
+    fn test_single_test_per_position() {
+        // Each argument is switched on once on the way to any clause
+        let tree = Decision::new(&[
+            vec![Pattern::Literal(Value::int(0)), Pattern::Wildcard],
+            vec![Pattern::Wildcard, Pattern::Literal(Value::int(0))],
+            vec![Pattern::Int, Pattern::Int],
+        ]);
+
+        fn check(decision: &Decision, seen: &mut Vec<Path>) {
+            if let Decision::Switch {
+                path,
+                cases,
+                default,
+            } = decision
+            {
+                assert!(!seen.contains(path));
+                seen.push(path.clone());
+                for (_, decision) in cases.iter() {
+                    check(decision, seen);
+                }
+                check(default, seen);
+                seen.pop();
+            }
+        }
+        check(&tree, &mut vec![]);
+
+        let select = |a: Value, b: Value| tree.select(&[a, b]);
+        assert_eq!(Some(0), select(Value::int(0), Value::float(0.5)));
+        assert_eq!(Some(1), select(Value::float(0.5), Value::int(0)));
+        assert_eq!(Some(2), select(Value::int(1), Value::int(1)));
+        assert_eq!(None, select(Value::int(1), Value::float(0.5)));
+    }
+
+    #[test]
+    fn test_guards() {
+        let tree = Decision::with_guards(
+            &[
+                vec![Pattern::Literal(Value::int(0))],
+                vec![Pattern::Int],
+                vec![Pattern::Wildcard],
+            ],
+            &[0, 1],
+        );
+
+        // The clauses after a guarded clause are still tried in order
+        let (clause, otherwise) = tree.search(&[Value::int(0)]).unwrap();
+        assert_eq!(0, clause);
+        let (clause, otherwise) = otherwise.unwrap().search(&[Value::int(0)]).unwrap();
+        assert_eq!(1, clause);
+        let next = otherwise.unwrap().search(&[Value::int(0)]);
+        assert_eq!(Some((2, None)), next);
+
+        let (clause, otherwise) = tree.search(&[Value::float(0.5)]).unwrap();
+        assert_eq!((2, None), (clause, otherwise));
+    }
+
+    #[test]
+    fn test_binding_paths() {
+        let circle = variant("Circle", 2);
+        let patterns = vec![
+            Pattern::Wildcard,
+            Pattern::Bind(Box::new(Pattern::Template(
+                circle,
+                Some(vec![
+                    Pattern::Wildcard,
+                    Pattern::Bind(Box::new(Pattern::Int)),
+                ]),
+            ))),
+        ];
+
+        assert_eq!(
+            vec![vec![Step::Index(1)], vec![Step::Index(1), Step::Index(1)]],
+            binding_paths(&patterns)
+        );
+    }
+}
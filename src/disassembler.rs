@@ -1,4 +1,6 @@
 use crate::chunk::{Chunk, OpCode};
+use crate::decision::{Decision, Step};
+use crate::value::Function;
 
 pub fn disassemble_chunk(chunk: &Chunk, header: &str) -> String {
     let result = format!("== {} ==\n", header);
@@ -6,6 +8,62 @@ pub fn disassemble_chunk(chunk: &Chunk, header: &str) -> String {
     disassemble_loop(chunk, 0, result.as_str())
 }
 
+/// Disassembles the decision tree of a function, followed by each of its clauses
+pub fn disassemble_function(function: &Function) -> String {
+    let mut result = format!("== {} ==\n", function.name());
+    let signature = |clause: usize| function.clauses()[clause].signature().to_string();
+    disassemble_decision(&signature, function.decision(), 0, &mut result);
+
+    function.clauses().iter().fold(result, |result, clause| {
+        let result = match clause.guard() {
+            Some(guard) => {
+                let header = format!("-- {} guard --\n", clause.signature());
+                disassemble_loop(guard, 0, &(result + &header))
+            }
+            None => result,
+        };
+        let header = format!("-- {} --\n", clause.signature());
+        disassemble_loop(clause.chunk(), 0, &(result + &header))
+    })
+}
+
+/// Writes a decision tree, naming each clause with `clause`
+fn disassemble_decision(
+    clause: &dyn Fn(usize) -> String,
+    decision: &Decision,
+    depth: usize,
+    result: &mut String,
+) {
+    match decision {
+        Decision::Clause(index) => result.push_str(&format!("{}\n", clause(*index))),
+        Decision::Guard {
+            clause: index,
+            otherwise,
+        } => {
+            result.push_str(&format!("{} if its guard is true\n", clause(*index)));
+            result.push_str(&format!("{}else => ", "  ".repeat(depth + 1)));
+            disassemble_decision(clause, otherwise, depth + 1, result);
+        }
+        Decision::Fail => result.push_str("FAIL\n"),
+        Decision::Switch {
+            path,
+            cases,
+            default,
+        } => {
+            let path: Vec<String> = path.iter().map(Step::to_string).collect();
+            result.push_str(&format!("SWITCH arg {}\n", path.join(".")));
+
+            let indent = "  ".repeat(depth + 1);
+            for (case, decision) in cases.iter() {
+                result.push_str(&format!("{}{} => ", indent, case));
+                disassemble_decision(clause, decision, depth + 1, result);
+            }
+            result.push_str(&format!("{}_ => ", indent));
+            disassemble_decision(clause, default, depth + 1, result);
+        }
+    }
+}
+
 fn disassemble_loop(chunk: &Chunk, instruction: usize, result: &str) -> String {
     if instruction >= chunk.get_size() {
         return String::from(result);
@@ -17,16 +75,10 @@ fn disassemble_loop(chunk: &Chunk, instruction: usize, result: &str) -> String {
 }
 
 pub fn disassemble_instruction(chunk: &Chunk, offset: usize, result: &str) -> (usize, String) {
-    let current_line = match chunk.get_line(offset) {
-        Some(line) => line,
-        None => 0,
-    };
+    let current_line = chunk.get_line(offset).unwrap_or_default();
 
     let previous_line = if offset > 0 {
-        match chunk.get_line(offset - 1) {
-            Some(line) => line,
-            None => 0,
-        }
+        chunk.get_line(offset - 1).unwrap_or_default()
     } else {
         0
     };
@@ -62,6 +114,69 @@ pub fn disassemble_instruction(chunk: &Chunk, offset: usize, result: &str) -> (u
         OpCode::Subtract => simple_instruction("OP_SUBTRACT", offset, result),
         OpCode::Multiply => simple_instruction("OP_MULTIPLY", offset, result),
         OpCode::Divide => simple_instruction("OP_DIVIDE", offset, result),
+        OpCode::Pop => simple_instruction("OP_POP", offset, result),
+        OpCode::GetLocal => byte_instruction("OP_GET_LOCAL", chunk, offset, result),
+        OpCode::GetGlobal => short_instruction("OP_GET_GLOBAL", chunk, offset, result),
+        OpCode::DefineGlobal => short_instruction("OP_DEFINE_GLOBAL", chunk, offset, result),
+        OpCode::Call => byte_instruction("OP_CALL", chunk, offset, result),
+        OpCode::EndScope => byte_instruction("OP_END_SCOPE", chunk, offset, result),
+        OpCode::Construct => {
+            let template = chunk
+                .get_short(offset + 1)
+                .and_then(|index| chunk.get_template(index));
+            match template {
+                Some(template) => (
+                    offset + 3,
+                    format!("{}{:<16} '{}'\n", result, "OP_CONSTRUCT", template.name()),
+                ),
+                None => (offset + 3, format!("{}UNDEFINED_TEMPLATE", result)),
+            }
+        }
+        OpCode::GetField => {
+            let name = chunk
+                .get_short(offset + 1)
+                .and_then(|index| chunk.get_name(index));
+            match name {
+                Some(name) => (
+                    offset + 3,
+                    format!("{}{:<16} '{}'\n", result, "OP_GET_FIELD", name),
+                ),
+                None => (offset + 3, format!("{}UNDEFINED_NAME", result)),
+            }
+        }
+        OpCode::Equal => simple_instruction("OP_EQUAL", offset, result),
+        OpCode::NotEqual => simple_instruction("OP_NOT_EQUAL", offset, result),
+        OpCode::Greater => simple_instruction("OP_GREATER", offset, result),
+        OpCode::GreaterEqual => simple_instruction("OP_GREATER_EQUAL", offset, result),
+        OpCode::Less => simple_instruction("OP_LESS", offset, result),
+        OpCode::LessEqual => simple_instruction("OP_LESS_EQUAL", offset, result),
+        OpCode::Tuple => byte_instruction("OP_TUPLE", chunk, offset, result),
+        OpCode::Record => {
+            let fields = chunk
+                .get_short(offset + 1)
+                .and_then(|index| chunk.get_record(index));
+            match fields {
+                Some(fields) => (
+                    offset + 3,
+                    format!("{}{:<16} {{{}}}\n", result, "OP_RECORD", fields.join(", ")),
+                ),
+                None => (offset + 3, format!("{}UNDEFINED_RECORD", result)),
+            }
+        }
+        OpCode::Destructure => match_instruction("OP_DESTRUCTURE", chunk, offset, result),
+        OpCode::Not => simple_instruction("OP_NOT", offset, result),
+        OpCode::Jump => jump_instruction("OP_JUMP", chunk, offset, result),
+        OpCode::JumpIfFalse => jump_instruction("OP_JUMP_IF_FALSE", chunk, offset, result),
+        OpCode::CheckBool => simple_instruction("OP_CHECK_BOOL", offset, result),
+        OpCode::Modulo => simple_instruction("OP_MODULO", offset, result),
+        OpCode::ToInt => simple_instruction("OP_TO_INT", offset, result),
+        OpCode::ToFloat => simple_instruction("OP_TO_FLOAT", offset, result),
+        OpCode::ToString => simple_instruction("OP_TO_STRING", offset, result),
+        OpCode::Interpolate => byte_instruction("OP_INTERPOLATE", chunk, offset, result),
+        OpCode::Is => match_instruction("OP_IS", chunk, offset, result),
+        OpCode::Print => simple_instruction("OP_PRINT", offset, result),
+        OpCode::ReadLine => simple_instruction("OP_READ_LINE", offset, result),
+        OpCode::Assert => simple_instruction("OP_ASSERT", offset, result),
         OpCode::UnexpectedEndOfChunk => (offset + 1, format!("{}UNEXPECTED_END_OF_CHUNK", result)),
         OpCode::Invalid(code) => (offset + 1, format!("{}UNKNOWN_OPCODE {}", result, code)),
     }
@@ -71,6 +186,66 @@ fn simple_instruction(name: &str, offset: usize, result: String) -> (usize, Stri
     (offset + 1, format!("{}{}\n", result, name))
 }
 
+fn byte_instruction(name: &str, chunk: &Chunk, offset: usize, result: String) -> (usize, String) {
+    match chunk.get_byte(offset + 1) {
+        Some(operand) => (
+            offset + 2,
+            format!("{}{:<16} {:4}\n", result, name, operand),
+        ),
+        None => (offset + 2, format!("{}UNEXPECTED_END_OF_CHUNK", result)),
+    }
+}
+
+fn short_instruction(name: &str, chunk: &Chunk, offset: usize, result: String) -> (usize, String) {
+    match chunk.get_short(offset + 1) {
+        Some(operand) => (
+            offset + 3,
+            format!("{}{:<16} {:4}\n", result, name, operand),
+        ),
+        None => (offset + 3, format!("{}UNEXPECTED_END_OF_CHUNK", result)),
+    }
+}
+
+/// Writes an instruction that matches a pattern,
+/// followed by the decision tree of the pattern
+fn match_instruction(name: &str, chunk: &Chunk, offset: usize, result: String) -> (usize, String) {
+    let pattern = chunk
+        .get_short(offset + 1)
+        .and_then(|index| chunk.get_match(index).map(|pattern| (index, pattern)));
+    match pattern {
+        Some((index, pattern)) => {
+            let mut tree = String::new();
+            disassemble_decision(&|_| "MATCH".to_string(), pattern.decision(), 0, &mut tree);
+            let tree: String = tree
+                .lines()
+                .map(|line| format!("{:10}{}\n", "", line))
+                .collect();
+            (
+                offset + 3,
+                format!("{}{:<16} {:4}\n{}", result, name, index, tree),
+            )
+        }
+        None => (offset + 3, format!("{}UNDEFINED_PATTERN", result)),
+    }
+}
+
+/// Writes a jump along with the offset of the instruction that it jumps to
+fn jump_instruction(name: &str, chunk: &Chunk, offset: usize, result: String) -> (usize, String) {
+    match chunk.get_short(offset + 1) {
+        Some(jump) => (
+            offset + 3,
+            format!(
+                "{}{:<16} {:04} -> {:04}\n",
+                result,
+                name,
+                offset,
+                offset + 3 + jump
+            ),
+        ),
+        None => (offset + 3, format!("{}UNEXPECTED_END_OF_CHUNK", result)),
+    }
+}
+
 fn constant_instruction(
     name: &str,
     is_long: bool,
@@ -113,12 +288,13 @@ fn constant_instruction(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::value::Value;
+    use crate::scanner::Span;
+    use crate::value::{Clause, Pattern, Value};
 
     #[test]
     fn test_disassemble_chunk() {
-        let chunk = Chunk::new().write_constant(Value::float(1.2), 1);
-        let chunk = chunk.write_chunk(&OpCode::Return, 1);
+        let chunk = Chunk::new().write_constant(Value::float(1.2), Span::on_line(1));
+        let chunk = chunk.write_chunk(&OpCode::Return, Span::on_line(1));
         assert_eq!(
             "\
 == test code ==
@@ -128,4 +304,57 @@ mod tests {
             disassemble_chunk(&chunk, "test code")
         );
     }
+
+    #[test]
+    fn test_disassemble_match() {
+        let mut chunk = Chunk::new();
+        let index = chunk.add_pattern(Pattern::Tuple(vec![Pattern::Int, Pattern::Wildcard]));
+        let chunk = chunk.write_operands(&OpCode::Is, &[0, index as u8], Span::on_line(1));
+        assert_eq!(
+            "\
+== test code ==
+0000 1    OP_IS               0
+          SWITCH arg 0
+            (_, _) => SWITCH arg 0.0
+              Int => MATCH
+              _ => FAIL
+            _ => FAIL
+",
+            disassemble_chunk(&chunk, "test code")
+        );
+    }
+
+    #[test]
+    fn test_disassemble_function() {
+        let clause = |signature: &str, pattern: Pattern| {
+            let chunk = Chunk::new().write_chunk(&OpCode::Return, Span::on_line(1));
+            Clause::new(signature, vec![pattern], chunk)
+        };
+        let function = Function::with_clauses(
+            "fib",
+            1,
+            vec![
+                clause("fib(0)", Pattern::Literal(Value::int(0))),
+                clause("fib(a is Int)", Pattern::Int),
+                clause("fib(_)", Pattern::Wildcard),
+            ],
+        );
+
+        assert_eq!(
+            "\
+== fib ==
+SWITCH arg 0
+  0 => fib(0)
+  Int => fib(a is Int)
+  _ => fib(_)
+-- fib(0) --
+0000 1    OP_RETURN
+-- fib(a is Int) --
+0000 1    OP_RETURN
+-- fib(_) --
+0000 1    OP_RETURN
+",
+            disassemble_function(&function)
+        );
+    }
 }
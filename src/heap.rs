@@ -0,0 +1,362 @@
+use std::cell::Cell;
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
+use std::ops::Deref;
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+use crate::value::{self, BoundMethod, DataType, Function, Template, Value};
+
+// The number of bytes that can be allocated before the first collection
+const FIRST_COLLECTION: usize = 1024 * 1024;
+// How much the heap can grow after a collection before the next one,
+// relative to the size of what survived it
+const GROWTH_FACTOR: usize = 2;
+
+/// A shared, immutable object that a value refers to
+///
+/// Objects that are made while a VM is running belong to its `Heap`,
+/// which frees them once nothing on the stack or in a global can reach
+/// them. Objects made anywhere else, such as the values of tests or
+/// the results that a VM returns, are reference counted instead, and
+/// never refer to objects in a heap.
+pub struct Obj<T>(Pointer<T>);
+
+enum Pointer<T> {
+    Counted(Rc<T>),
+    Traced(NonNull<Traced<T>>),
+}
+
+// An object in a heap, with the mark that the collector leaves on it
+// when it is reachable
+struct Traced<T> {
+    marked: Cell<bool>,
+    value: T,
+}
+
+impl<T> Obj<T> {
+    /// Creates a reference counted object
+    pub fn new(value: T) -> Self {
+        Obj(Pointer::Counted(Rc::new(value)))
+    }
+
+    /// Whether the object belongs to a heap
+    pub fn is_traced(&self) -> bool {
+        matches!(self.0, Pointer::Traced(_))
+    }
+
+    /// Marks the object if it belongs to a heap,
+    /// returning true if it wasn't marked already
+    fn mark(&self) -> bool {
+        match &self.0 {
+            Pointer::Counted(_) => false,
+            // SAFETY: The heap only frees objects during a collection,
+            // and only if they weren't reached while marking
+            Pointer::Traced(object) => !unsafe { object.as_ref() }.marked.replace(true),
+        }
+    }
+}
+
+impl<T> Deref for Obj<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match &self.0 {
+            Pointer::Counted(object) => object,
+            // SAFETY: A traced object is only reachable while the VM that owns
+            // its heap is running, and is only freed once it isn't reachable
+            Pointer::Traced(object) => &unsafe { object.as_ref() }.value,
+        }
+    }
+}
+
+impl<T> Clone for Obj<T> {
+    fn clone(&self) -> Self {
+        match &self.0 {
+            Pointer::Counted(object) => Obj(Pointer::Counted(Rc::clone(object))),
+            Pointer::Traced(object) => Obj(Pointer::Traced(*object)),
+        }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Obj<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Debug> Debug for Obj<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+// An object in a heap, of any type
+trait Object {
+    /// Removes the mark from the object, returning true if it was marked
+    fn unmark(&self) -> bool;
+}
+
+impl<T> Object for Traced<T> {
+    fn unmark(&self) -> bool {
+        self.marked.replace(false)
+    }
+}
+
+/// The objects that a VM makes while it runs, which are
+/// freed by a mark and sweep collector
+pub(crate) struct Heap {
+    // Every object in the heap, along with its size in bytes
+    objects: Vec<(NonNull<dyn Object>, usize)>,
+    allocated: usize,
+    next_collection: usize,
+    // The number of objects allocated since the last collection
+    allocations: usize,
+    // Whether to collect whenever anything has been allocated,
+    // to find objects that are freed while they are still in use
+    stress: bool,
+}
+
+impl Heap {
+    pub(crate) fn new() -> Self {
+        Heap {
+            objects: vec![],
+            allocated: 0,
+            next_collection: FIRST_COLLECTION,
+            allocations: 0,
+            stress: false,
+        }
+    }
+
+    /// Creates a heap that asks to be collected whenever anything has been allocated
+    pub(crate) fn stressed() -> Self {
+        let mut heap = Heap::new();
+        heap.stress = true;
+        heap
+    }
+
+    /// The number of bytes that the objects in the heap take up
+    pub(crate) fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    /// Whether enough has been allocated since the last collection to collect again
+    pub(crate) fn should_collect(&self) -> bool {
+        if self.stress {
+            self.allocations > 0
+        } else {
+            self.allocated >= self.next_collection
+        }
+    }
+
+    fn allocate<T: 'static>(&mut self, value: T, size: usize) -> Obj<T> {
+        let object = Box::new(Traced {
+            marked: Cell::new(false),
+            value,
+        });
+        let object = NonNull::from(Box::leak(object));
+        let size = mem::size_of::<Traced<T>>() + size;
+        self.objects.push((object, size));
+        self.allocated += size;
+        self.allocations += 1;
+        Obj(Pointer::Traced(object))
+    }
+
+    pub(crate) fn tuple(&mut self, elements: Vec<Value>) -> Value {
+        let size = elements.len() * mem::size_of::<Value>();
+        Value::new(None, DataType::Tuple(self.allocate(elements, size)))
+    }
+
+    /// Allocates a record with the given fields, which can be in any order
+    pub(crate) fn record(&mut self, mut fields: Vec<(String, Value)>) -> Value {
+        value::sort_fields(&mut fields);
+        let size = fields
+            .iter()
+            .map(|(name, _)| mem::size_of::<(String, Value)>() + name.len())
+            .sum();
+        Value::new(None, DataType::Record(self.allocate(fields, size)))
+    }
+
+    pub(crate) fn instance(&mut self, template: Rc<Template>, fields: Vec<Value>) -> Value {
+        let size = fields.len() * mem::size_of::<Value>();
+        let fields = self.allocate(fields, size);
+        Value::new(Some(template), DataType::Instance(fields))
+    }
+
+    pub(crate) fn variant(&mut self, template: Rc<Template>, payload: Vec<Value>) -> Value {
+        let size = payload.len() * mem::size_of::<Value>();
+        let payload = self.allocate(payload, size);
+        Value::new(Some(template), DataType::Variant(payload))
+    }
+
+    pub(crate) fn method(&mut self, receiver: Value, function: Rc<Function>) -> Value {
+        let method = self.allocate(BoundMethod::new(receiver, function), 0);
+        Value::new(None, DataType::Method(method))
+    }
+
+    /// Frees every object that can't be reached from `roots`
+    ///
+    /// # Safety
+    /// Any value that refers to an object in the heap
+    /// and is used after the collection must be reachable
+    /// from `roots`, or it will refer to freed memory.
+    pub(crate) unsafe fn collect<'a, I>(&mut self, roots: I)
+    where
+        I: IntoIterator<Item = &'a Value>,
+    {
+        // Values whose objects are marked but whose
+        // fields haven't been looked at yet
+        let mut gray: Vec<Value> = vec![];
+        for root in roots {
+            mark(root, &mut gray);
+        }
+        while let Some(value) = gray.pop() {
+            trace(&value, &mut gray);
+        }
+
+        let mut allocated = self.allocated;
+        self.objects.retain(|&(object, size)| {
+            // SAFETY: Objects are only freed here, and are
+            // removed from the heap once they are freed
+            if unsafe { object.as_ref() }.unmark() {
+                true
+            } else {
+                drop(unsafe { Box::from_raw(object.as_ptr()) });
+                allocated -= size;
+                false
+            }
+        });
+        self.allocated = allocated;
+        self.next_collection = (allocated * GROWTH_FACTOR).max(FIRST_COLLECTION);
+        self.allocations = 0;
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for &(object, _) in self.objects.iter() {
+            // SAFETY: Nothing can use the objects of a heap once it is dropped,
+            // since the VM that owns it detaches the values that it returns
+            drop(unsafe { Box::from_raw(object.as_ptr()) });
+        }
+    }
+}
+
+// Marks the object that `value` refers to, if it is in a heap,
+// saving the value to look at its fields later
+fn mark(value: &Value, gray: &mut Vec<Value>) {
+    let marked = match value.data() {
+        DataType::Instance(fields) | DataType::Variant(fields) | DataType::Tuple(fields) => {
+            fields.mark()
+        }
+        DataType::Record(fields) => fields.mark(),
+        DataType::Method(method) => method.mark(),
+        _ => false,
+    };
+    if marked {
+        gray.push(value.clone());
+    }
+}
+
+// Marks the objects that the fields of `value` refer to
+fn trace(value: &Value, gray: &mut Vec<Value>) {
+    match value.data() {
+        DataType::Instance(fields) | DataType::Variant(fields) | DataType::Tuple(fields) => {
+            for field in fields.iter() {
+                mark(field, gray);
+            }
+        }
+        DataType::Record(fields) => {
+            for (_, field) in fields.iter() {
+                mark(field, gray);
+            }
+        }
+        DataType::Method(method) => mark(method.receiver(), gray),
+        _ => {}
+    }
+}
+
+/// Copies `value` so that none of it is in a heap,
+/// so that it can outlive the VM that made it
+pub(crate) fn detach(value: &Value) -> Value {
+    let template = value.template().cloned();
+    let data = match value.data() {
+        DataType::Instance(fields) if fields.is_traced() => {
+            DataType::Instance(Obj::new(fields.iter().map(detach).collect()))
+        }
+        DataType::Variant(payload) if payload.is_traced() => {
+            DataType::Variant(Obj::new(payload.iter().map(detach).collect()))
+        }
+        DataType::Tuple(elements) if elements.is_traced() => {
+            DataType::Tuple(Obj::new(elements.iter().map(detach).collect()))
+        }
+        DataType::Record(fields) if fields.is_traced() => DataType::Record(Obj::new(
+            fields
+                .iter()
+                .map(|(name, field)| (name.clone(), detach(field)))
+                .collect(),
+        )),
+        DataType::Method(method) if method.is_traced() => DataType::Method(Obj::new(
+            BoundMethod::new(detach(method.receiver()), Rc::clone(method.function())),
+        )),
+        data => data.clone(),
+    };
+    Value::new(template, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        let mut heap = Heap::new();
+        let template = Rc::new(Template::new("Point", vec!["x".to_string()], vec![]));
+        let shared = heap.tuple(vec![Value::int(1)]);
+        let point = heap.instance(Rc::clone(&template), vec![shared.clone()]);
+        let pair = heap.record(vec![
+            ("b".to_string(), shared.clone()),
+            ("a".to_string(), point),
+        ]);
+        let function = Rc::new(Function::new("f", 1, Default::default()));
+        let method = heap.method(pair, function);
+        heap.tuple(vec![shared]);
+        assert_eq!(5, heap.objects.len());
+
+        // Objects reached more than once are only kept once
+        unsafe { heap.collect(vec![&method]) };
+        assert_eq!(4, heap.objects.len());
+        unsafe { heap.collect(vec![&method]) };
+        assert_eq!(4, heap.objects.len());
+
+        let detached = detach(&method);
+        unsafe { heap.collect(vec![]) };
+        assert!(heap.objects.is_empty());
+        assert_eq!(0, heap.allocated());
+        match detached.data() {
+            DataType::Method(method) => assert_eq!(
+                "{a: Point { x: (1,) }, b: (1,)}",
+                method.receiver().to_string()
+            ),
+            _ => panic!("expected a bound method"),
+        }
+    }
+
+    #[test]
+    fn test_stress() {
+        let mut heap = Heap::stressed();
+        assert!(!heap.should_collect());
+        let root = heap.tuple(vec![]);
+        assert!(heap.should_collect());
+        unsafe { heap.collect(vec![&root]) };
+        assert!(!heap.should_collect());
+        heap.tuple(vec![]);
+        assert!(heap.should_collect());
+    }
+}
@@ -1,6 +1,11 @@
+pub mod analysis;
+pub mod ast;
 pub mod chunk;
 pub mod compiler;
+pub mod cst;
+pub mod decision;
 pub mod disassembler;
+pub mod heap;
 pub mod scanner;
 pub mod value;
 pub mod virtual_machine;
@@ -23,21 +23,43 @@ fn main() {
 
 fn run_file(path: &str) {
     let source = read_file(path);
-    let vm = VM::new();
+    let mut vm = VM::new();
 
     let scanner = scanner::build_scanner(&source);
-    let chunk = compiler::compile(scanner);
+    let (chunk, warnings) = compiler::compile_program_with_warnings(scanner);
+    warnings
+        .iter()
+        .for_each(|warning| eprintln!("{}\n{}", warning, warning.span().underline(&source)));
+    let chunk = match chunk {
+        Ok(chunk) => chunk,
+        Err(errors) => {
+            errors
+                .iter()
+                .for_each(|error| eprintln!("{}\n{}", error, error.span().underline(&source)));
+            process::exit(65);
+        }
+    };
     let result = vm.interpret(&chunk);
 
     match result {
         VMResult::Okay(_) => process::exit(0),
         VMResult::CompileError => process::exit(65),
-        VMResult::RuntimeError => process::exit(70),
+        VMResult::RuntimeError(error) => {
+            eprintln!("{}", error);
+            if let Some(span) = error.span() {
+                eprintln!("{}", span.underline(&source));
+            }
+            process::exit(70);
+        }
     }
 }
 
-fn read_file(_path: &str) -> String {
-    let code = String::new();
-    fs::read_to_string(&code).unwrap();
-    code
+fn read_file(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("Could not read file \"{}\": {}", path, error);
+            process::exit(74);
+        }
+    }
 }
@@ -1,10 +1,11 @@
 use std::io::{self, Write};
 
-use lucent_lang::scanner::{self, TokenType};
-use lucent_lang::virtual_machine::VM;
+use lucent_lang::compiler;
+use lucent_lang::scanner;
+use lucent_lang::virtual_machine::{VMResult, VM};
 
 pub fn run() {
-    let _vm = VM::new();
+    let mut vm = VM::new();
 
     loop {
         print!(">> ");
@@ -21,20 +22,29 @@ pub fn run() {
             break;
         }
 
-        // Check to see if it is a complete statement
-        // and if it doesn't end in a semicolon, add one
-
-        let mut tokens = scanner::build_scanner(code);
+        if code.is_empty() {
+            continue;
+        }
 
-        loop {
-            if tokens.current_token().token_type() == TokenType::EOF {
-                break;
+        let chunk = match compiler::compile(scanner::build_scanner(code)) {
+            Ok(chunk) => chunk,
+            Err(errors) => {
+                errors
+                    .iter()
+                    .for_each(|error| eprintln!("{}\n{}", error, error.span().underline(code)));
+                continue;
             }
+        };
 
-            println!("{:?}", tokens.current_token());
-            tokens = tokens.scan_token();
+        match vm.interpret(&chunk) {
+            VMResult::Okay(value) => println!("{}", value),
+            VMResult::CompileError => eprintln!("Compile error"),
+            VMResult::RuntimeError(error) => {
+                eprintln!("{}", error);
+                if let Some(span) = error.span() {
+                    eprintln!("{}", span.underline(code));
+                }
+            }
         }
-        //let chunks = compiler::compile(tokens);
-        //let result = vm.interpret(chunks);
     }
 }
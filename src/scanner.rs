@@ -1,289 +1,649 @@
-pub fn build_scanner(code: &str) -> Scanner {
-    let scanner = Scanner {
-        current: Token::error("No available token".to_string(), "", 1, 1), // The tokens have not yet been scanned
-        next: Token::error("No available token".to_string(), "", 1, 1),
-        code: code.to_string(),
+use crate::compiler::CompileError;
+
+pub fn build_scanner(code: &str) -> Scanner<'_> {
+    new_scanner(code, FileId::default(), false)
+}
+
+/// Builds a scanner whose tokens are marked as coming from `file`
+pub fn build_file_scanner(code: &str, file: FileId) -> Scanner<'_> {
+    new_scanner(code, file, false)
+}
+
+/// Builds a scanner that also returns whitespace and comments
+/// as tokens, so that the lexemes of the tokens add up to `code`
+pub fn build_trivia_scanner(code: &str) -> Scanner<'_> {
+    new_scanner(code, FileId::default(), true)
+}
+
+fn new_scanner(code: &str, file: FileId, trivia: bool) -> Scanner<'_> {
+    let start = Position::new(0, 1, 1, 1);
+    let mut scanner = Scanner {
+        code,
+        file,
+        trivia,
+        current: Token::error("No available token".to_string(), "", Span::new(file, start, start)), // The first token has not yet been scanned
         index: 0,
         line: 1,
         column: 1,
+        utf16_column: 1,
+        interpolations: vec![],
+        errors: vec![],
+        finished: false,
     };
 
-    scanner.scan_token().scan_token()
+    scanner.current = scanner.scan_next();
+    scanner
 }
 
-pub struct Scanner {
+/// Scans the tokens of a piece of source code, one at a time
+///
+/// The scanner always holds the next token so that it can be
+/// peeked at. Once the `EOF` token has been returned by `next`,
+/// the scanner doesn't return any more tokens.
+///
+/// # Examples
+/// ```
+/// use lucent_lang::scanner::{self, TokenType};
+///
+/// let mut scanner = scanner::build_scanner("fib(1)");
+/// assert_eq!(TokenType::Identifier, scanner.peek().unwrap().token_type());
+///
+/// let tokens: Vec<TokenType> = scanner.map(|token| token.token_type()).collect();
+/// assert_eq!(
+///     vec![
+///         TokenType::Identifier,
+///         TokenType::LeftParen,
+///         TokenType::Int(1),
+///         TokenType::RightParen,
+///         TokenType::EOF,
+///     ],
+///     tokens
+/// );
+/// ```
+pub struct Scanner<'a> {
+    code: &'a str,
+    file: FileId,
+    // Whether whitespace and comments are returned as tokens
+    trivia: bool,
     current: Token,
-    next: Token,
-    code: String,
+    // The byte index of the next character to scan
     index: usize,
     line: u32,
     column: u32,
+    utf16_column: u32,
+    // The string interpolations that are being scanned,
+    // innermost last
+    interpolations: Vec<Interpolation>,
+    errors: Vec<CompileError>,
+    finished: bool,
+}
+
+/// An interpolated expression inside of a string
+struct Interpolation {
+    // The number of braces opened inside the expression
+    // that haven't been closed yet
+    depth: u32,
+    quotes: Quotes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quotes {
+    Single, // "..."
+    Triple, // """..."""
 }
 
-impl Scanner {
+impl Quotes {
+    fn delimiter(self) -> &'static str {
+        match self {
+            Quotes::Single => "\"",
+            Quotes::Triple => "\"\"\"",
+        }
+    }
+}
+
+impl<'a> Scanner<'a> {
+    /// The token that the next call to `next` will return,
+    /// or the `EOF` token once every token has been scanned
     pub fn current_token(&self) -> Token {
         self.current.clone()
     }
 
-    pub fn scan_token(self) -> Self {
-        let scanner = Scanner::skip_whitespace(self);
-        let index = scanner.index;
-        Scanner::scan_token_util(scanner, index)
+    pub fn peek(&self) -> Option<&Token> {
+        if self.finished {
+            None
+        } else {
+            Some(&self.current)
+        }
+    }
+
+    /// Every lexical error in the tokens scanned so far,
+    /// including the token that `peek` returns
+    pub fn errors(&self) -> &[CompileError] {
+        &self.errors
+    }
+
+    fn scan_next(&mut self) -> Token {
+        let token = self.scan_token();
+        if let TokenType::Error(message) = token.token_type() {
+            self.errors.push(CompileError::at(&token, &message));
+        }
+        token
     }
 
-    fn scan_token_util(scanner: Scanner, current: usize) -> Self {
-        if scanner.is_at_end(current) {
-            return Scanner {
-                current: scanner.next.clone(),
-                next: Token::new(TokenType::EOF, "", scanner.line, scanner.column),
-                index: current,
-                ..scanner
+    fn scan_token(&mut self) -> Token {
+        while let Some((t_type, start, position)) = self.scan_trivia() {
+            if self.trivia || !t_type.is_trivia() {
+                return self.make_token(t_type, start, position);
+            }
+        }
+
+        let start = self.index;
+        let position = self.current_position();
+
+        if self.is_at_end() {
+            return if self.interpolations.is_empty() {
+                self.make_token(TokenType::EOF, start, position)
+            } else {
+                self.interpolations.clear();
+                self.make_token(
+                    TokenType::Error("Unterminated string interpolation.".to_string()),
+                    start,
+                    position,
+                )
             };
         }
 
-        match scanner.get_char(current) {
-            '(' => scanner.add_token(TokenType::LeftParen, current, current),
-            ')' => scanner.add_token(TokenType::RightParen, current, current),
-            '{' => scanner.add_token(TokenType::LeftBrace, current, current),
-            '}' => scanner.add_token(TokenType::RightBrace, current, current),
-            ';' => scanner.add_token(TokenType::Semicolon, current, current),
-            ',' => scanner.add_token(TokenType::Comma, current, current),
-            '.' => scanner.add_token(TokenType::Dot, current, current),
-            '-' => scanner.add_token(TokenType::Minus, current, current),
-            '+' => scanner.add_token(TokenType::Plus, current, current),
-            '/' => scanner.add_token(TokenType::Slash, current, current),
-            '*' => scanner.add_token(TokenType::Star, current, current),
-            '?' => scanner.add_token(TokenType::Question, current, current),
-            ':' => scanner.add_token(TokenType::Colon, current, current),
-            '!' if scanner.match_char('=', current + 1) => {
-                scanner.add_token(TokenType::BangEqual, current, current + 1)
-            },
-            '!' => scanner.add_token(TokenType::Bang, current, current),
-            '=' if scanner.match_char('=', current + 1) => {
-                scanner.add_token(TokenType::EqualEqual, current, current + 1)
-            },
-            '=' => scanner.add_token(TokenType::Equal, current, current),
-            '<' if scanner.match_char('=', current + 1) => {
-                scanner.add_token(TokenType::LessEqual, current, current + 1)
-            },
-            '<' => scanner.add_token(TokenType::Less, current, current),
-            '>' if scanner.match_char('=', current + 1) => {
-                scanner.add_token(TokenType::GreaterEqual, current, current + 1)
+        let t_type = match self.advance() {
+            '(' => TokenType::LeftParen,
+            ')' => TokenType::RightParen,
+            '{' => {
+                if let Some(interpolation) = self.interpolations.last_mut() {
+                    interpolation.depth += 1;
+                }
+                TokenType::LeftBrace
+            }
+            '}' => match self.interpolations.last_mut() {
+                // This brace closes an interpolated expression,
+                // so the rest of the string follows it
+                Some(Interpolation { depth: 0, quotes }) => {
+                    let quotes = *quotes;
+                    self.interpolations.pop();
+                    return self.string(start, position, quotes, false, true);
+                }
+                Some(interpolation) => {
+                    interpolation.depth -= 1;
+                    TokenType::RightBrace
+                }
+                None => TokenType::RightBrace,
             },
-            '>' => scanner.add_token(TokenType::Greater, current, current),
-            '"' => scanner.string(current, current),
-            '1'...'9' => scanner.number(current, current, false),
-            'A'...'Z' | 'a'...'z' | '_' => scanner.identifier(current, current),
+            ';' => TokenType::Semicolon,
+            ',' => TokenType::Comma,
+            '.' if self.match_str("..") => TokenType::DotDotDot,
+            '.' => TokenType::Dot,
+            '-' => TokenType::Minus,
+            '+' => TokenType::Plus,
+            '/' => TokenType::Slash,
+            '*' => TokenType::Star,
+            '%' => TokenType::Percent,
+            '?' => TokenType::Question,
+            '$' => TokenType::Dollar,
+            ':' if self.match_str(":") => TokenType::ColonColon,
+            ':' => TokenType::Colon,
+            '!' if self.match_str("=") => TokenType::BangEqual,
+            '!' => TokenType::Bang,
+            '=' if self.match_str("=") => TokenType::EqualEqual,
+            '=' if self.match_str(">") => TokenType::EqualGreater,
+            '=' => TokenType::Equal,
+            '<' if self.match_str("=") => TokenType::LessEqual,
+            '<' => TokenType::Less,
+            '>' if self.match_str("=") => TokenType::GreaterEqual,
+            '>' => TokenType::Greater,
+            'r' if self.match_str("\"") => return self.start_string(start, position, true),
+            '"' => return self.start_string(start, position, false),
+            '0'..='9' => return self.number(start, position),
+            character if is_identifier_start(character) => return self.identifier(start, position),
             _ => {
-                let lexeme = scanner.get_lexeme(current, current).to_string();
-                scanner.error(
-                    current,
-                    current,
-                    "Invalid character: ".to_string() + lexeme.as_str(),
-                )
+                // Report a run of invalid characters as a single error
+                while !self.is_at_end() && !is_token_start(self.peek_char()) {
+                    self.advance();
+                }
+                let characters = &self.code[start..self.index];
+                let plural = if characters.chars().count() > 1 { "s" } else { "" };
+                TokenType::Error(format!("Invalid character{}: {}", plural, characters))
             }
-        }
-    }
+        };
 
-    fn skip_whitespace(scanner: Scanner) -> Self {
-        let (index, line, column) = (scanner.index, scanner.line, scanner.column);
-        Scanner::skip_ws_util(scanner, index, line, column)
+        self.make_token(t_type, start, position)
     }
 
-    fn skip_ws_util(scanner: Scanner, index: usize, line: u32, column: u32) -> Self {
-        if scanner.is_at_end(index) {
-            scanner.advance_to(index, line, column)
-        } else {
-            match scanner.get_char(index) {
-                ' ' => Scanner::skip_ws_util(scanner, index + 1, line, column + 1),
-                '\t' => Scanner::skip_ws_util(scanner, index + 1, line, column + 4),
-                '\r' => Scanner::skip_ws_util(scanner, index + 1, line, column + 1),
-                '\n' => Scanner::skip_ws_util(scanner, index + 1, line + 1, 1),
-                '/' => match scanner.get_char(index + 1) {
-                    '/' => Scanner::single_line_comment(scanner, index + 2, line, column + 2),
-                    '*' => Scanner::multi_line_comment(scanner, index + 2, line, column + 2, 1),
-                    _ => scanner.advance_to(index, line, column),
-                },
-                _ => scanner.advance_to(index, line, column),
+    /// Scans the whitespace or comment at the start of the rest of
+    /// the source, if there is one, without making it into a token
+    /// (which would copy it) since it is usually thrown away
+    fn scan_trivia(&mut self) -> Option<(TokenType, usize, Position)> {
+        let start = self.index;
+        let position = self.current_position();
+
+        let t_type = match self.peek_char() {
+            ' ' | '\t' | '\r' | '\n' if !self.is_at_end() => {
+                while matches!(self.peek_char(), ' ' | '\t' | '\r' | '\n') && !self.is_at_end() {
+                    self.advance();
+                }
+                TokenType::Whitespace
             }
-        }
+            '/' if self.rest().starts_with("//") => {
+                while !self.is_at_end() && self.peek_char() != '\n' {
+                    self.advance();
+                }
+                TokenType::LineComment
+            }
+            '/' if self.rest().starts_with("/*") => {
+                self.match_str("/*");
+                if self.multi_line_comment() {
+                    TokenType::BlockComment
+                } else {
+                    TokenType::Error("Unterminated comment.".to_string())
+                }
+            }
+            _ => return None,
+        };
+
+        Some((t_type, start, position))
     }
 
-    fn single_line_comment(scanner: Scanner, index: usize, line: u32, column: u32) -> Self {
-        if scanner.is_at_end(index) {
-            scanner.advance_to(index, line, column)
-        } else {
-            match scanner.get_char(index) {
-                '\n' => Scanner::skip_ws_util(scanner, index + 1, line + 1, 1),
-                '\t' => Scanner::single_line_comment(scanner, index + 1, line, column + 4),
-                _ => Scanner::single_line_comment(scanner, index + 1, line, column + 1),
+    /// Skips the rest of a comment after its opening `/*`,
+    /// returning whether it was closed
+    ///
+    /// Comments can contain nested comments.
+    fn multi_line_comment(&mut self) -> bool {
+        let mut nested = 1;
+
+        while nested > 0 && !self.is_at_end() {
+            if self.match_str("/*") {
+                nested += 1;
+            } else if self.match_str("*/") {
+                nested -= 1;
+            } else {
+                self.advance();
             }
         }
+
+        nested == 0
     }
 
-    fn multi_line_comment(
-        scanner: Scanner,
-        index: usize,
-        line: u32,
-        column: u32,
-        nested: u32,
-    ) -> Self {
-        if scanner.is_at_end(index) {
-            scanner.advance_to(index, line, column)
+    /// Starts scanning a string after its first quote
+    ///
+    /// Raw strings (`r"..."`) don't support escape sequences
+    /// or interpolation, and strings in triple quotes can
+    /// span multiple lines
+    fn start_string(&mut self, start: usize, position: Position, raw: bool) -> Token {
+        let quotes = if self.match_str("\"\"") {
+            Quotes::Triple
         } else {
-            match scanner.get_char(index) {
-                '/' => {
-                    if scanner.match_char('*', index + 1) {
-                        Scanner::multi_line_comment(
-                            scanner,
-                            index + 2,
-                            line,
-                            column + 2,
-                            nested + 1,
-                        )
-                    } else {
-                        Scanner::multi_line_comment(scanner, index + 1, line, column + 1, nested)
+            Quotes::Single
+        };
+        self.string(start, position, quotes, raw, false)
+    }
+
+    /// Scans the rest of a string, or the part of an interpolated
+    /// string that follows an interpolated expression if `continued`
+    fn string(
+        &mut self,
+        start: usize,
+        position: Position,
+        quotes: Quotes,
+        raw: bool,
+        continued: bool,
+    ) -> Token {
+        let mut value = String::new();
+        // The first invalid escape sequence in the string
+        let mut error = None;
+
+        loop {
+            if self.is_at_end() || (quotes == Quotes::Single && self.peek_char() == '\n') {
+                // The strings around an unterminated string can't be terminated either,
+                // so the tokens after it are no longer part of an interpolation
+                self.interpolations.clear();
+                return self.make_token(
+                    TokenType::Error("Unterminated string.".to_string()),
+                    start,
+                    position,
+                );
+            }
+
+            match self.peek_char() {
+                // A string in triple quotes ends at the last
+                // three quotes of a run of quotes
+                '"' if quotes == Quotes::Single || !self.rest().starts_with("\"\"\"\"") => {
+                    if self.match_str(quotes.delimiter()) {
+                        let t_type = match error {
+                            Some(message) => TokenType::Error(message),
+                            None if continued => TokenType::InterpolationEnd(value),
+                            None => TokenType::String(value),
+                        };
+                        return self.make_token(t_type, start, position);
                     }
+                    value.push(self.advance());
                 }
-                '*' => {
-                    if scanner.match_char('/', index + 1) && nested > 1 {
-                        Scanner::multi_line_comment(
-                            scanner,
-                            index + 2,
-                            line,
-                            column + 2,
-                            nested - 1,
-                        )
-                    } else if scanner.match_char('/', index + 1) {
-                        Scanner::skip_ws_util(scanner, index + 2, line, column + 2)
-                    } else {
-                        Scanner::multi_line_comment(scanner, index + 1, line, column + 1, nested)
-                    }
+                '$' if !raw && self.match_str("${") => {
+                    self.interpolations.push(Interpolation { depth: 0, quotes });
+                    let t_type = match error {
+                        Some(message) => TokenType::Error(message),
+                        None if continued => TokenType::InterpolationSegment(value),
+                        None => TokenType::InterpolationStart(value),
+                    };
+                    return self.make_token(t_type, start, position);
                 }
-                '\n' => Scanner::multi_line_comment(scanner, index + 1, line + 1, 1, nested),
-                '\t' => Scanner::multi_line_comment(scanner, index + 1, line, column + 4, nested),
-                _ => Scanner::multi_line_comment(scanner, index + 1, line, column + 1, nested),
+                '\\' if !raw => match self.escape_sequence() {
+                    Ok(character) => value.push(character),
+                    Err(message) => error = error.or(Some(message)),
+                },
+                _ => value.push(self.advance()),
             }
         }
     }
 
-    fn string(self, start: usize, current: usize) -> Self {
-        if self.match_char('"', current + 1) {
-            let string = self.get_lexeme(start + 1, current).to_string();
-            self.add_token(TokenType::String(string), start, current + 1)
-        } else {
-            self.string(start, current + 1)
+    /// Scans the escape sequence that starts at the next backslash
+    fn escape_sequence(&mut self) -> Result<char, String> {
+        let start = self.index;
+        self.advance();
+
+        match self.advance() {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            '\\' => Ok('\\'),
+            '"' => Ok('"'),
+            '$' => Ok('$'),
+            'u' => self.unicode_escape(start),
+            _ => Err(format!(
+                "Invalid escape sequence: {}",
+                &self.code[start..self.index]
+            )),
         }
     }
 
-    fn number(self, start: usize, current: usize, decimal_seen: bool) -> Self {
-        println!("Shouldn't enter number");
-        match self.get_char(current) {
-            '0'...'9' => self.number(start, current + 1, decimal_seen),
-            '.' if !decimal_seen && !self.is_at_end(current + 1) && self.is_digit(current + 1) => {
-                self.number(start, current + 1, true)
+    /// Scans the rest of an escape sequence of the form `\u{1F600}`,
+    /// which contains the hexadecimal value of a unicode code point
+    fn unicode_escape(&mut self, start: usize) -> Result<char, String> {
+        if self.match_str("{") {
+            let digits_start = self.index;
+            while self.peek_char().is_ascii_hexdigit() {
+                self.advance();
             }
-            _ => match self.get_lexeme(start, current - 1).parse() {
-                Ok(value) => {
-                    let line = self.line;
-                    let column = self.column + (current - start) as u32;
-                    self.add_token(TokenType::Number(value), start, current - 1)
-                        .advance_to(current, line, column)
-                }
-                Err(_) => {
-                    let lexeme = self.get_lexeme(start, current - 1).to_string();
-                    self.error(
-                        start,
-                        current - 1,
-                        "Unable to parse number: ".to_string() + &lexeme,
-                    )
+            let digits = &self.code[digits_start..self.index];
+
+            if self.match_str("}") && !digits.is_empty() && digits.len() <= 6 {
+                let character = u32::from_str_radix(digits, 16)
+                    .ok()
+                    .and_then(std::char::from_u32);
+                if let Some(character) = character {
+                    return Ok(character);
                 }
-            },
+            }
         }
+
+        Err(format!(
+            "Invalid unicode escape: {}",
+            &self.code[start..self.index]
+        ))
     }
-    
-    fn identifier(self, start: usize, index: usize) -> Self {
-        let next_char = self.get_char(index + 1);
-        if !next_char.is_ascii_alphanumeric() && next_char != '_' {
-            let t_type = match self.get_lexeme(start, index){
-                "_" => TokenType::Underscore,
-                "and" => TokenType::And,
-                "assert" => TokenType::Assert,
-                "else" => TokenType::Else,
-                "false" => TokenType::False,
-                "fn" => TokenType::Function,
-                "if" => TokenType::If,
-                "let" => TokenType::Let,
-                "or" => TokenType::Or,
-                "return" => TokenType::Return,
-                "self" => TokenType::SelfKey,
-                "true" => TokenType::True,
-                "where" => TokenType::Where,
-                _ => TokenType::Identifier,
-            };
-            self.add_token(t_type, start, index)
-        } else {
-            self.identifier(start, index + 1)
+
+    /// Scans the longest run of characters that could be part of
+    /// a number, so that malformed literals such as `123.0.1` or
+    /// `0b102` become a single error instead of several tokens
+    fn number(&mut self, start: usize, position: Position) -> Token {
+        let hexadecimal = self.code[start..].starts_with("0x") || self.code[start..].starts_with("0X");
+
+        loop {
+            let character = self.peek_char();
+            let previous = self.code[..self.index].chars().next_back();
+            let next_is_digit = self
+                .rest()
+                .chars()
+                .nth(1)
+                .is_some_and(|next| next.is_ascii_digit());
+
+            let continues = character.is_ascii_alphanumeric()
+                || character == '_'
+                || (character == '.' && next_is_digit)
+                || ((character == '+' || character == '-')
+                    && !hexadecimal
+                    && (previous == Some('e') || previous == Some('E'))
+                    && next_is_digit);
+
+            if self.is_at_end() || !continues {
+                break;
+            }
+            self.advance();
         }
+
+        let t_type = match parse_number(&self.code[start..self.index]) {
+            Ok(t_type) => t_type,
+            Err(message) => TokenType::Error(message),
+        };
+        self.make_token(t_type, start, position)
     }
 
-    fn get_char(&self, index: usize) -> char {
-        if self.is_at_end(index) {
-            '\u{0}'
-        } else {
-            self.code[index..=index].chars().next().unwrap()
+    fn identifier(&mut self, start: usize, position: Position) -> Token {
+        while !self.is_at_end() && is_identifier_continue(self.peek_char()) {
+            self.advance();
         }
+
+        let t_type = match &self.code[start..self.index] {
+            "_" => TokenType::Underscore,
+            "and" => TokenType::And,
+            "assert" => TokenType::Assert,
+            "define" => TokenType::Define,
+            "else" => TokenType::Else,
+            "enum" => TokenType::Enum,
+            "false" => TokenType::False,
+            "fn" => TokenType::Function,
+            "if" => TokenType::If,
+            "impure" => TokenType::Impure,
+            "is" => TokenType::Is,
+            "let" => TokenType::Let,
+            "match" => TokenType::Match,
+            "or" => TokenType::Or,
+            "return" => TokenType::Return,
+            "self" => TokenType::SelfKey,
+            "struct" => TokenType::Struct,
+            "trait" => TokenType::Trait,
+            "true" => TokenType::True,
+            "where" => TokenType::Where,
+            _ => TokenType::Identifier,
+        };
+        self.make_token(t_type, start, position)
     }
 
-    fn match_char(&self, character: char, index: usize) -> bool {
-        !self.is_at_end(index) && self.get_char(index) == character
+    /// Consumes the next character, keeping
+    /// track of the line and column
+    fn advance(&mut self) -> char {
+        let character = self.peek_char();
+        if self.is_at_end() {
+            return character;
+        }
+
+        self.index += character.len_utf8();
+        match character {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
+                self.utf16_column = 1;
+            }
+            '\t' => {
+                self.column += 4;
+                self.utf16_column += 1;
+            }
+            _ => {
+                self.column += 1;
+                self.utf16_column += character.len_utf16() as u32;
+            }
+        }
+        character
     }
 
-    fn get_lexeme(&self, start: usize, end: usize) -> &str {
-        if !self.is_at_end(end) {
-            &self.code[start..=end]
+    /// Consumes `expected` if the rest of the source starts with it
+    fn match_str(&mut self, expected: &str) -> bool {
+        if self.rest().starts_with(expected) {
+            expected.chars().for_each(|_| {
+                self.advance();
+            });
+            true
         } else {
-            self.get_lexeme(start, end - 1)
+            false
         }
     }
 
-    fn advance_to(self, index: usize, line: u32, column: u32) -> Self {
-        Scanner {
-            index,
-            line,
-            column,
-            ..self
-        }
+    fn peek_char(&self) -> char {
+        self.rest().chars().next().unwrap_or('\u{0}')
     }
 
-    fn add_token(self, t_type: TokenType, start: usize, current: usize) -> Self {
-        let lexeme = self.get_lexeme(start, current);
-        Scanner {
-            current: self.next.clone(),
-            next: Token::new(t_type, lexeme, self.line, self.column),
-            index: current + 1,
-            column: self.column + (current - start) as u32 + 1,
-            ..self
-        }
+    fn rest(&self) -> &'a str {
+        &self.code[self.index..]
     }
 
-    fn error(self, start: usize, end: usize, message: String) -> Self {
-        let lexeme = self.get_lexeme(start, end);
-        Scanner {
-            current: self.next.clone(),
-            next: Token::error(message, lexeme, self.line, self.column),
-            index: end + 1,
-            column: self.column + (end - start) as u32,
-            ..self
+    fn current_position(&self) -> Position {
+        Position::new(self.index, self.line, self.column, self.utf16_column)
+    }
+
+    fn make_token(&self, t_type: TokenType, start: usize, position: Position) -> Token {
+        let span = Span::new(self.file, position, self.current_position());
+        Token::new(t_type, &self.code[start..self.index], span)
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.index >= self.code.len()
+    }
+}
+
+impl Iterator for Scanner<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.finished {
+            return None;
         }
+
+        let next = if self.current.token_type() == TokenType::EOF {
+            self.finished = true;
+            self.current.clone()
+        } else {
+            self.scan_next()
+        };
+        Some(std::mem::replace(&mut self.current, next))
     }
+}
+
+/// Whether a character can start a token or whitespace,
+/// so that scanning can continue from it after an error
+fn is_token_start(character: char) -> bool {
+    matches!(character, ' ' | '\t' | '\r' | '\n' | '"')
+        || "(){};,.-+/*%?$:!=<>".contains(character)
+        || character.is_ascii_digit()
+        || is_identifier_start(character)
+}
 
-    fn is_digit(&self, index: usize) -> bool {
-        self.get_char(index).is_ascii_digit()
+/// Identifiers may start with any alphabetic character
+fn is_identifier_start(character: char) -> bool {
+    character == '_' || character.is_alphabetic()
+}
+
+fn is_identifier_continue(character: char) -> bool {
+    character == '_' || character.is_alphanumeric()
+}
+
+/// Parses the lexeme of a number literal into an `Int` or `Float` token
+fn parse_number(lexeme: &str) -> Result<TokenType, String> {
+    let prefix = lexeme.get(..2).map(str::to_ascii_lowercase);
+    let (radix, digits, name) = match prefix.as_deref() {
+        Some("0x") => (16, &lexeme[2..], "hexadecimal"),
+        Some("0o") => (8, &lexeme[2..], "octal"),
+        Some("0b") => (2, &lexeme[2..], "binary"),
+        _ => (10, lexeme, "decimal"),
+    };
+
+    if digits.is_empty() {
+        return Err(format!("Expect digits after '{}' in number literal.", &lexeme[..2]));
+    }
+
+    let separators_valid = digits.char_indices().all(|(index, character)| {
+        character != '_'
+            || (index > 0
+                && digits[..index].ends_with(|c: char| c.is_digit(radix))
+                && digits[index + 1..].starts_with(|c: char| c.is_digit(radix)))
+    });
+    if !separators_valid {
+        return Err(format!(
+            "Digit separators must be between two digits: {}",
+            lexeme
+        ));
     }
+    let digits = digits.replace('_', "");
 
-    fn is_at_end(&self, index: usize) -> bool {
-        index >= self.code.len()
+    if radix != 10 {
+        return match digits.chars().find(|character| !character.is_digit(radix)) {
+            Some(character) => Err(format!(
+                "Invalid digit '{}' in {} literal: {}",
+                character, name, lexeme
+            )),
+            None => i64::from_str_radix(&digits, radix)
+                .map(TokenType::Int)
+                .map_err(|_| format!("Integer literal is too large: {}", lexeme)),
+        };
+    }
+
+    let integer_end = digits
+        .find(|character: char| !character.is_ascii_digit())
+        .unwrap_or(digits.len());
+    let rest = &digits[integer_end..];
+    if integer_end > 1 && digits.starts_with('0') {
+        return Err(format!(
+            "Leading zeros are not allowed in number literals: {}",
+            lexeme
+        ));
+    }
+
+    // The scanner only includes a '.' if a digit follows it
+    let rest = match rest.strip_prefix('.') {
+        Some(fraction) => fraction.trim_start_matches(|character: char| character.is_ascii_digit()),
+        None => rest,
+    };
+    if rest.starts_with('.') {
+        return Err(format!(
+            "A number literal can only have one decimal point: {}",
+            lexeme
+        ));
+    }
+
+    let rest = match rest.strip_prefix(|character| character == 'e' || character == 'E') {
+        Some(exponent) => {
+            let exponent = exponent.trim_start_matches(['+', '-']);
+            if !exponent.starts_with(|character: char| character.is_ascii_digit()) {
+                return Err(format!(
+                    "Expect digits in the exponent of number literal: {}",
+                    lexeme
+                ));
+            }
+            exponent.trim_start_matches(|character: char| character.is_ascii_digit())
+        }
+        None => rest,
+    };
+    if let Some(character) = rest.chars().next() {
+        return Err(format!(
+            "Invalid character '{}' in number literal: {}",
+            character, lexeme
+        ));
+    }
+
+    if integer_end == digits.len() {
+        digits
+            .parse()
+            .map(TokenType::Int)
+            .map_err(|_| format!("Integer literal is too large: {}", lexeme))
+    } else {
+        match digits.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(TokenType::Float(value)),
+            _ => Err(format!("Float literal is too large: {}", lexeme)),
+        }
     }
 }
 
@@ -291,26 +651,23 @@ impl Scanner {
 pub struct Token {
     t_type: TokenType,
     lexeme: String,
-    line: u32,
-    column: u32,
+    span: Span,
 }
 
 impl Token {
-    fn new(t_type: TokenType, lexeme: &str, line: u32, column: u32) -> Self {
+    pub(crate) fn new(t_type: TokenType, lexeme: &str, span: Span) -> Self {
         Token {
             t_type,
             lexeme: lexeme.to_string(),
-            line,
-            column,
+            span,
         }
     }
 
-    fn error(message: String, lexeme: &str, line: u32, column: u32) -> Self {
+    pub(crate) fn error(message: String, lexeme: &str, span: Span) -> Self {
         Token {
             t_type: TokenType::Error(message),
             lexeme: lexeme.to_string(),
-            line,
-            column,
+            span,
         }
     }
 
@@ -322,6 +679,154 @@ impl Token {
         self.lexeme.clone()
     }
 
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn position(&self) -> Position {
+        self.span.start
+    }
+
+    pub fn line(&self) -> u32 {
+        self.span.start.line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.span.start.column
+    }
+
+    pub fn utf16_column(&self) -> u32 {
+        self.span.start.utf16_column
+    }
+
+    pub fn offset(&self) -> usize {
+        self.span.start.offset
+    }
+}
+
+/// Identifies the source file that a span is in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct FileId(pub u32);
+
+/// A range of source code, from the first character
+/// of `start` up to but not including `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    file: FileId,
+    start: Position,
+    end: Position,
+}
+
+impl Span {
+    pub(crate) fn new(file: FileId, start: Position, end: Position) -> Self {
+        Span { file, start, end }
+    }
+
+    /// The span from the start of this span to the end of `other`
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    pub fn start(&self) -> Position {
+        self.start
+    }
+
+    pub fn end(&self) -> Position {
+        self.end
+    }
+
+    pub fn len(&self) -> usize {
+        self.end.offset - self.start.offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Shows the first line of the span in `source`,
+    /// with the span underlined
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::{compiler, scanner};
+    ///
+    /// let source = "1 +\n  2 € 3";
+    /// let errors = compiler::compile(scanner::build_scanner(source)).unwrap_err();
+    ///
+    /// assert_eq!("2 |   2 € 3\n  |     ^\n", errors[0].span().underline(source));
+    /// ```
+    pub fn underline(&self, source: &str) -> String {
+        let start = self.start.offset.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |index| start + index);
+        let line = source[line_start..line_end].trim_end_matches('\r');
+
+        // Keep any tabs so that the underline lines up with the code
+        let indent: String = source[line_start..start]
+            .chars()
+            .map(|character| if character == '\t' { '\t' } else { ' ' })
+            .collect();
+        let end = self.end.offset.clamp(start, line_end);
+        let width = source[start..end].chars().count().max(1);
+
+        let number = self.start.line.to_string();
+        let gutter = " ".repeat(number.len());
+        format!(
+            "{} | {}\n{} | {}{}\n",
+            number,
+            line,
+            gutter,
+            indent,
+            "^".repeat(width)
+        )
+    }
+}
+
+#[cfg(test)]
+impl Span {
+    /// An empty span at the start of `line`, for tests
+    /// that don't come from scanning source code
+    pub(crate) fn on_line(line: u32) -> Self {
+        let position = Position::new(0, line, 1, 1);
+        Span::new(FileId::default(), position, position)
+    }
+}
+
+/// A location in the source, between two characters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    // The byte offset from the start of the source
+    offset: usize,
+    line: u32,
+    // The column in characters, counting a tab as 4 columns
+    column: u32,
+    // The column in UTF-16 code units, as used by editors
+    utf16_column: u32,
+}
+
+impl Position {
+    pub(crate) fn new(offset: usize, line: u32, column: u32, utf16_column: u32) -> Self {
+        Position {
+            offset,
+            line,
+            column,
+            utf16_column,
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
     pub fn line(&self) -> u32 {
         self.line
     }
@@ -329,6 +834,10 @@ impl Token {
     pub fn column(&self) -> u32 {
         self.column
     }
+
+    pub fn utf16_column(&self) -> u32 {
+        self.utf16_column
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -341,18 +850,23 @@ pub enum TokenType {
     Colon,
     Comma,
     Dot,
+    Dollar,
     Minus,
+    Percent,
     Plus,
     Semicolon,
     Slash,
     Star,
     Question,
 
-    // One or two character tokens.
+    // One, two or three character tokens.
     Bang,
     BangEqual,
+    ColonColon,
+    DotDotDot,
     Equal,
     EqualEqual,
+    EqualGreater,
     Greater,
     GreaterEqual,
     Less,
@@ -361,52 +875,80 @@ pub enum TokenType {
     // Literals.
     Identifier,
     String(String),
-    Number(f64),
+    Int(i64),
+    Float(f64),
+
+    // String interpolation, eg. "a ${b} c ${d} e" is scanned as
+    // InterpolationStart("a ") b InterpolationSegment(" c ") d InterpolationEnd(" e")
+    InterpolationStart(String),
+    InterpolationSegment(String),
+    InterpolationEnd(String),
 
     // Keywords.
     Underscore,
     And,
     Assert,
+    Define,
     Else,
+    Enum,
     False,
     Function,
     If,
+    Impure,
     Is,
     Let,
+    Match,
     Or,
     Return,
     SelfKey, // Can't use 'Self'
+    Struct,
+    Trait,
     True,
     Where,
 
+    // Trivia, only scanned by a trivia scanner.
+    Whitespace,
+    LineComment,
+    BlockComment,
+
     Error(String),
     EOF,
 }
 
+impl TokenType {
+    pub fn is_comment(&self) -> bool {
+        matches!(self, TokenType::LineComment | TokenType::BlockComment)
+    }
+
+    pub fn is_trivia(&self) -> bool {
+        *self == TokenType::Whitespace || self.is_comment()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn whitespace_skipping() {
-        let scanner = build_scanner(
+        let mut scanner = build_scanner(
             " ;\t\r\
 // This = should be ignored
 /* This /* is /* a */ nested */ comment */;",
         );
 
         assert_eq!(
-            Token::new(TokenType::Semicolon, ";", 1, 2),
+            token(TokenType::Semicolon, ";", 1, 2, 1),
             scanner.current_token()
         );
-        let scanner = scanner.scan_token();
+        scanner.next();
         assert_eq!(
-            Token::new(TokenType::Semicolon, ";", 2, 43),
+            token(TokenType::Semicolon, ";", 2, 43, 74),
             scanner.current_token()
         );
-        let scanner = scanner.scan_token();
+        scanner.next();
         assert_eq!(
-            Token::new(TokenType::EOF, "", 2, 44),
+            token(TokenType::EOF, "", 2, 44, 75),
             scanner.current_token()
         );
     }
@@ -414,84 +956,561 @@ mod tests {
     #[test]
     fn literal_scanning() {
         // String
-        let scanner = build_scanner(" \"Hello\" \"\" ");
+        let mut scanner = build_scanner(" \"Hello\" \"\" ");
         assert_eq!(
-            Token::new(TokenType::String("Hello".to_string()), "\"Hello\"", 1, 2),
+            token(TokenType::String("Hello".to_string()), "\"Hello\"", 1, 2, 1),
             scanner.current_token()
         );
 
-        let scanner = scanner.scan_token();
+        scanner.next();
         assert_eq!(
-            Token::new(TokenType::String("".to_string()), "\"\"", 1, 10),
+            token(TokenType::String("".to_string()), "\"\"", 1, 10, 9),
             scanner.current_token()
         );
 
         // Int
-        let scanner = build_scanner("123.");
+        let mut scanner = build_scanner("123.");
         assert_eq!(
-            Token::new(TokenType::Number(123.0), "123", 1, 1),
+            token(TokenType::Int(123), "123", 1, 1, 0),
             scanner.current_token()
         );
 
-        let scanner = scanner.scan_token();
+        scanner.next();
         assert_eq!(
-            Token::new(TokenType::Dot, ".", 1, 4),
+            token(TokenType::Dot, ".", 1, 4, 3),
             scanner.current_token()
         );
 
+        // Float
         let scanner = build_scanner("123.0");
         assert_eq!(
-            Token::new(TokenType::Number(123.0), "123.0", 1, 1),
+            token(TokenType::Float(123.0), "123.0", 1, 1, 0),
             scanner.current_token()
         );
+    }
 
-        let scanner = build_scanner("123.0.1");
+    #[test]
+    fn number_scanning() {
+        let scanner = build_scanner(
+            "0 fib(0) 1_000_000 0x1F 0XfF_fF 0b1010 0o17 1e3 2.5E-3 1_0.0_1e+1_0 9223372036854775807",
+        );
+        assert_tokens(
+            scanner,
+            vec![
+                TokenType::Int(0),
+                TokenType::Identifier,
+                TokenType::LeftParen,
+                TokenType::Int(0),
+                TokenType::RightParen,
+                TokenType::Int(1_000_000),
+                TokenType::Int(0x1F),
+                TokenType::Int(0xFFFF),
+                TokenType::Int(0b1010),
+                TokenType::Int(0o17),
+                TokenType::Float(1e3),
+                TokenType::Float(2.5e-3),
+                TokenType::Float(10.01e10),
+                TokenType::Int(i64::MAX),
+            ],
+        );
+
+        let scanner = build_scanner("a-1 1..2 1.e");
+        assert_tokens(
+            scanner,
+            vec![
+                TokenType::Identifier,
+                TokenType::Minus,
+                TokenType::Int(1),
+                TokenType::Int(1),
+                TokenType::Dot,
+                TokenType::Dot,
+                TokenType::Int(2),
+                TokenType::Int(1),
+                TokenType::Dot,
+                TokenType::Identifier,
+            ],
+        );
+    }
+
+    #[test]
+    fn malformed_numbers() {
+        let errors = vec![
+            ("123.0.1", "A number literal can only have one decimal point: 123.0.1"),
+            ("0b102", "Invalid digit '2' in binary literal: 0b102"),
+            ("0o8", "Invalid digit '8' in octal literal: 0o8"),
+            ("0x", "Expect digits after '0x' in number literal."),
+            ("0xg", "Invalid digit 'g' in hexadecimal literal: 0xg"),
+            ("1__0", "Digit separators must be between two digits: 1__0"),
+            ("1_", "Digit separators must be between two digits: 1_"),
+            ("1_.5", "Digit separators must be between two digits: 1_.5"),
+            ("007", "Leading zeros are not allowed in number literals: 007"),
+            ("1e", "Expect digits in the exponent of number literal: 1e"),
+            ("12abc", "Invalid character 'a' in number literal: 12abc"),
+            ("9223372036854775808", "Integer literal is too large: 9223372036854775808"),
+            ("1e999", "Float literal is too large: 1e999"),
+        ];
+
+        errors.into_iter().for_each(|(code, message)| {
+            let mut scanner = build_scanner(code);
+            assert_eq!(
+                error_token(message.to_string(), code, 1, 1, 0),
+                scanner.current_token()
+            );
+            scanner.next();
+            assert_eq!(TokenType::EOF, scanner.current_token().token_type());
+        });
+    }
+
+    #[test]
+    fn identifier_recognition() {
+        let mut scanner = build_scanner("abc and def");
         assert_eq!(
-            Token::new(TokenType::Number(123.0), "123.0", 1, 1),
+            token(TokenType::Identifier, "abc", 1, 1, 0),
             scanner.current_token()
         );
+        
+        scanner.next();
+        assert_eq!(
+            token(TokenType::And, "and", 1, 5, 4),
+            scanner.current_token()
+        );
+        
+        scanner.next();
+        assert_eq!(
+            token(TokenType::Identifier, "def", 1, 9, 8),
+            scanner.current_token()
+        )
+    }
+
+    #[test]
+    fn keyword_recognition() {
+        let scanner = build_scanner(
+            "struct enum define trait impure is match fn let where self _ structure",
+        );
+        assert_tokens(
+            scanner,
+            vec![
+                TokenType::Struct,
+                TokenType::Enum,
+                TokenType::Define,
+                TokenType::Trait,
+                TokenType::Impure,
+                TokenType::Is,
+                TokenType::Match,
+                TokenType::Function,
+                TokenType::Let,
+                TokenType::Where,
+                TokenType::SelfKey,
+                TokenType::Underscore,
+                TokenType::Identifier,
+            ],
+        );
+    }
+
+    #[test]
+    fn punctuation_scanning() {
+        let scanner = build_scanner("IO::println $ {x, ...} .. a => b % c : = ==");
+        assert_tokens(
+            scanner,
+            vec![
+                TokenType::Identifier,
+                TokenType::ColonColon,
+                TokenType::Identifier,
+                TokenType::Dollar,
+                TokenType::LeftBrace,
+                TokenType::Identifier,
+                TokenType::Comma,
+                TokenType::DotDotDot,
+                TokenType::RightBrace,
+                TokenType::Dot,
+                TokenType::Dot,
+                TokenType::Identifier,
+                TokenType::EqualGreater,
+                TokenType::Identifier,
+                TokenType::Percent,
+                TokenType::Identifier,
+                TokenType::Colon,
+                TokenType::Equal,
+                TokenType::EqualEqual,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let scanner = build_scanner("\"This string has ${1 + 2} string interpolation\"");
+        assert_tokens(
+            scanner,
+            vec![
+                TokenType::InterpolationStart("This string has ".to_string()),
+                TokenType::Int(1),
+                TokenType::Plus,
+                TokenType::Int(2),
+                TokenType::InterpolationEnd(" string interpolation".to_string()),
+            ],
+        );
+
+        let scanner = build_scanner("\"This string ${\"has mixed ${\"interpolation\"}\"}\"");
+        assert_tokens(
+            scanner,
+            vec![
+                TokenType::InterpolationStart("This string ".to_string()),
+                TokenType::InterpolationStart("has mixed ".to_string()),
+                TokenType::String("interpolation".to_string()),
+                TokenType::InterpolationEnd("".to_string()),
+                TokenType::InterpolationEnd("".to_string()),
+            ],
+        );
+
+        let scanner = build_scanner("\"x: ${ point(1) }, y: ${ {y: 2}.y }\"");
+        assert_tokens(
+            scanner,
+            vec![
+                TokenType::InterpolationStart("x: ".to_string()),
+                TokenType::Identifier,
+                TokenType::LeftParen,
+                TokenType::Int(1),
+                TokenType::RightParen,
+                TokenType::InterpolationSegment(", y: ".to_string()),
+                TokenType::LeftBrace,
+                TokenType::Identifier,
+                TokenType::Colon,
+                TokenType::Int(2),
+                TokenType::RightBrace,
+                TokenType::Dot,
+                TokenType::Identifier,
+                TokenType::InterpolationEnd("".to_string()),
+            ],
+        );
 
-        let scanner = scanner.scan_token();
+        let scanner = build_scanner("\"Seems like a good place for ${recursion()}...?\"");
+        assert_tokens(
+            scanner,
+            vec![
+                TokenType::InterpolationStart("Seems like a good place for ".to_string()),
+                TokenType::Identifier,
+                TokenType::LeftParen,
+                TokenType::RightParen,
+                TokenType::InterpolationEnd("...?".to_string()),
+            ],
+        );
+
+        let scanner =
+            build_scanner("\"Also, interpolation should automatically convert ${to_string()}\"");
+        assert_tokens(
+            scanner,
+            vec![
+                TokenType::InterpolationStart(
+                    "Also, interpolation should automatically convert ".to_string(),
+                ),
+                TokenType::Identifier,
+                TokenType::LeftParen,
+                TokenType::RightParen,
+                TokenType::InterpolationEnd("".to_string()),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        let mut scanner = build_scanner(r#""a\n\t\\\"\$\u{1F600}\u{e9}" "\${not interpolated}""#);
         assert_eq!(
-            Token::new(TokenType::Dot, ".", 1, 6),
+            TokenType::String("a\n\t\\\"$\u{1F600}\u{e9}".to_string()),
+            scanner.current_token().token_type()
+        );
+
+        scanner.next();
+        assert_eq!(
+            TokenType::String("${not interpolated}".to_string()),
+            scanner.current_token().token_type()
+        );
+
+        // Errors point at the opening quote, and scanning
+        // continues after the end of the string
+        let mut scanner = build_scanner(r#"; "bad \q escape" "\u{110000}" "\u{41""#);
+        scanner.next();
+        assert_eq!(
+            error_token(
+                "Invalid escape sequence: \\q".to_string(),
+                r#""bad \q escape""#,
+                1,
+                3,
+                2
+            ),
             scanner.current_token()
         );
 
-        let scanner = scanner.scan_token();
+        scanner.next();
+        assert_eq!(
+            TokenType::Error("Invalid unicode escape: \\u{110000}".to_string()),
+            scanner.current_token().token_type()
+        );
+        assert_eq!(19, scanner.current_token().column());
+
+        scanner.next();
         assert_eq!(
-            Token::new(TokenType::Number(1.0), "1", 1, 7),
+            TokenType::Error("Invalid unicode escape: \\u{41".to_string()),
+            scanner.current_token().token_type()
+        );
+
+        scanner.next();
+        assert_eq!(TokenType::EOF, scanner.current_token().token_type());
+    }
+
+    #[test]
+    fn test_unterminated_strings() {
+        let mut scanner = build_scanner("; \"no end\n;");
+        scanner.next();
+        assert_eq!(
+            error_token("Unterminated string.".to_string(), "\"no end", 1, 3, 2),
+            scanner.current_token()
+        );
+
+        scanner.next();
+        assert_eq!(
+            token(TokenType::Semicolon, ";", 2, 1, 10),
+            scanner.current_token()
+        );
+
+        let scanner = build_scanner("\"\"\"no\nend\"");
+        assert_eq!(
+            error_token("Unterminated string.".to_string(), "\"\"\"no\nend\"", 1, 1, 0),
             scanner.current_token()
         );
+
+        let mut scanner = build_scanner("\"${ 1 ");
+        scanner.next();
+        scanner.next();
+        assert_eq!(
+            TokenType::Error("Unterminated string interpolation.".to_string()),
+            scanner.current_token().token_type()
+        );
     }
 
     #[test]
-    fn identifier_recognition() {
-        let scanner = build_scanner("abc and def");
+    fn test_raw_and_multiline_strings() {
+        let mut scanner = build_scanner(r#"r"C:\path\${x}" r"""say "hi"""""#);
+        assert_eq!(
+            TokenType::String(r"C:\path\${x}".to_string()),
+            scanner.current_token().token_type()
+        );
+
+        scanner.next();
+        assert_eq!(
+            TokenType::String(r#"say "hi""#.to_string()),
+            scanner.current_token().token_type()
+        );
+
+        let mut scanner = build_scanner("\"\"\"first\n\t\"second\"\n${ 1 }\"\"\" ;");
         assert_eq!(
-            Token::new(TokenType::Identifier, "abc", 1, 1),
+            TokenType::InterpolationStart("first\n\t\"second\"\n".to_string()),
+            scanner.current_token().token_type()
+        );
+
+        scanner.next();
+        assert_eq!(
+            token(TokenType::Int(1), "1", 3, 4, 22),
             scanner.current_token()
         );
-        
-        let scanner = scanner.scan_token();
+
+        scanner.next();
         assert_eq!(
-            Token::new(TokenType::And, "and", 1, 5),
+            TokenType::InterpolationEnd("".to_string()),
+            scanner.current_token().token_type()
+        );
+
+        scanner.next();
+        assert_eq!(
+            token(TokenType::Semicolon, ";", 3, 11, 29),
             scanner.current_token()
         );
-        
-        let scanner = scanner.scan_token();
+    }
+
+    #[test]
+    fn unicode_scanning() {
+        let mut scanner = build_scanner("\"héllo 😀\" // ünïcödé\n/* 日本 /* 語 */ */ café\t€ ;");
         assert_eq!(
-            Token::new(TokenType::Identifier, "def", 1, 9),
+            token(TokenType::String("héllo 😀".to_string()), "\"héllo 😀\"", 1, 1, 0),
             scanner.current_token()
-        )
+        );
+
+        scanner.next();
+        assert_eq!(
+            token(TokenType::Identifier, "café", 2, 18, 52),
+            scanner.current_token()
+        );
+
+        scanner.next();
+        assert_eq!(
+            Token::error(
+                "Invalid character: €".to_string(),
+                "€",
+                Span::new(
+                    FileId::default(),
+                    Position::new(58, 2, 26, 23),
+                    Position::new(61, 2, 27, 24)
+                )
+            ),
+            scanner.current_token()
+        );
+
+        scanner.next();
+        assert_eq!(
+            Token::new(
+                TokenType::Semicolon,
+                ";",
+                Span::new(
+                    FileId::default(),
+                    Position::new(62, 2, 28, 25),
+                    Position::new(63, 2, 29, 26)
+                )
+            ),
+            scanner.current_token()
+        );
+
+        // Characters outside of the basic multilingual plane
+        // take up two UTF-16 code units
+        let mut scanner = build_scanner("\"😀\" ;");
+        scanner.next();
+        assert_eq!(5, scanner.current_token().column());
+        assert_eq!(6, scanner.current_token().utf16_column());
+        assert_eq!(7, scanner.current_token().offset());
     }
 
     #[test]
-    #[ignore]
-    fn test_string_interpolation() {
-        let _scanner = build_scanner("\"This string has ${1 + 0} string interpolation\"");
-        let _scanner = build_scanner("\"This string ${\"has mixed ${\"interpolation\"}\"}\"");
-        let _scanner = build_scanner("\"Seems like a good place for ${recursion()}...?\"");
-        let _scanner = build_scanner("\"Also, interpolation should automatically convert ${to_string()}\"");
-        unimplemented!(); // TODO add support for string interpolation
+    fn error_recovery() {
+        let source = "a €£ b ~\n\"c\n0b2 /* d /* e */";
+        assert_tokens(
+            build_scanner(source),
+            vec![
+                TokenType::Identifier,
+                TokenType::Error("Invalid characters: €£".to_string()),
+                TokenType::Identifier,
+                TokenType::Error("Invalid character: ~".to_string()),
+                TokenType::Error("Unterminated string.".to_string()),
+                TokenType::Error("Invalid digit '2' in binary literal: 0b2".to_string()),
+                TokenType::Error("Unterminated comment.".to_string()),
+            ],
+        );
+
+        let mut scanner = build_scanner(source);
+        scanner.by_ref().for_each(drop);
+        let errors: Vec<(String, u32, u32)> = scanner
+            .errors()
+            .iter()
+            .map(|error| (error.lexeme(), error.line(), error.column()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("€£".to_string(), 1, 3),
+                ("~".to_string(), 1, 8),
+                ("\"c".to_string(), 2, 1),
+                ("0b2".to_string(), 3, 1),
+                ("/* d /* e */".to_string(), 3, 5),
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn underlining() {
+        let source = "let\n\tx = \"日本\" + y;\r\n";
+        let mut scanner = build_scanner(source);
+        scanner.nth(2);
+        assert_eq!(
+            "2 | \tx = \"日本\" + y;\n  | \t    ^^^^\n",
+            scanner.current_token().span().underline(source)
+        );
+
+        // Spans over multiple lines are only underlined on their first line
+        let source = "\"\"\"a\nb\"\"\"";
+        let span = build_scanner(source).current_token().span();
+        assert_eq!("1 | \"\"\"a\n  | ^^^^\n", span.underline(source));
+    }
+
+    #[test]
+    fn trivia_scanning() {
+        let source = "a // b\n\t/* c /* d */ */\"${ e }\" /* f";
+        assert_tokens(
+            build_trivia_scanner(source),
+            vec![
+                TokenType::Identifier,
+                TokenType::Whitespace,
+                TokenType::LineComment,
+                TokenType::Whitespace,
+                TokenType::BlockComment,
+                TokenType::InterpolationStart("".to_string()),
+                TokenType::Whitespace,
+                TokenType::Identifier,
+                TokenType::Whitespace,
+                TokenType::InterpolationEnd("".to_string()),
+                TokenType::Whitespace,
+                TokenType::Error("Unterminated comment.".to_string()),
+            ],
+        );
+
+        let lexemes: String = build_trivia_scanner(source).map(|token| token.lexeme()).collect();
+        assert_eq!(source, lexemes);
+    }
+
+    #[test]
+    fn span_scanning() {
+        let mut scanner = build_file_scanner("let\n\"\"\"a\nbc\"\"\" ...", FileId(3));
+        scanner.next();
+        let span = scanner.current_token().span();
+        assert_eq!(FileId(3), span.file());
+        assert_eq!((4, 2, 1), (span.start().offset(), span.start().line(), span.start().column()));
+        assert_eq!((14, 3, 6), (span.end().offset(), span.end().line(), span.end().column()));
+
+        scanner.next();
+        let span = scanner.current_token().span();
+        assert_eq!(3, span.len());
+        assert_eq!((3, 10), (span.end().line(), span.end().column()));
+    }
+
+    #[test]
+    fn long_input_scanning() {
+        let comment = format!("/* {} */ 1", "comment ".repeat(1 << 20));
+        assert_tokens(build_scanner(&comment), vec![TokenType::Int(1)]);
+
+        let text = "text ".repeat(1 << 20);
+        let string = format!("\"{}\"", text);
+        assert_tokens(build_scanner(&string), vec![TokenType::String(text)]);
+
+        let expression = "1 + ".repeat(1 << 18) + "1";
+        let mut scanner = build_scanner(&expression);
+        assert_eq!(Some(TokenType::Int(1)), scanner.peek().map(Token::token_type));
+        assert_eq!((1 << 19) + 2, scanner.by_ref().count());
+        assert_eq!(None, scanner.peek());
+    }
+
+    /// Builds the expected token for a position
+    /// on a line without any tabs before it
+    fn token(t_type: TokenType, lexeme: &str, line: u32, column: u32, offset: usize) -> Token {
+        Token::new(t_type, lexeme, span(lexeme, line, column, offset))
+    }
+
+    fn error_token(message: String, lexeme: &str, line: u32, column: u32, offset: usize) -> Token {
+        Token::error(message, lexeme, span(lexeme, line, column, offset))
+    }
+
+    /// Builds the span of a lexeme that doesn't contain any tabs
+    fn span(lexeme: &str, line: u32, column: u32, offset: usize) -> Span {
+        let lines = lexeme.split('\n').count() as u32;
+        let last_line = lexeme.rsplit('\n').next().unwrap_or_default();
+        let start_column = if lines == 1 { column } else { 1 };
+        let end_column = start_column + last_line.chars().count() as u32;
+        let end_utf16_column = start_column + last_line.encode_utf16().count() as u32;
+        Span::new(
+            FileId::default(),
+            Position::new(offset, line, column, column),
+            Position::new(offset + lexeme.len(), line + lines - 1, end_column, end_utf16_column),
+        )
+    }
+
+    fn assert_tokens(scanner: Scanner, t_types: Vec<TokenType>) {
+        let scanned: Vec<TokenType> = scanner.map(|token| token.token_type()).collect();
+        let expected: Vec<TokenType> = t_types.into_iter().chain(vec![TokenType::EOF]).collect();
+        assert_eq!(expected, scanned);
     }
 }
@@ -1,17 +1,63 @@
 // TODO Move all of this to VM module
+use std::cell::OnceCell;
+use std::collections::HashSet;
 use std::fmt::{self, Display, Formatter};
+use std::rc::Rc;
+
+use crate::chunk::Chunk;
+use crate::decision::{self, Decision, Path};
+use crate::heap::Obj;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Value {
-    // template: Option<Rc<Template>>, // TODO Implement templates
+    // The type of a struct instance
+    template: Option<Rc<Template>>,
     data: DataType,
 }
 
 impl Value {
+    pub(crate) fn new(template: Option<Rc<Template>>, data: DataType) -> Self {
+        Value { template, data }
+    }
+
+    // Int datatype functions
+    /// Creates a Value with the Int data type from a given integer
+    pub fn int(val: i64) -> Self {
+        Value {
+            template: None,
+            data: DataType::Int(val),
+        }
+    }
+
+    /// Returns true if the value contains an integer
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::value::Value;
+    ///
+    /// assert!(Value::int(2).is_int());
+    /// assert!(!Value::float(2.0).is_int());
+    /// ```
+    pub fn is_int(&self) -> bool {
+        matches!(self.data, DataType::Int(_))
+    }
+
+    /// Get the integer value of a Value object
+    ///
+    /// # Panics
+    /// This function panics if the value is not an integer
+    pub fn as_int(&self) -> i64 {
+        match self.data {
+            DataType::Int(val) => val,
+            _ => panic!("Tried to get int from non-int value."),
+        }
+    }
+
     // Float datatype functions
     /// Creates a Value with the Float data type from a given float
     pub fn float(val: f64) -> Self {
         Value {
+            template: None,
             data: DataType::Float(val),
         }
     }
@@ -32,10 +78,7 @@ impl Value {
     /// assert!(!u.is_float());
     /// ```
     pub fn is_float(&self) -> bool {
-        match self.data {
-            DataType::Float(_) => true,
-            _ => false,
-        }
+        matches!(self.data, DataType::Float(_))
     }
 
     /// Takes a function and maps that function over
@@ -115,6 +158,7 @@ impl Value {
     /// Creates a Value with the Unit data type
     pub fn unit() -> Self {
         Value {
+            template: None,
             data: DataType::Unit,
         }
     }
@@ -129,43 +173,722 @@ impl Value {
     /// assert!(u.is_unit());
     /// ```
     pub fn is_unit(&self) -> bool {
+        matches!(self.data, DataType::Unit)
+    }
+
+    // Bool datatype functions
+    /// Creates a Value with the Bool data type
+    pub fn bool(val: bool) -> Self {
+        Value {
+            template: None,
+            data: DataType::Bool(val),
+        }
+    }
+
+    /// Returns true if the value is `true` or `false`
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::value::Value;
+    ///
+    /// let b = Value::bool(false);
+    /// assert!(b.is_bool());
+    /// assert!(!Value::float(0.0).is_bool());
+    /// ```
+    pub fn is_bool(&self) -> bool {
+        matches!(self.data, DataType::Bool(_))
+    }
+
+    /// Get the bool value of a Value object
+    ///
+    /// # Panics
+    /// This function panics if the value is not a bool
+    pub fn as_bool(&self) -> bool {
         match self.data {
-            DataType::Unit => true,
-            _ => false,
+            DataType::Bool(val) => val,
+            _ => panic!("Tried to get bool from non-bool value."),
+        }
+    }
+
+    // String datatype functions
+    /// Creates a Value with the String data type,
+    /// which shares its text with any other copies
+    pub fn string(val: Rc<str>) -> Self {
+        Value {
+            template: None,
+            data: DataType::String(val),
+        }
+    }
+
+    /// Returns true if the value is a string
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::value::Value;
+    ///
+    /// assert!(Value::string("hi".into()).is_string());
+    /// assert!(!Value::unit().is_string());
+    /// ```
+    pub fn is_string(&self) -> bool {
+        matches!(self.data, DataType::String(_))
+    }
+
+    /// Get the text of a string Value
+    ///
+    /// # Panics
+    /// This function panics if the value is not a string
+    pub fn as_str(&self) -> &str {
+        match &self.data {
+            DataType::String(val) => val,
+            _ => panic!("Tried to get text from non-string value."),
+        }
+    }
+
+    // Struct instance functions
+    /// Creates an instance of the struct described by `template`,
+    /// with the values of its fields in the order they were declared
+    pub fn instance(template: Rc<Template>, fields: Vec<Value>) -> Self {
+        Value {
+            template: Some(template),
+            data: DataType::Instance(Obj::new(fields)),
+        }
+    }
+
+    /// Creates a variant of an enum, with the values of its payload
+    pub fn variant(template: Rc<Template>, payload: Vec<Value>) -> Self {
+        Value {
+            template: Some(template),
+            data: DataType::Variant(Obj::new(payload)),
+        }
+    }
+
+    pub fn template(&self) -> Option<&Rc<Template>> {
+        self.template.as_ref()
+    }
+
+    /// Gets the value of the field called `name`,
+    /// if the value has a field with that name
+    pub fn field(&self, name: &str) -> Option<Value> {
+        self.named_field(name).cloned()
+    }
+
+    /// The field called `name` of a struct instance or a record
+    pub fn named_field(&self, name: &str) -> Option<&Value> {
+        match (&self.template, &self.data) {
+            (Some(template), DataType::Instance(fields)) => template
+                .field_index(name)
+                .and_then(|index| fields.get(index)),
+            (_, DataType::Record(fields)) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// The values of the fields of a struct instance, of the payload
+    /// of a variant or of the elements of a tuple, in order
+    pub fn fields(&self) -> Option<&[Value]> {
+        match &self.data {
+            DataType::Instance(fields) | DataType::Variant(fields) | DataType::Tuple(fields) => {
+                Some(fields)
+            }
+            _ => None,
+        }
+    }
+
+    // Tuple datatype functions
+    /// Creates a tuple with the given elements
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::value::Value;
+    ///
+    /// // Strings inside of other values are quoted
+    /// let a = Value::tuple(vec![Value::string("a \"${b}\"".into()), Value::int(1)]);
+    /// assert_eq!(r#"("a \"\${b}\"", 1)"#, a.to_string());
+    ///
+    /// // A tuple with one element has a trailing comma, like in the source
+    /// let one = Value::tuple(vec![Value::int(1)]);
+    /// assert_eq!("(1,)", one.to_string());
+    /// ```
+    pub fn tuple(elements: Vec<Value>) -> Self {
+        Value {
+            template: None,
+            data: DataType::Tuple(Obj::new(elements)),
+        }
+    }
+
+    // Record datatype functions
+    /// Creates a record with the given fields, which can be in any order
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::value::Value;
+    ///
+    /// let a = Value::record(vec![
+    ///     ("y".to_string(), Value::int(2)),
+    ///     ("x".to_string(), Value::int(1)),
+    /// ]);
+    /// let b = Value::record(vec![
+    ///     ("x".to_string(), Value::int(1)),
+    ///     ("y".to_string(), Value::int(2)),
+    /// ]);
+    /// assert_eq!(a, b);
+    /// assert_eq!("{x: 1, y: 2}", a.to_string());
+    /// ```
+    pub fn record(mut fields: Vec<(String, Value)>) -> Self {
+        sort_fields(&mut fields);
+        Value {
+            template: None,
+            data: DataType::Record(Obj::new(fields)),
+        }
+    }
+
+    /// The names of the fields of a record, in order
+    pub fn record_fields(&self) -> Option<Vec<&str>> {
+        match &self.data {
+            DataType::Record(fields) => {
+                Some(fields.iter().map(|(name, _)| name.as_str()).collect())
+            }
+            _ => None,
+        }
+    }
+
+    // Function datatype functions
+    pub fn function(function: Function) -> Self {
+        Value {
+            template: None,
+            data: DataType::Function(Rc::new(function)),
+        }
+    }
+
+    /// Binds `function` to `receiver`, which is passed to
+    /// the function as its first argument when it is called
+    pub fn method(receiver: Value, function: Rc<Function>) -> Self {
+        Value {
+            template: None,
+            data: DataType::Method(Obj::new(BoundMethod::new(receiver, function))),
         }
     }
-    
+
+    pub fn data(&self) -> &DataType {
+        &self.data
+    }
+
     pub fn compare_values(a: Value, b: Value) -> bool {
         match (a.data, b.data) {
             (DataType::Float(a), DataType::Float(b)) => {
                 Value::compare_floats(Value::float(a), Value::float(b), 10)
             }
             (DataType::Unit, DataType::Unit) => true,
-            _ => false,
+            (a, b) => a == b,
         }
     }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self.data {
-                DataType::Float(f) => f.to_string(),
-                DataType::Unit => "unit".to_string(),
+        match (&self.template, &self.data) {
+            (Some(template), DataType::Instance(fields)) => {
+                write!(f, "{} {{", template.name)?;
+                for (index, (name, value)) in template.fields.iter().zip(fields.iter()).enumerate()
+                {
+                    let separator = if index == 0 { "" } else { "," };
+                    write!(f, "{} {}: {}", separator, name, Nested(value))?;
+                }
+                write!(f, " }}")
+            }
+            (Some(template), DataType::Variant(payload)) => {
+                write!(f, "{}", template.qualified_name())?;
+                if !payload.is_empty() {
+                    write!(f, "(")?;
+                    for (index, value) in payload.iter().enumerate() {
+                        let separator = if index == 0 { "" } else { ", " };
+                        write!(f, "{}{}", separator, Nested(value))?;
+                    }
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            (_, DataType::Tuple(elements)) => {
+                write!(f, "(")?;
+                for (index, value) in elements.iter().enumerate() {
+                    let separator = if index == 0 { "" } else { ", " };
+                    write!(f, "{}{}", separator, Nested(value))?;
+                }
+                // So that it isn't mistaken for its element in parentheses
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            (_, DataType::Record(fields)) => {
+                write!(f, "{{")?;
+                for (index, (name, value)) in fields.iter().enumerate() {
+                    let separator = if index == 0 { "" } else { ", " };
+                    write!(f, "{}{}: {}", separator, name, Nested(value))?;
+                }
+                write!(f, "}}")
+            }
+            (_, DataType::String(string)) => write!(f, "{}", string),
+            (_, DataType::Int(int)) => write!(f, "{}", int),
+            // Whole floats keep their decimal point so they can't be mistaken for Ints
+            (_, DataType::Float(float)) if float.fract() == 0.0 => write!(f, "{:.1}", float),
+            (_, DataType::Float(float)) => write!(f, "{}", float),
+            (_, DataType::Bool(val)) => write!(f, "{}", val),
+            (_, DataType::Unit) => write!(f, "unit"),
+            (_, DataType::Function(function)) => write!(f, "<fn {}>", function.name),
+            (_, DataType::Method(method)) => write!(f, "<fn {}>", method.function.name),
+            (None, DataType::Instance(_)) | (None, DataType::Variant(_)) => {
+                write!(f, "<instance>")
+            }
+        }
+    }
+}
+
+// A value inside of another value, where a string is written as it would be
+// in the source, so that `("a", 1)` and `(a, 1)` can be told apart
+struct Nested<'a>(&'a Value);
+
+impl Display for Nested<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let string = match self.0.data() {
+            DataType::String(string) => string,
+            _ => return write!(f, "{}", self.0),
+        };
+
+        write!(f, "\"")?;
+        let mut characters = string.chars().peekable();
+        while let Some(character) = characters.next() {
+            match character {
+                '\\' => write!(f, "\\\\")?,
+                '"' => write!(f, "\\\"")?,
+                '\n' => write!(f, "\\n")?,
+                '\t' => write!(f, "\\t")?,
+                '$' if characters.peek() == Some(&'{') => write!(f, "\\$")?,
+                _ => write!(f, "{}", character)?,
             }
-        )
+        }
+        write!(f, "\"")
     }
 }
 
+/// Sorts the fields of a record by their names,
+/// so that records with the same fields are equal
+pub(crate) fn sort_fields(fields: &mut [(String, Value)]) {
+    fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum DataType {
+    Int(i64),
     Float(f64),
+    Bool(bool),
     Unit,
+    String(Rc<str>),
+    Instance(Obj<Vec<Value>>),
+    Variant(Obj<Vec<Value>>),
+    Tuple(Obj<Vec<Value>>),
+    // The fields of a record, in order of their names
+    Record(Obj<Vec<(String, Value)>>),
+    Function(Rc<Function>),
+    Method(Obj<BoundMethod>),
+}
+
+/// The type of a struct or of a variant of an enum,
+/// which every instance of the type refers to
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    name: String,
+    // The name of the enum that a variant belongs to
+    enum_name: Option<String>,
+    fields: Vec<String>,
+    // The pattern that each field of a struct has to match, along with
+    // the pattern as it was written, which are set once every type is known
+    types: OnceCell<Vec<(Pattern, String)>>,
+    // The global slots of the functions in the struct's define block
+    methods: Vec<(String, usize)>,
+}
+
+impl Template {
+    pub fn new(name: &str, fields: Vec<String>, methods: Vec<(String, usize)>) -> Self {
+        Template {
+            name: name.to_string(),
+            enum_name: None,
+            fields,
+            types: OnceCell::new(),
+            methods,
+        }
+    }
+
+    /// Creates the template of the variant `name` of the enum `enum_name`,
+    /// which has one field for each value in its payload
+    pub fn variant(
+        enum_name: &str,
+        name: &str,
+        fields: Vec<String>,
+        methods: Vec<(String, usize)>,
+    ) -> Self {
+        Template {
+            name: name.to_string(),
+            enum_name: Some(enum_name.to_string()),
+            fields,
+            types: OnceCell::new(),
+            methods,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn enum_name(&self) -> Option<&str> {
+        self.enum_name.as_deref()
+    }
+
+    /// The name of the type, including
+    /// the name of its enum if it is a variant
+    pub fn qualified_name(&self) -> String {
+        match &self.enum_name {
+            Some(enum_name) => format!("{}::{}", enum_name, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|field| field == name)
+    }
+
+    /// Sets the patterns that the fields have to match, with one
+    /// pattern and its source text for each field in order
+    ///
+    /// The types can only be set once, and fields
+    /// without a type can be given `Pattern::Wildcard`.
+    pub fn set_types(&self, types: Vec<(Pattern, String)>) {
+        let _ = self.types.set(types);
+    }
+
+    /// The first of `values` that doesn't match the type of its field,
+    /// along with the name of the field and its type as it was written
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::value::{Pattern, Template, Value};
+    ///
+    /// let fields = vec!["x".to_string(), "y".to_string()];
+    /// let point = Template::new("Point", fields, vec![]);
+    /// point.set_types(vec![
+    ///     (Pattern::Int, "Int".to_string()),
+    ///     (Pattern::Wildcard, "_".to_string()),
+    /// ]);
+    ///
+    /// assert_eq!(None, point.mismatch(&[Value::int(1), Value::bool(true)]));
+    /// let values = [Value::float(1.0), Value::int(2)];
+    /// assert_eq!(Some(("x", "Int", &values[0])), point.mismatch(&values));
+    /// ```
+    pub fn mismatch<'a>(&self, values: &'a [Value]) -> Option<(&str, &str, &'a Value)> {
+        let types = self.types.get()?;
+        self.fields
+            .iter()
+            .zip(types.iter())
+            .zip(values.iter())
+            .find(|((_, (pattern, _)), value)| !pattern.matches(value, &mut vec![]))
+            .map(|((field, (_, written)), value)| (field.as_str(), written.as_str(), value))
+    }
+
+    /// The global slot of the method called `name`
+    pub fn method(&self, name: &str) -> Option<usize> {
+        self.methods
+            .iter()
+            .find(|(method, _)| method == name)
+            .map(|(_, slot)| *slot)
+    }
+}
+
+/// A compiled function, made up of one or more clauses
+#[derive(Debug)]
+pub struct Function {
+    name: String,
+    arity: usize,
+    // The clauses of the function, in the order they are tried
+    clauses: Vec<Clause>,
+    // Which clause to run for a set of arguments
+    decision: Decision,
+}
+
+impl Function {
+    /// Creates a function with a single clause that accepts any `arity` arguments
+    pub fn new(name: &str, arity: usize, chunk: Chunk) -> Self {
+        let params = vec!["_"; arity].join(", ");
+        let clause = Clause::new(
+            &format!("{}({})", name, params),
+            vec![Pattern::Wildcard; arity],
+            chunk,
+        );
+        Function::with_clauses(name, arity, vec![clause])
+    }
+
+    /// Creates a function from clauses that each
+    /// have one pattern for each of the `arity` parameters
+    pub fn with_clauses(name: &str, arity: usize, clauses: Vec<Clause>) -> Self {
+        let patterns: Vec<Vec<Pattern>> = clauses
+            .iter()
+            .map(|clause| clause.patterns.clone())
+            .collect();
+        let guarded: Vec<usize> = clauses
+            .iter()
+            .enumerate()
+            .filter(|(_, clause)| clause.guard.is_some())
+            .map(|(index, _)| index)
+            .collect();
+        Function {
+            name: name.to_string(),
+            arity,
+            clauses,
+            decision: Decision::with_guards(&patterns, &guarded),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
+
+    pub fn clauses(&self) -> &[Clause] {
+        &self.clauses
+    }
+
+    pub fn decision(&self) -> &Decision {
+        &self.decision
+    }
+
+    /// Finds the first clause whose patterns accept `arguments`,
+    /// along with the values that its patterns bind
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::chunk::Chunk;
+    /// use lucent_lang::value::{Clause, Function, Pattern, Value};
+    ///
+    /// let zero = Clause::new("f(0)", vec![Pattern::Literal(Value::int(0))], Chunk::new());
+    /// let any = Clause::new("f(a)", vec![Pattern::Wildcard], Chunk::new());
+    /// let function = Function::with_clauses("f", 1, vec![zero, any]);
+    ///
+    /// let dispatch = function.dispatch(&[Value::int(1)]).unwrap();
+    /// assert_eq!(1, dispatch.clause);
+    /// assert!(dispatch.bindings.is_empty());
+    /// ```
+    pub fn dispatch(&self, arguments: &[Value]) -> Option<Dispatch> {
+        self.resume(&self.decision, arguments)
+    }
+
+    /// Finds the first clause in `decision` whose patterns accept
+    /// `arguments`, after the guard of an earlier clause was false
+    pub fn resume(&self, decision: &Decision, arguments: &[Value]) -> Option<Dispatch> {
+        let (clause, otherwise) = decision.search(arguments)?;
+        let bindings = self.clauses[clause]
+            .bindings
+            .iter()
+            .map(|path| decision::value_at(arguments, path).cloned())
+            .collect::<Option<Vec<Value>>>()?;
+        Some(Dispatch {
+            clause,
+            bindings,
+            otherwise: otherwise.map(Rc::clone),
+        })
+    }
 }
 
-#[derive(Default, Debug)]
+/// The clause chosen to run for a call
+#[derive(Debug)]
+pub struct Dispatch {
+    pub clause: usize,
+    /// The values that the clause's patterns bind
+    pub bindings: Vec<Value>,
+    /// Where to look for a clause if the clause's guard is false
+    pub otherwise: Option<Rc<Decision>>,
+}
+
+// Functions are only equal to themselves
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+/// One definition of a function, which is only run
+/// if the arguments match each of its patterns
+#[derive(Debug)]
+pub struct Clause {
+    // The clause as it was written, for error messages
+    signature: String,
+    patterns: Vec<Pattern>,
+    // Where to find the values that the patterns bind
+    bindings: Vec<Path>,
+    // Checked after the patterns match, with the same local variables
+    guard: Option<Chunk>,
+    chunk: Chunk,
+}
+
+impl Clause {
+    pub fn new(signature: &str, patterns: Vec<Pattern>, chunk: Chunk) -> Self {
+        Clause {
+            signature: signature.to_string(),
+            bindings: decision::binding_paths(&patterns),
+            patterns,
+            guard: None,
+            chunk,
+        }
+    }
+
+    /// Creates a clause that only runs if `guard` returns true
+    pub fn guarded(signature: &str, patterns: Vec<Pattern>, guard: Chunk, chunk: Chunk) -> Self {
+        Clause {
+            guard: Some(guard),
+            ..Clause::new(signature, patterns, chunk)
+        }
+    }
+
+    pub fn signature(&self) -> &str {
+        &self.signature
+    }
+
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    pub fn guard(&self) -> Option<&Chunk> {
+        self.guard.as_ref()
+    }
+
+    pub fn chunk(&self) -> &Chunk {
+        &self.chunk
+    }
+}
+
+/// A compiled pattern, which is matched against
+/// the arguments of a function when it is called
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Wildcard,
+    /// Matches the inner pattern and binds the value to a local variable
+    Bind(Box<Pattern>),
+    /// A value that the argument has to be equal to
+    Literal(Value),
+    Float,
+    Int,
+    Bool,
+    Unit,
+    String,
+    /// An instance of a struct or a variant of an enum, with patterns
+    /// for each of its fields if they are matched by position
+    Template(Rc<Template>, Option<Vec<Pattern>>),
+    /// Any variant of the named enum
+    Enum(String),
+    /// A tuple with one element for each pattern
+    Tuple(Vec<Pattern>),
+    /// A record with patterns for some of its fields, which can't
+    /// have any other fields unless the pattern is open
+    Record {
+        fields: Vec<(String, Pattern)>,
+        open: bool,
+    },
+}
+
+impl Pattern {
+    /// Matches `value` against the pattern, pushing the values
+    /// of any bindings onto `bindings` in the order they appear
+    ///
+    /// # Examples
+    /// ```
+    /// use lucent_lang::value::{Pattern, Value};
+    ///
+    /// let pattern = Pattern::Bind(Box::new(Pattern::Int));
+    /// let mut bindings = vec![];
+    ///
+    /// assert!(pattern.matches(&Value::int(2), &mut bindings));
+    /// assert_eq!(vec![Value::int(2)], bindings);
+    /// assert!(!pattern.matches(&Value::float(2.0), &mut bindings));
+    /// ```
+    pub fn matches(&self, value: &Value, bindings: &mut Vec<Value>) -> bool {
+        match (self, &value.data) {
+            (Pattern::Wildcard, _) => true,
+            (Pattern::Bind(pattern), _) => {
+                bindings.push(value.clone());
+                pattern.matches(value, bindings)
+            }
+            (Pattern::Literal(literal), _) => literal == value,
+            (Pattern::Int, DataType::Int(_))
+            | (Pattern::Float, DataType::Float(_))
+            | (Pattern::Bool, DataType::Bool(_))
+            | (Pattern::Unit, DataType::Unit)
+            | (Pattern::String, DataType::String(_)) => true,
+            (Pattern::Template(template, fields), DataType::Instance(values))
+            | (Pattern::Template(template, fields), DataType::Variant(values)) => {
+                let same_type = value
+                    .template
+                    .as_ref()
+                    .is_some_and(|other| Rc::ptr_eq(template, other));
+                same_type
+                    && fields
+                        .iter()
+                        .flatten()
+                        .zip(values.iter())
+                        .all(|(field, value)| field.matches(value, bindings))
+            }
+            (Pattern::Tuple(patterns), DataType::Tuple(elements)) => {
+                patterns.len() == elements.len()
+                    && patterns
+                        .iter()
+                        .zip(elements.iter())
+                        .all(|(pattern, element)| pattern.matches(element, bindings))
+            }
+            (Pattern::Record { fields, open }, DataType::Record(values)) => {
+                (*open || fields.len() == values.len())
+                    && fields.iter().all(|(name, pattern)| {
+                        value
+                            .named_field(name)
+                            .is_some_and(|field| pattern.matches(field, bindings))
+                    })
+            }
+            (Pattern::Enum(name), DataType::Variant(_)) => value
+                .template
+                .as_ref()
+                .is_some_and(|template| template.enum_name() == Some(name)),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BoundMethod {
+    receiver: Value,
+    function: Rc<Function>,
+}
+
+impl BoundMethod {
+    pub(crate) fn new(receiver: Value, function: Rc<Function>) -> Self {
+        BoundMethod { receiver, function }
+    }
+
+    pub fn receiver(&self) -> &Value {
+        &self.receiver
+    }
+
+    pub fn function(&self) -> &Rc<Function> {
+        &self.function
+    }
+}
+
+#[derive(Default, Debug, Clone)]
 pub struct ValueArray(Vec<Value>);
 
 impl ValueArray {
@@ -192,5 +915,44 @@ impl ValueArray {
     }
 }
 
+/// The text of strings that are known before the program runs,
+/// such as string literals and the names of fields, which is
+/// only stored once however many times it appears
+#[derive(Debug, Default)]
+pub struct Interner {
+    strings: HashSet<Rc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Interner {
+            strings: HashSet::new(),
+        }
+    }
+
+    /// Gets the shared copy of `string`, adding it if it is new
+    ///
+    /// # Examples
+    /// ```
+    /// use std::rc::Rc;
+    /// use lucent_lang::value::Interner;
+    ///
+    /// let mut interner = Interner::new();
+    /// let a = interner.intern("name");
+    /// let b = interner.intern(&"name".to_string());
+    /// assert!(Rc::ptr_eq(&a, &b));
+    /// ```
+    pub fn intern(&mut self, string: &str) -> Rc<str> {
+        match self.strings.get(string) {
+            Some(interned) => Rc::clone(interned),
+            None => {
+                let interned: Rc<str> = Rc::from(string);
+                self.strings.insert(Rc::clone(&interned));
+                interned
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {}
@@ -1,37 +1,82 @@
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::rc::Rc;
+
 use crate::chunk::{Chunk, OpCode};
+use crate::decision::Decision;
 use crate::disassembler::disassemble_instruction;
-use crate::value::Value;
+use crate::heap::{self, Heap};
+use crate::scanner::Span;
+use crate::value::{DataType, Dispatch, Function, Value};
+
+const FRAMES_MAX: usize = 256;
+const STACK_MAX: usize = FRAMES_MAX * 256;
+const OVERFLOW: &str = "Integer overflow.";
+const MISMATCHED_OPERANDS: &str = "Operands must be two Ints, two Floats or two Strings.";
+const NOT_A_NUMBER: &str = "Operand must be a number.";
+const NOT_A_STRING: &str = "A 'to_string' method must return a String.";
 
-const STACK_MAX: usize = 256;
+/// Whether an action of a binary operator applies to its operands
+type Predicate = fn(&Value, &Value) -> bool;
+/// Applies a binary operator to its operands, or fails with a message
+type Action = fn(&Value, &Value) -> Result<Value, &'static str>;
 
 #[derive(Default)]
 pub struct VM {
     debug: DebugFlags,
+    // The objects made by the program
+    heap: Heap,
 }
 
 impl VM {
     pub fn new() -> Self {
         VM {
             debug: DebugFlags::new(),
+            heap: Heap::new(),
         }
     }
 
     pub fn new_debugger(debug: DebugFlags) -> Self {
-        VM { debug }
+        let heap = if debug.stress_gc {
+            Heap::stressed()
+        } else {
+            Heap::new()
+        };
+        VM { debug, heap }
     }
 
-    pub fn interpret(&self, chunk: &Chunk) -> VMResult {
-        let ip = 0;
-        self.run(&chunk, ip)
+    pub fn interpret(&mut self, chunk: &Chunk) -> VMResult {
+        let script = Rc::new(Function::new("script", 0, chunk.clone()));
+        self.run(script)
     }
 
-    fn run(&self, chunk: &Chunk, ip: usize) -> VMResult {
+    fn run(&mut self, script: Rc<Function>) -> VMResult {
         let mut stack: Stack = Stack::new();
-        let mut ip = ip;
+        let mut globals: Vec<Option<Value>> = vec![];
+        // The frames of the functions that called the current function
+        let mut frames: Vec<CallFrame> = vec![];
+        let mut frame = CallFrame {
+            function: script,
+            clause: 0,
+            guard: None,
+            ip: 0,
+            call: 0,
+            callee: 0,
+            base: 0,
+        };
+
         loop {
+            if self.heap.should_collect() {
+                self.collect_garbage(&stack, &globals);
+            }
+
+            let function = Rc::clone(&frame.function);
+            let chunk = VM::running(&function, &frame);
+            let mut ip = frame.ip;
+
             if self.debug.print_stack {
-                print!(
-                    "          {}\n",
+                println!(
+                    "          {}",
                     stack
                         .clone()
                         .into_iter()
@@ -43,41 +88,83 @@ impl VM {
             }
 
             if self.debug.print_instructions {
-                let (_, command) = disassemble_instruction(&chunk, ip, "");
+                let (_, command) = disassemble_instruction(chunk, ip, "");
                 print!("{}", command);
             }
 
+            // The start of the current instruction, for error reporting
+            let instruction = ip;
             let opcode = OpCode::from(match chunk.get_byte(ip) {
                 Some(i) => i,
-                None => return VMResult::RuntimeError,
+                None => return VM::runtime_error(chunk, instruction, "Unexpected end of chunk."),
             });
 
             ip += 1;
-            ip = match opcode {
+            frame.ip = match opcode {
                 OpCode::Return => {
-                    /*if self.debug {
-                        println!(
-                            "{}",
-                            value::string_from(match stack.pop() {
-                                Some(i) => i,
-                                None => return VMResult::CompileError,
-                            })
-                        );
-                    }*/
+                    let value = stack.pop().unwrap_or_else(Value::unit);
 
-                    return VMResult::Okay(match stack.pop() {
-                        Some(i) => i,
-                        None => Value::float(0f64),
-                    });
+                    // Once a guard is done, either its clause runs
+                    // or the search for a clause carries on
+                    if let Some(otherwise) = frame.guard.take() {
+                        if !value.is_bool() {
+                            let message = "A guard must be true or false.";
+                            return VM::runtime_error(chunk, instruction, message);
+                        }
+                        if value.as_bool() {
+                            frame.ip = 0;
+                            continue;
+                        }
+
+                        stack.truncate(frame.base + function.arity());
+                        let arguments = stack.values(frame.base);
+                        let dispatch = match function.resume(&otherwise, arguments) {
+                            Some(dispatch) => dispatch,
+                            None => {
+                                let message = VM::no_clause_message(&function, arguments);
+                                // The error is reported at the call
+                                return match frames.last() {
+                                    Some(caller) => VM::runtime_error(
+                                        VM::running(&caller.function, caller),
+                                        caller.call,
+                                        &message,
+                                    ),
+                                    None => VM::runtime_error(chunk, instruction, &message),
+                                };
+                            }
+                        };
+                        let (callee, base) = (frame.callee, frame.base);
+                        let function = Rc::clone(&function);
+                        frame = match VM::enter(&mut stack, function, dispatch, callee, base) {
+                            Ok(frame) => frame,
+                            Err(()) => {
+                                return VM::runtime_error(chunk, instruction, "Stack overflow.")
+                            }
+                        };
+                        continue;
+                    }
+
+                    match frames.pop() {
+                        Some(caller) => {
+                            stack.truncate(frame.callee);
+                            if stack.push(value).is_err() {
+                                return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                            }
+                            frame = caller;
+                            continue;
+                        }
+                        // The result outlives the objects in the heap
+                        None => return VMResult::Okay(heap::detach(&value)),
+                    }
                 }
                 OpCode::Constant => {
                     let constant = match chunk.get_byte(ip) {
                         Some(i) => i,
-                        None => return VMResult::RuntimeError,
+                        None => return VMResult::CompileError,
                     };
                     let constant = match chunk.get_constant(constant as usize) {
                         Some(i) => i,
-                        None => return VMResult::RuntimeError,
+                        None => return VMResult::CompileError,
                     };
 
                     if self.debug.print_constants {
@@ -85,7 +172,7 @@ impl VM {
                     }
 
                     if stack.push(constant).is_err() {
-                        return VMResult::RuntimeError;
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
                     }
 
                     ip + 1
@@ -93,24 +180,24 @@ impl VM {
                 OpCode::ConstantLong => {
                     let first_byte = match chunk.get_byte(ip) {
                         Some(i) => i,
-                        None => return VMResult::RuntimeError,
+                        None => return VMResult::CompileError,
                     };
                     let second_byte = match chunk.get_byte(ip + 1) {
                         Some(i) => i,
-                        None => return VMResult::RuntimeError,
+                        None => return VMResult::CompileError,
                     };
                     let constant =
                         match chunk.get_long_constant(first_byte as usize, second_byte as usize) {
                             Some(i) => i,
-                            None => return VMResult::RuntimeError,
+                            None => return VMResult::CompileError,
                         };
 
                     if self.debug.print_constants {
-                        println!("{}", constant.to_string());
+                        println!("{}", constant);
                     }
 
                     if stack.push(constant).is_err() {
-                        return VMResult::RuntimeError;
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
                     }
 
                     ip + 2
@@ -118,75 +205,703 @@ impl VM {
                 OpCode::Negate => {
                     let val = match stack.pop() {
                         Some(i) => i,
-                        None => return VMResult::RuntimeError,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
                     };
-                    if !val.is_float() || stack.push(val.map_float(|val| -val)).is_err() {
-                        return VMResult::RuntimeError;
+                    let negated = match val.data() {
+                        DataType::Int(int) => match int.checked_neg() {
+                            Some(int) => Value::int(int),
+                            None => return VM::runtime_error(chunk, instruction, OVERFLOW),
+                        },
+                        DataType::Float(_) => val.map_float(|val| -val),
+                        _ => return VM::runtime_error(chunk, instruction, NOT_A_NUMBER),
+                    };
+                    if stack.push(negated).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
                     }
                     ip
                 }
                 OpCode::Add => {
-                    let actions = vec![(
-                        |a: &Value, b: &Value| a.is_float() && b.is_float(),
-                        |a: &Value, b: &Value| a.map_float(|a| a + b.as_float()),
-                    )];
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        (ints, |a, b| checked(a.as_int().checked_add(b.as_int()))),
+                        (floats, |a, b| Ok(a.map_float(|a| a + b.as_float()))),
+                        (strings, |a, b| Ok(concatenate(&[a.as_str(), b.as_str()]))),
+                    ];
                     stack = match VM::binary_op(stack, actions) {
                         Ok(stack) => stack,
-                        Err(result) => return result,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
                     };
                     ip
                 }
                 OpCode::Subtract => {
-                    let actions = vec![(
-                        |a: &Value, b: &Value| a.is_float() && b.is_float(),
-                        |a: &Value, b: &Value| a.map_float(|a| a - b.as_float()),
-                    )];
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        (ints, |a, b| checked(a.as_int().checked_sub(b.as_int()))),
+                        (floats, |a, b| Ok(a.map_float(|a| a - b.as_float()))),
+                    ];
                     stack = match VM::binary_op(stack, actions) {
                         Ok(stack) => stack,
-                        Err(result) => return result,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
                     };
                     ip
                 }
                 OpCode::Multiply => {
-                    let actions = vec![(
-                        |a: &Value, b: &Value| a.is_float() && b.is_float(),
-                        |a: &Value, b: &Value| a.map_float(|a| a * b.as_float()),
-                    )];
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        (ints, |a, b| checked(a.as_int().checked_mul(b.as_int()))),
+                        (floats, |a, b| Ok(a.map_float(|a| a * b.as_float()))),
+                    ];
                     stack = match VM::binary_op(stack, actions) {
                         Ok(stack) => stack,
-                        Err(result) => return result,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
                     };
                     ip
                 }
                 OpCode::Divide => {
-                    let actions = vec![(
-                        |a: &Value, b: &Value| a.is_float() && b.is_float(),
-                        |a: &Value, b: &Value| a.map_float(|a| a / b.as_float()),
-                    )];
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        // Dividing Ints rounds towards zero
+                        (ints, |a, b| divide(a, b, i64::checked_div)),
+                        (floats, |a, b| Ok(a.map_float(|a| a / b.as_float()))),
+                    ];
+                    stack = match VM::binary_op(stack, actions) {
+                        Ok(stack) => stack,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
+                    };
+                    ip
+                }
+                OpCode::Modulo => {
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        // The remainder has the sign of the dividend, and
+                        // can't overflow since the remainder of i64::MIN / -1 is 0
+                        (ints, |a, b| divide(a, b, |a, b| Some(a.wrapping_rem(b)))),
+                        (floats, |a, b| Ok(a.map_float(|a| a % b.as_float()))),
+                    ];
+                    stack = match VM::binary_op(stack, actions) {
+                        Ok(stack) => stack,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
+                    };
+                    ip
+                }
+                OpCode::Equal => {
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        (|_, _| true, |a, b| Ok(Value::bool(a == b))),
+                    ];
+                    stack = match VM::binary_op(stack, actions) {
+                        Ok(stack) => stack,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
+                    };
+                    ip
+                }
+                OpCode::NotEqual => {
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        (|_, _| true, |a, b| Ok(Value::bool(a != b))),
+                    ];
+                    stack = match VM::binary_op(stack, actions) {
+                        Ok(stack) => stack,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
+                    };
+                    ip
+                }
+                OpCode::Greater => {
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        (ints, |a, b| Ok(Value::bool(a.as_int() > b.as_int()))),
+                        (floats, |a, b| Ok(Value::bool(a.as_float() > b.as_float()))),
+                    ];
+                    stack = match VM::binary_op(stack, actions) {
+                        Ok(stack) => stack,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
+                    };
+                    ip
+                }
+                OpCode::GreaterEqual => {
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        (ints, |a, b| Ok(Value::bool(a.as_int() >= b.as_int()))),
+                        (floats, |a, b| Ok(Value::bool(a.as_float() >= b.as_float()))),
+                    ];
                     stack = match VM::binary_op(stack, actions) {
                         Ok(stack) => stack,
-                        Err(result) => return result,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
                     };
                     ip
                 }
+                OpCode::Less => {
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        (ints, |a, b| Ok(Value::bool(a.as_int() < b.as_int()))),
+                        (floats, |a, b| Ok(Value::bool(a.as_float() < b.as_float()))),
+                    ];
+                    stack = match VM::binary_op(stack, actions) {
+                        Ok(stack) => stack,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
+                    };
+                    ip
+                }
+                OpCode::LessEqual => {
+                    let actions: Vec<(Predicate, Action)> = vec![
+                        (ints, |a, b| Ok(Value::bool(a.as_int() <= b.as_int()))),
+                        (floats, |a, b| Ok(Value::bool(a.as_float() <= b.as_float()))),
+                    ];
+                    stack = match VM::binary_op(stack, actions) {
+                        Ok(stack) => stack,
+                        Err(message) => return VM::runtime_error(chunk, instruction, message),
+                    };
+                    ip
+                }
+                OpCode::ToInt => {
+                    let val = match stack.pop() {
+                        Some(i) => i,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    let int = match val.data() {
+                        DataType::Int(_) => val,
+                        // Converting a Float drops its fractional part
+                        DataType::Float(float)
+                            if float.is_finite()
+                                && *float >= i64::MIN as f64
+                                && *float < i64::MAX as f64 =>
+                        {
+                            Value::int(float.trunc() as i64)
+                        }
+                        DataType::Float(_) => {
+                            let message = "The Float is out of range for an Int.";
+                            return VM::runtime_error(chunk, instruction, message);
+                        }
+                        _ => return VM::runtime_error(chunk, instruction, NOT_A_NUMBER),
+                    };
+                    if stack.push(int).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip
+                }
+                OpCode::ToFloat => {
+                    let val = match stack.pop() {
+                        Some(i) => i,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    let float = match val.data() {
+                        DataType::Int(int) => Value::float(*int as f64),
+                        DataType::Float(_) => val,
+                        _ => return VM::runtime_error(chunk, instruction, NOT_A_NUMBER),
+                    };
+                    if stack.push(float).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip
+                }
+                OpCode::ToString => {
+                    let val = match stack.pop() {
+                        Some(i) => i,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+
+                    // A value whose type has a `to_string` method is converted by calling it
+                    let method = val
+                        .template()
+                        .and_then(|template| template.method("to_string"))
+                        .and_then(|slot| globals.get(slot).cloned().flatten());
+                    if let Some(DataType::Function(function)) = method.as_ref().map(Value::data) {
+                        let callee = stack.len();
+                        let method = self.heap.method(val, Rc::clone(function));
+                        if stack.push(method).is_err() {
+                            return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                        }
+                        let next = match VM::call(&mut stack, callee, frames.len()) {
+                            Ok(next) => next,
+                            Err(message) => return VM::runtime_error(chunk, instruction, &message),
+                        };
+                        frame.ip = ip;
+                        frame.call = instruction;
+                        frames.push(frame);
+                        frame = next;
+                        continue;
+                    }
+
+                    let string = if val.is_string() {
+                        val
+                    } else {
+                        Value::string(Rc::from(val.to_string()))
+                    };
+                    if stack.push(string).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip
+                }
+                OpCode::Interpolate => {
+                    let count = match chunk.get_byte(ip) {
+                        Some(count) => count as usize,
+                        None => return VMResult::CompileError,
+                    };
+                    let parts = match stack.len().checked_sub(count) {
+                        Some(start) => stack.split_off(start),
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    if !parts.iter().all(Value::is_string) {
+                        return VM::runtime_error(chunk, instruction, NOT_A_STRING);
+                    }
+                    let parts: Vec<&str> = parts.iter().map(Value::as_str).collect();
+                    if stack.push(concatenate(&parts)).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip + 1
+                }
+                OpCode::Print => {
+                    let string = match stack.pop() {
+                        Some(string) => string,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    if !string.is_string() {
+                        return VM::runtime_error(chunk, instruction, NOT_A_STRING);
+                    }
+                    println!("{}", string.as_str());
+                    if stack.push(Value::unit()).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip
+                }
+                OpCode::ReadLine => {
+                    let mut line = String::new();
+                    if io::stdin().read_line(&mut line).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Unable to read a line.");
+                    }
+                    // The line is read without its line ending
+                    let line = line.trim_end_matches(&['\n', '\r'][..]);
+                    if stack.push(Value::string(Rc::from(line))).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip
+                }
+                OpCode::Assert => {
+                    let condition = match stack.pop() {
+                        Some(condition) => condition,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    if !condition.is_bool() {
+                        let message = "An assertion must be true or false.";
+                        return VM::runtime_error(chunk, instruction, message);
+                    }
+                    if !condition.as_bool() {
+                        return VM::runtime_error(chunk, instruction, "Assertion failed.");
+                    }
+                    ip
+                }
+                OpCode::Pop => {
+                    if stack.pop().is_none() {
+                        return VM::runtime_error(chunk, instruction, "Stack underflow.");
+                    }
+                    ip
+                }
+                OpCode::GetLocal => {
+                    let slot = match chunk.get_byte(ip) {
+                        Some(slot) => slot as usize,
+                        None => return VMResult::CompileError,
+                    };
+                    let value = match stack.get(frame.base + slot) {
+                        Some(value) => value.clone(),
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    if stack.push(value).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip + 1
+                }
+                OpCode::GetGlobal => {
+                    let slot = match chunk.get_short(ip) {
+                        Some(slot) => slot,
+                        None => return VMResult::CompileError,
+                    };
+                    let value = match globals.get(slot) {
+                        Some(Some(value)) => value.clone(),
+                        _ => {
+                            return VM::runtime_error(
+                                chunk,
+                                instruction,
+                                "Constant used before it was defined.",
+                            )
+                        }
+                    };
+                    if stack.push(value).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip + 2
+                }
+                OpCode::DefineGlobal => {
+                    let slot = match chunk.get_short(ip) {
+                        Some(slot) => slot,
+                        None => return VMResult::CompileError,
+                    };
+                    let value = match stack.pop() {
+                        Some(value) => value,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    if globals.len() <= slot {
+                        globals.resize(slot + 1, None);
+                    }
+                    globals[slot] = Some(value);
+                    ip + 2
+                }
+                OpCode::Call => {
+                    let arguments = match chunk.get_byte(ip) {
+                        Some(arguments) => arguments as usize,
+                        None => return VMResult::CompileError,
+                    };
+                    let callee = match stack.len().checked_sub(arguments + 1) {
+                        Some(callee) => callee,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+
+                    // Calling a tuple gets the element at an index
+                    if let Some(DataType::Tuple(elements)) = stack.get(callee).map(Value::data) {
+                        let element = match VM::index(elements, stack.values(callee + 1)) {
+                            Ok(element) => element,
+                            Err(message) => return VM::runtime_error(chunk, instruction, &message),
+                        };
+                        stack.truncate(callee);
+                        if stack.push(element).is_err() {
+                            return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                        }
+                        frame.ip = ip + 1;
+                        continue;
+                    }
+
+                    let next = match VM::call(&mut stack, callee, frames.len()) {
+                        Ok(next) => next,
+                        Err(message) => return VM::runtime_error(chunk, instruction, &message),
+                    };
+                    frame.ip = ip + 1;
+                    frame.call = instruction;
+                    frames.push(frame);
+                    frame = next;
+                    continue;
+                }
+                OpCode::EndScope => {
+                    let locals = match chunk.get_byte(ip) {
+                        Some(locals) => locals as usize,
+                        None => return VMResult::CompileError,
+                    };
+                    let value = match stack.pop() {
+                        Some(value) => value,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    stack.truncate(stack.len().saturating_sub(locals));
+                    if stack.push(value).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip + 1
+                }
+                OpCode::Construct => {
+                    let template = match chunk.get_short(ip).and_then(|index| chunk.get_template(index)) {
+                        Some(template) => template,
+                        None => return VMResult::CompileError,
+                    };
+                    let fields = match stack.len().checked_sub(template.fields().len()) {
+                        Some(start) => stack.split_off(start),
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    if let Some((field, written, value)) = template.mismatch(&fields) {
+                        let message = format!(
+                            "The field '{}' of '{}' must match '{}', but got {}.",
+                            field,
+                            template.name(),
+                            written,
+                            value
+                        );
+                        return VM::runtime_error(chunk, instruction, &message);
+                    }
+                    let value = if template.enum_name().is_some() {
+                        self.heap.variant(template, fields)
+                    } else {
+                        self.heap.instance(template, fields)
+                    };
+                    if stack.push(value).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip + 2
+                }
+                OpCode::GetField => {
+                    let name = match chunk.get_short(ip).and_then(|index| chunk.get_name(index)) {
+                        Some(name) => name,
+                        None => return VMResult::CompileError,
+                    };
+                    let object = match stack.pop() {
+                        Some(object) => object,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+
+                    // Fields take precedence over methods with the same name
+                    let method = object
+                        .template()
+                        .and_then(|template| template.method(name))
+                        .and_then(|slot| globals.get(slot).cloned().flatten());
+                    let value = match (object.field(name), method) {
+                        (Some(field), _) => field,
+                        (None, Some(method)) => match method.data() {
+                            DataType::Function(function) => {
+                                self.heap.method(object, Rc::clone(function))
+                            }
+                            _ => method,
+                        },
+                        (None, None) => {
+                            let message = format!("Undefined field '{}'.", name);
+                            return VM::runtime_error(chunk, instruction, &message);
+                        }
+                    };
+                    if stack.push(value).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip + 2
+                }
+                OpCode::Tuple => {
+                    let length = match chunk.get_byte(ip) {
+                        Some(length) => length as usize,
+                        None => return VMResult::CompileError,
+                    };
+                    let elements = match stack.len().checked_sub(length) {
+                        Some(start) => stack.split_off(start),
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    let tuple = self.heap.tuple(elements);
+                    if stack.push(tuple).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip + 1
+                }
+                OpCode::Record => {
+                    let names = chunk.get_short(ip).and_then(|index| chunk.get_record(index));
+                    let names = match names {
+                        Some(names) => names,
+                        None => return VMResult::CompileError,
+                    };
+                    let values = match stack.len().checked_sub(names.len()) {
+                        Some(start) => stack.split_off(start),
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    let fields = names.iter().cloned().zip(values).collect();
+                    let record = self.heap.record(fields);
+                    if stack.push(record).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip + 2
+                }
+                OpCode::Destructure => {
+                    let pattern = chunk.get_short(ip).and_then(|index| chunk.get_match(index));
+                    let pattern = match pattern {
+                        Some(pattern) => pattern,
+                        None => return VMResult::CompileError,
+                    };
+                    let value = match stack.peek() {
+                        Some(value) => value.clone(),
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+
+                    // The value stays on the stack, followed by
+                    // the values bound inside of the pattern
+                    let bindings = match pattern.bind(&value) {
+                        Some(bindings) => bindings,
+                        None => {
+                            let message =
+                                format!("The value {} doesn't match the pattern.", value);
+                            return VM::runtime_error(chunk, instruction, &message);
+                        }
+                    };
+                    for binding in bindings {
+                        if stack.push(binding).is_err() {
+                            return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                        }
+                    }
+                    ip + 2
+                }
+                OpCode::Is => {
+                    let pattern = chunk.get_short(ip).and_then(|index| chunk.get_match(index));
+                    let pattern = match pattern {
+                        Some(pattern) => pattern,
+                        None => return VMResult::CompileError,
+                    };
+                    let value = match stack.pop() {
+                        Some(value) => value,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    let matches = pattern.decision().select(&[value]).is_some();
+                    if stack.push(Value::bool(matches)).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip + 2
+                }
+                OpCode::Not => {
+                    let value = match stack.pop() {
+                        Some(value) => value,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    if !value.is_bool() {
+                        let message = "Operand must be true or false.";
+                        return VM::runtime_error(chunk, instruction, message);
+                    }
+                    if stack.push(Value::bool(!value.as_bool())).is_err() {
+                        return VM::runtime_error(chunk, instruction, "Stack overflow.");
+                    }
+                    ip
+                }
+                OpCode::Jump => {
+                    let offset = match chunk.get_short(ip) {
+                        Some(offset) => offset,
+                        None => return VMResult::CompileError,
+                    };
+                    ip + 2 + offset
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = match chunk.get_short(ip) {
+                        Some(offset) => offset,
+                        None => return VMResult::CompileError,
+                    };
+                    // The condition is left on the stack
+                    let condition = match stack.peek() {
+                        Some(condition) => condition,
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    };
+                    if !condition.is_bool() {
+                        let message = "The condition must be true or false.";
+                        return VM::runtime_error(chunk, instruction, message);
+                    }
+                    if condition.as_bool() {
+                        ip + 2
+                    } else {
+                        ip + 2 + offset
+                    }
+                }
+                OpCode::CheckBool => {
+                    match stack.peek() {
+                        Some(value) if value.is_bool() => (),
+                        Some(_) => {
+                            let message = "Operand must be true or false.";
+                            return VM::runtime_error(chunk, instruction, message);
+                        }
+                        None => return VM::runtime_error(chunk, instruction, "Stack underflow."),
+                    }
+                    ip
+                }
                 OpCode::UnexpectedEndOfChunk => return VMResult::CompileError,
                 OpCode::Invalid(_) => return VMResult::CompileError,
             }
         }
     }
 
-    fn binary_op<F, G>(mut stack: Stack, actions: Vec<(F, G)>) -> Result<Stack, VMResult>
-    where
-        F: Fn(&Value, &Value) -> bool, // predicate
-        G: Fn(&Value, &Value) -> Value, // action
-    {
+    /// Frees the objects that the program can no longer use
+    ///
+    /// The arguments and local variables of every call are on the stack,
+    /// so the values on it and in the globals are the only roots.
+    fn collect_garbage(&mut self, stack: &Stack, globals: &[Option<Value>]) {
+        let before = self.heap.allocated();
+        let roots = stack.values(0).iter().chain(globals.iter().flatten());
+        // SAFETY: Collections only happen between instructions, when
+        // every value that the program can still use is a root
+        unsafe { self.heap.collect(roots) };
+
+        if self.debug.log_gc {
+            let after = self.heap.allocated();
+            println!("-- gc: freed {} bytes, {} bytes left", before - after, after);
+        }
+    }
+
+    /// Gets the element of a tuple that a call to the tuple asks for
+    fn index(elements: &[Value], arguments: &[Value]) -> Result<Value, String> {
+        let index = match arguments {
+            [index] => index,
+            _ => {
+                return Err(format!(
+                    "A tuple takes 1 index but got {}.",
+                    arguments.len()
+                ))
+            }
+        };
+        if !index.is_int() {
+            return Err("A tuple index must be an Int.".to_string());
+        }
+
+        let position = index.as_int();
+        if position < 0 || position >= elements.len() as i64 {
+            return Err(format!(
+                "Index {} is out of range for a tuple with {} elements.",
+                index,
+                elements.len()
+            ));
+        }
+        Ok(elements[position as usize].clone())
+    }
+
+    /// Starts a call to the function or bound method at `callee`,
+    /// with the values above it on the stack as its arguments
+    fn call(stack: &mut Stack, callee: usize, depth: usize) -> Result<CallFrame, String> {
+        // A bound method takes the place of its receiver,
+        // which becomes the first argument of the function
+        let (function, base) = match stack.get(callee).map(Value::data) {
+            Some(DataType::Function(function)) => (Rc::clone(function), callee + 1),
+            Some(DataType::Method(method)) => {
+                let function = Rc::clone(method.function());
+                stack.set(callee, method.receiver().clone());
+                (function, callee)
+            }
+            _ => return Err("Can only call functions.".to_string()),
+        };
+
+        let given = stack.len() - base;
+        if given != function.arity() {
+            return Err(format!(
+                "Expected {} arguments but got {}.",
+                function.arity(),
+                given
+            ));
+        }
+        if depth >= FRAMES_MAX {
+            return Err("Stack overflow.".to_string());
+        }
+
+        let dispatch = match function.dispatch(stack.values(base)) {
+            Some(dispatch) => dispatch,
+            None => return Err(VM::no_clause_message(&function, stack.values(base))),
+        };
+        VM::enter(stack, function, dispatch, callee, base)
+            .map_err(|()| "Stack overflow.".to_string())
+    }
+
+    /// Starts running the clause chosen for a call, or its guard if it has one
+    fn enter(
+        stack: &mut Stack,
+        function: Rc<Function>,
+        dispatch: Dispatch,
+        callee: usize,
+        base: usize,
+    ) -> Result<CallFrame, ()> {
+        // The values bound inside of the clause's patterns
+        // are the function's next local variables
+        for binding in dispatch.bindings {
+            stack.push(binding)?;
+        }
+        Ok(CallFrame {
+            function,
+            clause: dispatch.clause,
+            guard: dispatch.otherwise,
+            ip: 0,
+            call: 0,
+            callee,
+            base,
+        })
+    }
+
+    /// The chunk that `frame` is running
+    fn running<'a>(function: &'a Function, frame: &CallFrame) -> &'a Chunk {
+        let clause = &function.clauses()[frame.clause];
+        match (&frame.guard, clause.guard()) {
+            (Some(_), Some(guard)) => guard,
+            _ => clause.chunk(),
+        }
+    }
+
+    fn binary_op(
+        mut stack: Stack,
+        actions: Vec<(Predicate, Action)>,
+    ) -> Result<Stack, &'static str> {
         let b = match stack.pop() {
             Some(i) => i,
-            None => return Err(VMResult::RuntimeError),
+            None => return Err("Stack underflow."),
         };
         let a = match stack.pop() {
             Some(i) => i,
-            None => return Err(VMResult::RuntimeError),
+            None => return Err("Stack underflow."),
         };
         
         // Determine which actions apply,
@@ -199,15 +914,92 @@ impl VM {
             .map(|(_, op)| op(&a, &b))
             .next();
         
-        if let Some(val) = result {
-            match stack.push(val) {
+        match result {
+            Some(Ok(val)) => match stack.push(val) {
                 Ok(()) => Ok(stack),
-                Err(_) => Err(VMResult::RuntimeError),
-            }
-        } else {
-            Err(VMResult::RuntimeError)
+                Err(_) => Err("Stack overflow."),
+            },
+            Some(Err(message)) => Err(message),
+            None => Err(MISMATCHED_OPERANDS),
         }
     }
+
+    fn no_clause_message(function: &Function, arguments: &[Value]) -> String {
+        let arguments: Vec<String> = arguments.iter().map(Value::to_string).collect();
+        let clauses: Vec<String> = function
+            .clauses()
+            .iter()
+            .map(|clause| format!("\n    {}", clause.signature()))
+            .collect();
+        format!(
+            "No clause of '{}' matches ({}). Tried:{}",
+            function.name(),
+            arguments.join(", "),
+            clauses.concat()
+        )
+    }
+
+    fn runtime_error(chunk: &Chunk, instruction: usize, message: &str) -> VMResult {
+        VMResult::RuntimeError(RuntimeError {
+            message: message.to_string(),
+            span: chunk.get_span(instruction),
+        })
+    }
+}
+
+fn ints(a: &Value, b: &Value) -> bool {
+    a.is_int() && b.is_int()
+}
+
+fn floats(a: &Value, b: &Value) -> bool {
+    a.is_float() && b.is_float()
+}
+
+fn strings(a: &Value, b: &Value) -> bool {
+    a.is_string() && b.is_string()
+}
+
+/// A new string with the text of each of `parts` in order
+fn concatenate(parts: &[&str]) -> Value {
+    Value::string(Rc::from(parts.concat()))
+}
+
+/// The result of an Int operation, which is `None` if it overflowed
+fn checked(result: Option<i64>) -> Result<Value, &'static str> {
+    result.map(Value::int).ok_or(OVERFLOW)
+}
+
+/// Divides two Ints with `operation`, checking for division by zero
+fn divide(
+    a: &Value,
+    b: &Value,
+    operation: fn(i64, i64) -> Option<i64>,
+) -> Result<Value, &'static str> {
+    match b.as_int() {
+        0 => Err("Division by zero."),
+        b => checked(operation(a.as_int(), b)),
+    }
+}
+
+/// The state of a function call
+struct CallFrame {
+    function: Rc<Function>,
+    // The clause of the function that is running
+    clause: usize,
+    // While the clause's guard is running, where to
+    // look for another clause if the guard is false
+    guard: Option<Rc<Decision>>,
+    // The offset of the next instruction to run
+    ip: usize,
+    // The offset of the instruction that made the call
+    // that the frame is waiting on, for error reporting
+    call: usize,
+    // The stack slot of the function that was called,
+    // which is replaced by its result when it returns
+    callee: usize,
+    // The stack slot of the first argument,
+    // which is the first local variable
+    base: usize,
 }
 
 #[derive(Clone, Debug)]
@@ -230,6 +1022,36 @@ impl Stack {
     fn pop(&mut self) -> Option<Value> {
         self.0.pop()
     }
+
+    fn get(&self, index: usize) -> Option<&Value> {
+        self.0.get(index)
+    }
+
+    /// The value on top of the stack
+    fn peek(&self) -> Option<&Value> {
+        self.0.last()
+    }
+
+    /// The values from `start` to the top of the stack
+    fn values(&self, start: usize) -> &[Value] {
+        &self.0[start.min(self.0.len())..]
+    }
+
+    fn set(&mut self, index: usize, val: Value) {
+        self.0[index] = val;
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+
+    fn split_off(&mut self, at: usize) -> Vec<Value> {
+        self.0.split_off(at)
+    }
 }
 
 impl IntoIterator for Stack {
@@ -246,6 +1068,8 @@ pub struct DebugFlags {
     print_instructions: bool,
     print_stack: bool,
     print_constants: bool,
+    stress_gc: bool,
+    log_gc: bool,
 }
 
 impl DebugFlags {
@@ -254,6 +1078,8 @@ impl DebugFlags {
             print_instructions: false,
             print_stack: false,
             print_constants: false,
+            stress_gc: false,
+            log_gc: false,
         }
     }
 
@@ -271,6 +1097,14 @@ impl DebugFlags {
                 print_constants: value,
                 ..self
             },
+            DebugFlag::StressGc => DebugFlags {
+                stress_gc: value,
+                ..self
+            },
+            DebugFlag::LogGc => DebugFlags {
+                log_gc: value,
+                ..self
+            },
         }
     }
 }
@@ -279,24 +1113,62 @@ pub enum DebugFlag {
     PrintInstructions,
     PrintStack,
     PrintConstants,
+    // Collects garbage whenever anything has been allocated
+    StressGc,
+    LogGc,
 }
 
 pub enum VMResult {
     Okay(Value),
     CompileError,
-    RuntimeError,
+    RuntimeError(RuntimeError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    message: String,
+    // The span of the instruction that failed,
+    // if it came from a chunk with spans
+    span: Option<Span>,
+}
+
+impl RuntimeError {
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(
+                f,
+                "[line {}:{}] Runtime error: {}",
+                span.start().line(),
+                span.start().column(),
+                self.message
+            ),
+            None => write!(f, "Runtime error: {}", self.message),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::compiler::compile_program;
+    use crate::scanner::{build_scanner, Span};
     use crate::value::Value;
 
     #[test]
     fn test_return() {
         let chunk = Chunk::new()
-            .write_constant(Value::float(1.0), 1)
-            .write_chunk(&OpCode::Return, 1);
+            .write_constant(Value::float(1.0), Span::on_line(1))
+            .write_chunk(&OpCode::Return, Span::on_line(1));
         return_equals(Value::float(1.0), &chunk);
     }
 
@@ -308,6 +1180,56 @@ mod tests {
         test_math_op(&OpCode::Divide, 2.0, 2.0, 1.0); // 2 / 2 = 1
     }
 
+    #[test]
+    fn test_int_math() {
+        let chunk = build_binary_op_chunk(Value::int(7), Value::int(-2), &OpCode::Divide);
+        return_equals(Value::int(-3), &chunk);
+        let chunk = build_binary_op_chunk(Value::int(7), Value::int(-2), &OpCode::Modulo);
+        return_equals(Value::int(1), &chunk);
+
+        let chunk = build_binary_op_chunk(Value::int(i64::MAX), Value::int(2), &OpCode::Multiply);
+        match VM::new().interpret(&chunk) {
+            VMResult::RuntimeError(error) => assert_eq!("Integer overflow.", error.message()),
+            _ => panic!("multiplying past i64::MAX should fail"),
+        }
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let chunk = Chunk::new()
+            .write_constant(Value::unit(), Span::on_line(1))
+            .write_chunk(&OpCode::Negate, Span::on_line(2))
+            .write_chunk(&OpCode::Return, Span::on_line(2));
+
+        match VM::new().interpret(&chunk) {
+            VMResult::RuntimeError(error) => {
+                assert_eq!("Operand must be a number.", error.message());
+                assert_eq!(Some(Span::on_line(2)), error.span());
+            }
+            _ => panic!("negating unit should fail"),
+        }
+    }
+
+    #[test]
+    fn test_garbage_collection() {
+        let code = "fn f(0) { 0 } fn f(n) { (n, (n, n))(0) + f(n - 1) } fn main() { f(200) }";
+        let chunk = compile_program(build_scanner(code)).unwrap();
+        let run = |vm: &mut VM| match vm.interpret(&chunk) {
+            VMResult::Okay(value) => assert_eq!(Value::int(20100), value),
+            _ => panic!("the program should run"),
+        };
+
+        let mut vm = VM::new();
+        run(&mut vm);
+        let debug = DebugFlags::new().set_flag(&DebugFlag::StressGc, true);
+        let mut stressed = VM::new_debugger(debug);
+        run(&mut stressed);
+
+        // Without collections every call's tuples are kept, but
+        // a stressed heap only keeps the ones from the last call
+        assert!(stressed.heap.allocated() * 100 < vm.heap.allocated());
+    }
+
     fn test_math_op(op: &OpCode, operand_a: f64, operand_b: f64, result: f64) {
         let chunk = build_binary_op_chunk(Value::float(operand_a), Value::float(operand_b), op);
         return_equals(Value::float(result), &chunk);
@@ -315,10 +1237,10 @@ mod tests {
 
     fn build_binary_op_chunk(a: Value, b: Value, op: &OpCode) -> Chunk {
         Chunk::new()
-            .write_constant(a, 1)
-            .write_constant(b, 1)
-            .write_chunk(op, 1)
-            .write_chunk(&OpCode::Return, 1)
+            .write_constant(a, Span::on_line(1))
+            .write_constant(b, Span::on_line(1))
+            .write_chunk(op, Span::on_line(1))
+            .write_chunk(&OpCode::Return, Span::on_line(1))
     }
 
     fn return_equals(val: Value, chunk: &Chunk) {